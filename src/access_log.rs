@@ -0,0 +1,421 @@
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use http_body_util::{BodyExt, Either};
+use hyper::body::Body;
+use serde::Serialize;
+
+use crate::configuration::v2::{AccessLogConfig, AccessLogFormat};
+use crate::global_state::GlobalState;
+use crate::http_proxy::{create_response_channel, create_stream_response, EpicResponse};
+use crate::CustomError;
+use crate::types::proc_info::BgTaskInfo;
+
+// set by the background worker, so that requests are not tracked when nothing would be written
+static ENABLED : AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    static ref ENTRIES : tokio::sync::broadcast::Sender<AccessLogEntry> = tokio::sync::broadcast::channel(1024).0;
+    /// Json formatted access log entries, streamed over the admin api websocket.
+    pub static ref STREAM : tokio::sync::broadcast::Sender<String> = tokio::sync::broadcast::channel(128).0;
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub enum AccessLogEvent {
+    Request,
+    TunnelOpened,
+    TunnelClosed
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessLogEntry {
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub timestamp : chrono::DateTime<Local>,
+    pub event : AccessLogEvent,
+    pub client_ip : IpAddr,
+    pub host : String,
+    pub method : Option<String>,
+    pub path : Option<String>,
+    /// Http version used by the client, or TCP/TLS for tunnels
+    pub protocol : String,
+    /// Http version used when talking to the upstream server
+    pub upstream_protocol : Option<String>,
+    pub upstream : Option<String>,
+    pub status : Option<u16>,
+    /// Bytes of the response body sent to the client, or bytes sent to the client through a closed tunnel.
+    pub bytes_sent : Option<u64>,
+    /// Bytes received from the client. Only known for closed tunnels.
+    pub bytes_received : Option<u64>,
+    pub duration_ms : Option<u64>,
+    pub referer : Option<String>,
+    pub user_agent : Option<String>,
+}
+
+fn serialize_timestamp<S:serde::Serializer>(timestamp:&chrono::DateTime<Local>, serializer:S) -> Result<S::Ok,S::Error> {
+    serializer.serialize_str(&timestamp.to_rfc3339())
+}
+
+impl AccessLogEntry {
+
+    // the part between quotes in the common log format, such as: GET /index.html HTTP/1.1
+    fn request_line(&self) -> String {
+        match (&self.method,&self.path) {
+            (Some(method),Some(path)) => format!("{method} {path} {}",self.protocol),
+            _ => format!("{:?} {} {}",self.event,self.host,self.protocol)
+        }
+    }
+
+    pub fn format(&self, format:&AccessLogFormat) -> String {
+        let optional = |x:Option<String>| x.unwrap_or(String::from("-"));
+        let common = format!("{} - - [{}] \"{}\" {} {}",
+            self.client_ip,
+            self.timestamp.format("%d/%b/%Y:%H:%M:%S %z"),
+            self.request_line(),
+            optional(self.status.map(|x|x.to_string())),
+            optional(self.bytes_sent.map(|x|x.to_string()))
+        );
+        match format {
+            AccessLogFormat::Common => common,
+            AccessLogFormat::Combined => format!("{common} \"{}\" \"{}\"",
+                optional(self.referer.clone()),
+                optional(self.user_agent.clone())
+            ),
+            AccessLogFormat::Json => serde_json::to_string(self).unwrap_or_default()
+        }
+    }
+}
+
+/// Added to the extensions of a response so that the access log knows where the response came from.
+/// Responses created by odd-box itself, such as redirects and error pages, do not have one.
+#[derive(Debug, Clone)]
+pub struct Upstream {
+    pub address : String,
+    /// False if the response was created without calling the upstream server, such as responses from the cache.
+    pub responded : bool
+}
+
+/// Details about an incoming http request that are kept until its response has been sent.
+pub struct PendingRequest {
+    started_at : Instant,
+    timestamp : chrono::DateTime<Local>,
+    client_ip : IpAddr,
+    host : String,
+    method : String,
+    path : String,
+    protocol : String,
+    referer : Option<String>,
+    user_agent : Option<String>,
+}
+
+impl PendingRequest {
+    pub fn new<B>(req:&hyper::Request<B>, host:&str, client_ip:IpAddr) -> Self {
+        let header = |name:hyper::header::HeaderName| req.headers().get(name).and_then(|x|x.to_str().ok()).map(|x|x.to_string());
+        Self {
+            started_at: Instant::now(),
+            timestamp: Local::now(),
+            client_ip,
+            host: host.to_string(),
+            method: req.method().to_string(),
+            path: req.uri().path_and_query().map(|x|x.to_string()).unwrap_or(String::from("/")),
+            protocol: format!("{:?}",req.version()),
+            referer: header(hyper::header::REFERER),
+            user_agent: header(hyper::header::USER_AGENT),
+        }
+    }
+
+    /// Records the request once the body of the response has been sent to the client,
+    /// so that streamed and compressed responses are logged with the number of bytes that were actually sent.
+    /// The response is returned untouched if the access log is not enabled.
+    pub fn complete(self, response:EpicResponse) -> EpicResponse {
+
+        if !ENABLED.load(Ordering::Relaxed) {
+            return response
+        }
+
+        let upstream = response.extensions().get::<Upstream>();
+        let mut entry = AccessLogEntry {
+            timestamp: self.timestamp,
+            event: AccessLogEvent::Request,
+            client_ip: self.client_ip,
+            host: self.host,
+            method: Some(self.method),
+            path: Some(self.path),
+            protocol: self.protocol,
+            upstream_protocol: upstream.filter(|x|x.responded).map(|_| format!("{:?}",response.version())),
+            upstream: upstream.map(|x|x.address.clone()),
+            status: Some(response.status().as_u16()),
+            bytes_sent: None,
+            bytes_received: None,
+            duration_ms: None,
+            referer: self.referer,
+            user_agent: self.user_agent,
+        };
+
+        let (parts, mut body) = response.into_parts();
+
+        // bodies that are already in memory are sent as they are
+        let known_size = match &body {
+            Either::Right(Either::Left(full)) => full.size_hint().exact(),
+            _ if body.is_end_stream() => Some(0),
+            _ => None
+        };
+        if let Some(size) = known_size {
+            entry.bytes_sent = Some(size);
+            entry.duration_ms = Some(self.started_at.elapsed().as_millis() as u64);
+            record(entry);
+            return EpicResponse::from_parts(parts, body)
+        }
+
+        let started_at = self.started_at;
+        let (tx, rx) = create_response_channel(1);
+        tokio::spawn(async move {
+            let mut bytes_sent = 0u64;
+            while let Some(frame) = body.frame().await {
+                let frame = frame.map_err(|e| CustomError(format!("{e:?}")));
+                let failed = frame.is_err();
+                let size = frame.as_ref().ok().and_then(|x|x.data_ref()).map(|x|x.len() as u64).unwrap_or_default();
+                if tx.send(frame).await.is_err() {
+                    // the client went away
+                    break
+                }
+                bytes_sent += size;
+                if failed {
+                    break
+                }
+            }
+            entry.bytes_sent = Some(bytes_sent);
+            entry.duration_ms = Some(started_at.elapsed().as_millis() as u64);
+            record(entry);
+        });
+
+        let (_, body) = create_stream_response(rx).into_parts();
+        EpicResponse::from_parts(parts, body)
+    }
+}
+
+/// Queues an entry for the access log. Entries are dropped if the access log is not enabled.
+pub fn record(entry:AccessLogEntry) {
+    _ = ENTRIES.send(entry);
+}
+
+struct AccessLogFile {
+    path : PathBuf,
+    file : std::fs::File,
+    size : u64
+}
+
+impl AccessLogFile {
+    fn open(path:&Path) -> anyhow::Result<Self> {
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path: path.to_path_buf(), file, size })
+    }
+
+    // access.log -> access.log.1 -> access.log.2 and so on, dropping the oldest file
+    fn rotate(&mut self, max_files:u32) -> anyhow::Result<()> {
+        let rotated = |n:u32| PathBuf::from(format!("{}.{n}",self.path.display()));
+        if max_files == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            _ = std::fs::remove_file(rotated(max_files));
+            for n in (1..max_files).rev() {
+                if rotated(n).exists() {
+                    std::fs::rename(rotated(n), rotated(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, rotated(1))?;
+        }
+        *self = Self::open(&self.path)?;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line:&str, config:&AccessLogConfig) -> anyhow::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 + 1 > config.max_file_size_bytes() {
+            self.rotate(config.max_files())?;
+        }
+        writeln!(self.file, "{line}")?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+}
+
+pub async fn bg_worker_for_access_log(state: Arc<GlobalState>) {
+    let liveness_token = Arc::new(true);
+    let mut receiver = ENTRIES.subscribe();
+
+    let mut config : Option<AccessLogConfig> = None;
+    let mut config_read_at : Option<Instant> = None;
+    let mut file : Option<AccessLogFile> = None;
+    let mut status = String::new();
+    let mut written = 0u64;
+
+    loop {
+
+        // NOTE: We re-read the configuration every few seconds since it can be changed at runtime.
+        if config_read_at.map_or(true, |x| x.elapsed() > Duration::from_secs(5)) {
+            let new_config = state.config.read().await.access_log.clone();
+            if new_config.as_ref().and_then(|x|x.file.as_ref()) != config.as_ref().and_then(|x|x.file.as_ref()) {
+                file = match new_config.as_ref().and_then(|x|x.file.as_ref()) {
+                    Some(path) => match AccessLogFile::open(Path::new(path)) {
+                        Ok(f) => Some(f),
+                        Err(e) => {
+                            tracing::error!("Failed to open the access log file {path}: {e:?}");
+                            None
+                        }
+                    },
+                    None => None
+                };
+            }
+            config = new_config;
+            config_read_at = Some(Instant::now());
+            ENABLED.store(config.is_some(), Ordering::Relaxed);
+            status = match (&config,&file) {
+                (None,_) => String::from("Disabled. access_log not configured."),
+                (Some(_),Some(f)) => format!("Writing to {} - entries written: {written}", f.path.display()),
+                (Some(_),None) => String::from("Streaming to the admin api only.")
+            };
+        }
+
+        crate::BG_WORKER_THREAD_MAP.insert("Access Log".into(), BgTaskInfo {
+            liveness_ptr: Arc::downgrade(&liveness_token),
+            status: status.clone()
+        }); // we dont need to clean this up if we exit, there is a cleanup task that will do it.
+
+        let entry = match tokio::time::timeout(Duration::from_secs(1), receiver.recv()).await {
+            Ok(Ok(entry)) => entry,
+            Ok(Err(tokio::sync::broadcast::error::RecvError::Lagged(n))) => {
+                tracing::warn!("The access log fell behind and dropped {n} entries.");
+                continue
+            },
+            Ok(Err(_)) => break,
+            Err(_) => continue
+        };
+
+        let cfg = match &config {
+            Some(c) => c,
+            None => continue
+        };
+
+        _ = STREAM.send(entry.format(&AccessLogFormat::Json));
+
+        if let Some(f) = &mut file {
+            let line = entry.format(cfg.format.as_ref().unwrap_or(&AccessLogFormat::Combined));
+            match f.write_line(&line, cfg) {
+                Ok(()) => written += 1,
+                Err(e) => tracing::warn!("Failed to write to the access log: {e:?}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use chrono::TimeZone;
+
+    fn entry() -> AccessLogEntry {
+        AccessLogEntry {
+            timestamp: Local.with_ymd_and_hms(2024, 5, 17, 13, 37, 0).unwrap(),
+            event: AccessLogEvent::Request,
+            client_ip: "10.0.0.1".parse().unwrap(),
+            host: "site.local".into(),
+            method: Some("GET".into()),
+            path: Some("/index.html?q=1".into()),
+            protocol: "HTTP/1.1".into(),
+            upstream_protocol: Some("HTTP/2.0".into()),
+            upstream: Some("http://backend.local:8080/index.html?q=1".into()),
+            status: Some(200),
+            bytes_sent: Some(512),
+            bytes_received: None,
+            duration_ms: Some(12),
+            referer: None,
+            user_agent: Some("curl/8.0".into()),
+        }
+    }
+
+    #[test]
+    fn entries_can_be_formatted_as_common_combined_and_json() {
+
+        let entry = entry();
+        let timestamp = entry.timestamp.format("%d/%b/%Y:%H:%M:%S %z");
+
+        let common = entry.format(&AccessLogFormat::Common);
+        assert_eq!(common, format!("10.0.0.1 - - [{timestamp}] \"GET /index.html?q=1 HTTP/1.1\" 200 512"));
+        assert_eq!(entry.format(&AccessLogFormat::Combined), format!("{common} \"-\" \"curl/8.0\""));
+
+        let json : serde_json::Value = serde_json::from_str(&entry.format(&AccessLogFormat::Json)).expect("should be valid json");
+        assert_eq!(json["host"], "site.local");
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["bytes_sent"], 512);
+        assert_eq!(json["event"], "Request");
+        assert_eq!(json["timestamp"], entry.timestamp.to_rfc3339());
+
+        // tunnels have no request line, and unknown values are written as dashes
+        let tunnel = AccessLogEntry { event: AccessLogEvent::TunnelOpened, method: None, path: None, protocol: "TLS".into(), status: None, bytes_sent: None, ..entry };
+        assert_eq!(tunnel.format(&AccessLogFormat::Common), format!("10.0.0.1 - - [{timestamp}] \"TunnelOpened site.local TLS\" - -"));
+
+    }
+
+    #[test]
+    fn files_are_rotated_once_they_are_full() {
+
+        let dir = std::env::temp_dir().join(format!("odd-box-access-log-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("should create the test directory");
+        let path = dir.join("access.log");
+        let rotated = |n:u32| PathBuf::from(format!("{}.{n}", path.display()));
+        let config = AccessLogConfig { format: None, file: None, max_file_size_mb: Some(1), max_files: Some(2) };
+
+        let mut file = AccessLogFile::open(&path).expect("should open the log file");
+        let line = "x".repeat(600 * 1024);
+        for n in 0..4 {
+            file.write_line(&format!("{n}{line}"), &config).expect("should write the line");
+        }
+
+        // each line only fits in a file of its own, and the oldest file is dropped
+        let first_char = |path:&Path| std::fs::read_to_string(path).expect("should read the file").chars().next();
+        assert_eq!(first_char(&path), Some('3'));
+        assert_eq!(first_char(&rotated(1)), Some('2'));
+        assert_eq!(first_char(&rotated(2)), Some('1'));
+        assert!(!rotated(3).exists());
+
+        _ = std::fs::remove_dir_all(&dir);
+
+    }
+
+    #[tokio::test]
+    async fn streamed_responses_are_logged_with_the_bytes_that_were_sent() {
+
+        ENABLED.store(true, Ordering::Relaxed);
+        let mut entries = ENTRIES.subscribe();
+        let req = hyper::Request::get("/events").header(hyper::header::HOST, "stream.local").body(()).unwrap();
+        let pending = PendingRequest::new(&req, "stream.local", "10.0.0.2".parse().unwrap());
+
+        let (tx, rx) = create_response_channel(4);
+        let mut response = create_stream_response(rx);
+        response.extensions_mut().insert(Upstream { address: "http://backend.local".into(), responded: true });
+        let response = pending.complete(response);
+
+        tokio::spawn(async move {
+            for chunk in ["first ", "second ", "third"] {
+                _ = tx.send(Ok(hyper::body::Frame::data(bytes::Bytes::from(chunk)))).await;
+            }
+        });
+        let body = response.into_body().collect().await.expect("should read the body").to_bytes();
+        assert_eq!(body.as_ref(), b"first second third");
+
+        let entry = loop {
+            let entry = entries.recv().await.expect("should receive the entry");
+            if entry.host == "stream.local" { break entry }
+        };
+        assert_eq!(entry.bytes_sent, Some(18));
+        assert_eq!(entry.upstream.as_deref(), Some("http://backend.local"));
+        assert_eq!(entry.path.as_deref(), Some("/events"));
+
+    }
+}
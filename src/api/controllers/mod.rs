@@ -16,6 +16,11 @@ pub async fn routes(state:Arc<GlobalState>) -> Router {
         .route("/sites/start", axum::routing::put(sites::start_handler)).with_state(state.clone())
         .route("/sites/stop", axum::routing::put(sites::stop_handler)).with_state(state.clone())
         .route("/sites/status", axum::routing::get(sites::status_handler)).with_state(state.clone())
+        .route("/sites/health", axum::routing::get(sites::health_handler)).with_state(state.clone())
+        .route("/sites/rewrite_test", axum::routing::get(sites::rewrite_test_handler)).with_state(state.clone())
+        .route("/sites/rate_limits", axum::routing::get(sites::rate_limits_handler)).with_state(state.clone())
+        .route("/sites/cache", axum::routing::get(sites::cache_entries_handler)).with_state(state.clone())
+        .route("/sites/cache", axum::routing::delete(sites::cache_purge_handler)).with_state(state.clone())
         ;
 
     let settings = Router::new()
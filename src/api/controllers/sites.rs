@@ -1,7 +1,8 @@
 use std::sync::Arc;
 
-use crate::configuration::v2::{InProcessSiteConfig, RemoteSiteConfig};
+use crate::configuration::v2::{InProcessSiteConfig, RemoteSiteConfig, RewriteOutcome};
 use crate::configuration::OddBoxConfiguration;
+use crate::types::circuit_breaker::CircuitState;
 use super::*;
 use axum::extract::{Query, State};
 use utoipa::{IntoParams, ToSchema};
@@ -25,8 +26,8 @@ impl IntoResponse for SitesError {
 
 #[derive(ToSchema,Serialize)]
 pub enum ConfigurationItem {
-   HostedProcess(InProcessSiteConfig),
-   RemoteSite(RemoteSiteConfig)
+   HostedProcess(Box<InProcessSiteConfig>),
+   RemoteSite(Box<RemoteSiteConfig>)
 }
 
 #[derive(ToSchema,Serialize)]
@@ -43,8 +44,10 @@ pub struct StatusResponse {
 #[derive(Debug,PartialEq,Clone,serde::Serialize,ToSchema)]
 pub enum BasicProcState {
     Faulty,
+    CrashLooping,
     Stopped,    
     Starting,
+    Ready,
     Stopping,
     Running,
     Remote
@@ -53,8 +56,10 @@ impl From<crate::ProcState> for BasicProcState {
     fn from(l: crate::ProcState) -> Self {
         match l {
             crate::types::app_state::ProcState::Faulty => BasicProcState::Faulty,
+            crate::types::app_state::ProcState::CrashLooping => BasicProcState::CrashLooping,
             crate::types::app_state::ProcState::Stopped => BasicProcState::Stopped,
             crate::types::app_state::ProcState::Starting => BasicProcState::Starting,
+            crate::types::app_state::ProcState::Ready => BasicProcState::Ready,
             crate::types::app_state::ProcState::Stopping => BasicProcState::Stopping,
             crate::types::app_state::ProcState::Running => BasicProcState::Running,
             crate::types::app_state::ProcState::Remote => BasicProcState::Remote,
@@ -64,7 +69,13 @@ impl From<crate::ProcState> for BasicProcState {
 #[derive(ToSchema,Serialize)]
 pub struct StatusItem {
     pub hostname: String,
-    pub state: BasicProcState
+    pub state: BasicProcState,
+    /// Number of automatic restarts since odd-box started. Always zero for remote sites.
+    pub restart_count: u32,
+    /// Why the process was last restarted automatically, if it ever was
+    pub last_failure: Option<String>,
+    /// Exit code from the last time the process stopped on its own
+    pub last_exit_code: Option<i32>
 }
 
 /// List all configured sites.
@@ -86,7 +97,7 @@ pub async fn list_handler(state: axum::extract::State<Arc<GlobalState>>) -> axum
     let rems = cfg_guard.remote_target.clone().unwrap_or_default();
 
     Ok(Json(ListResponse {
-        items: procs.into_iter().map(ConfigurationItem::HostedProcess).chain(rems.into_iter().map(ConfigurationItem::RemoteSite)).collect()
+        items: procs.into_iter().map(|x| ConfigurationItem::HostedProcess(Box::new(x))).chain(rems.into_iter().map(|x| ConfigurationItem::RemoteSite(Box::new(x)))).collect()
     }))
     
 }
@@ -108,9 +119,13 @@ pub async fn status_handler(state: axum::extract::State<Arc<GlobalState>>) -> ax
     Ok(Json(StatusResponse {
         items: state.app_state.site_status_map.iter().map(|guard|{
             let (site,state) = guard.pair();
+            let proc_info = crate::PROC_THREAD_MAP.iter().find(|x| x.config.host_name == *site);
             StatusItem {
                 hostname: site.clone(),
-                state: state.clone().into()
+                state: state.clone().into(),
+                restart_count: proc_info.as_ref().map(|x|x.restart_count).unwrap_or_default(),
+                last_failure: proc_info.as_ref().and_then(|x|x.last_failure.clone()),
+                last_exit_code: proc_info.and_then(|x|x.last_exit_code)
             }
         }).collect()
     }))
@@ -118,10 +133,93 @@ pub async fn status_handler(state: axum::extract::State<Arc<GlobalState>>) -> ax
 }
 
 
+#[derive(ToSchema,Serialize)]
+pub struct BackendHealthResponse {
+    pub items : Vec<BackendHealthItem>
+}
+
+#[derive(ToSchema,Serialize)]
+pub struct BackendHealthItem {
+    pub hostname: String,
+    pub address: String,
+    pub port: u16,
+    /// False if no health check is configured for this backend
+    pub monitored: bool,
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+    pub last_check: Option<String>,
+    pub last_error: Option<String>,
+    /// False if no outlier detection is configured for this backend
+    pub outlier_detection: bool,
+    /// Backends in the Open state have been ejected by the outlier detection and get no traffic
+    pub circuit_state: CircuitState,
+    /// When an ejected backend gets traffic again
+    pub ejected_until: Option<String>,
+    /// Number of times the outlier detection has ejected this backend
+    pub ejections: u32,
+    /// The last failure seen by the outlier detection
+    pub last_outlier_error: Option<String>
+}
+
+/// List the health of all remote site backends, from the active health checks as well as the outlier detection.
+#[utoipa::path(
+    operation_id="health",
+    get,
+    tag = "Site management",
+    path = "/sites/health",
+    responses(
+        (status = 200, description = "Successful Response", body = BackendHealthResponse),
+        (status = 500, description = "When something goes wrong", body = String),
+    )
+)]
+pub async fn health_handler(state: axum::extract::State<Arc<GlobalState>>) -> axum::response::Result<impl IntoResponse,SitesError> {
+    
+    let cfg_guard = state.config.read().await;
+    
+    let mut items = vec![];
+    for site in cfg_guard.remote_target.iter().flatten() {
+        for backend in &site.backends {
+            let health = state.app_state.backend_health.get(&crate::health_checks::health_key(&site.host_name,backend)).map(|x|x.value().clone());
+            let breaker = state.app_state.circuit_breakers.get(&crate::health_checks::health_key(&site.host_name,backend)).map(|x|x.value().clone());
+            let now = std::time::Instant::now();
+            let circuit_state = match (&backend.outlier_detection, &breaker) {
+                (Some(config), Some(breaker)) => breaker.state(config, now),
+                _ => CircuitState::Closed
+            };
+            let ejected_until = match (&backend.outlier_detection, breaker.as_ref().and_then(|x|x.ejected_at)) {
+                (Some(config), Some(ejected_at)) if circuit_state == CircuitState::Open => {
+                    let remaining = (ejected_at + config.cooldown()).saturating_duration_since(now);
+                    chrono::Duration::from_std(remaining).ok().map(|x| (chrono::Local::now() + x).to_rfc3339())
+                },
+                _ => None
+            };
+            items.push(BackendHealthItem {
+                hostname: site.host_name.clone(),
+                address: backend.address.clone(),
+                port: backend.port,
+                monitored: backend.health_check.is_some(),
+                healthy: health.as_ref().map(|x|x.healthy).unwrap_or(true),
+                consecutive_failures: health.as_ref().map(|x|x.consecutive_failures).unwrap_or_default(),
+                last_check: health.as_ref().and_then(|x|x.last_check).map(|x|x.to_rfc3339()),
+                last_error: health.and_then(|x|x.last_error),
+                outlier_detection: backend.outlier_detection.is_some(),
+                circuit_state,
+                ejected_until,
+                ejections: breaker.as_ref().map(|x|x.ejections).unwrap_or_default(),
+                last_outlier_error: breaker.and_then(|x|x.last_error)
+            });
+        }
+    }
+
+    Ok(Json(BackendHealthResponse { items }))
+    
+}
+
+
 #[derive(Deserialize, Serialize, ToSchema)]
 pub enum ConfigItem {
-    RemoteSite(RemoteSiteConfig),
-    HostedProcess(InProcessSiteConfig)
+    RemoteSite(Box<RemoteSiteConfig>),
+    HostedProcess(Box<InProcessSiteConfig>)
 }
 
 
@@ -167,7 +265,7 @@ pub async fn update_handler(State(state): axum::extract::State<Arc<GlobalState>>
             let hostname = query.hostname.clone().unwrap_or(new_cfg.host_name.clone());
 
             match conf_guard.add_or_replace_remote_site(
-                &hostname,new_cfg.to_owned(),
+                &hostname,new_cfg.as_ref().to_owned(),
                 state.clone()         
             ).await {
                 Ok(_) => {
@@ -181,7 +279,7 @@ pub async fn update_handler(State(state): axum::extract::State<Arc<GlobalState>>
         ConfigItem::HostedProcess(new_cfg) => {
             
             let hostname = query.hostname.clone().unwrap_or(new_cfg.host_name.clone());
-            match conf_guard.add_or_replace_hosted_process(&hostname,new_cfg.to_owned(),state.clone()).await {
+            match conf_guard.add_or_replace_hosted_process(&hostname,new_cfg.as_ref().to_owned(),state.clone()).await {
                 Ok(_) => {
                     state.invalidate_cache();
                     Ok(())
@@ -369,3 +467,133 @@ pub async fn start_handler(
 }
 
 
+
+
+#[derive(Deserialize,IntoParams)]
+#[into_params(
+    parameter_in=Query
+)]
+pub struct RewriteTestQueryParams {
+    #[param(example = json!("old.localtest.me"))]
+    pub hostname: String,
+    /// Path and query of the request
+    #[param(example = json!("/blog/2024?page=2"))]
+    pub path: String,
+}
+
+#[derive(ToSchema,Serialize)]
+pub struct RewriteTestResponse {
+    /// None if no redirect site or rewrite rule matches the request
+    pub outcome: Option<RewriteOutcome>
+}
+
+/// Shows how redirect sites and rewrite rules would handle a request, without sending it anywhere.
+#[utoipa::path(
+    operation_id="rewrite_test",
+    get,
+    tag = "Site management",
+    params(RewriteTestQueryParams),
+    path = "/sites/rewrite_test",
+    responses(
+        (status = 200, description = "Successful Response", body = RewriteTestResponse),
+        (status = 500, description = "When something goes wrong", body = String),
+    )
+)]
+pub async fn rewrite_test_handler(
+    axum::extract::State(global_state): axum::extract::State<Arc<GlobalState>>, 
+    Query(query): Query<RewriteTestQueryParams>
+) -> axum::response::Result<impl IntoResponse,SitesError> {
+
+    let (path, query_string) = match query.path.split_once('?') {
+        Some((path, query_string)) => (path, Some(query_string)),
+        None => (query.path.as_str(), None)
+    };
+    let outcome = global_state.config.read().await.find_rewrite(&query.hostname, path, query_string);
+    Ok(Json(RewriteTestResponse { outcome }))
+
+}
+
+
+#[derive(ToSchema,Serialize)]
+pub struct RateLimitsResponse {
+    pub items : Vec<crate::rate_limit::RateLimitCounter>
+}
+
+/// List the current rate limit counters of all clients.
+#[utoipa::path(
+    operation_id="rate_limits",
+    get,
+    tag = "Site management",
+    path = "/sites/rate_limits",
+    responses(
+        (status = 200, description = "Successful Response", body = RateLimitsResponse),
+        (status = 500, description = "When something goes wrong", body = String),
+    )
+)]
+pub async fn rate_limits_handler(_state: axum::extract::State<Arc<GlobalState>>) -> axum::response::Result<impl IntoResponse,SitesError> {
+    Ok(Json(RateLimitsResponse { items: crate::rate_limit::counters() }))
+}
+
+
+#[derive(ToSchema,Serialize)]
+pub struct CacheEntriesResponse {
+    pub items : Vec<crate::http_cache::CacheEntryInfo>
+}
+
+/// List the responses that are currently cached in memory.
+#[utoipa::path(
+    operation_id="cache_entries",
+    get,
+    tag = "Site management",
+    path = "/sites/cache",
+    responses(
+        (status = 200, description = "Successful Response", body = CacheEntriesResponse),
+        (status = 500, description = "When something goes wrong", body = String),
+    )
+)]
+pub async fn cache_entries_handler(_state: axum::extract::State<Arc<GlobalState>>) -> axum::response::Result<impl IntoResponse,SitesError> {
+    Ok(Json(CacheEntriesResponse { items: crate::http_cache::entries() }))
+}
+
+#[derive(Deserialize,IntoParams)]
+#[into_params(
+    parameter_in=Query
+)]
+pub struct CachePurgeQueryParams {
+    /// Host name of the site, leave out to purge all sites
+    #[param(example = json!("my_site.com"))]
+    pub hostname: Option<String>,
+    /// Cache key as listed by GET /sites/cache, leave out to purge every response of the site
+    #[param(example = json!("my_site.com/index.html"))]
+    pub key: Option<String>,
+}
+
+#[derive(ToSchema,Serialize)]
+pub struct CachePurgeResponse {
+    pub removed : usize
+}
+
+/// Remove cached responses from memory and disk.
+#[utoipa::path(
+    operation_id="cache_purge",
+    delete,
+    tag = "Site management",
+    params(CachePurgeQueryParams),
+    path = "/sites/cache",
+    responses(
+        (status = 200, description = "Successful Response", body = CachePurgeResponse),
+        (status = 500, description = "When something goes wrong", body = String),
+    )
+)]
+pub async fn cache_purge_handler(
+    axum::extract::State(global_state): axum::extract::State<Arc<GlobalState>>, 
+    Query(query): Query<CachePurgeQueryParams>
+) -> axum::response::Result<impl IntoResponse,SitesError> {
+
+    let disk_dirs = global_state.config.read().await.remote_target.iter().flatten()
+        .filter_map(|x| x.cache.as_ref().and_then(|c| c.disk_dir.clone()))
+        .collect::<Vec<String>>();
+    let removed = crate::http_cache::purge(query.hostname.as_deref(), query.key.as_deref(), disk_dirs).await;
+    Ok(Json(CachePurgeResponse { removed }))
+
+}
@@ -86,6 +86,12 @@ pub async fn run(globally_shared_state: Arc<crate::global_state::GlobalState>,po
 
     let cors_env_var = std::env::vars().find(|(key,_)| key=="ODDBOX_CORS_ALLOWED_ORIGIN").map(|x|x.1.to_lowercase());
     let cors_env_var_cloned_for_ws = cors_env_var.clone();
+    let cors_env_var_cloned_for_access_log_ws = cors_env_var.clone();
+
+    let access_log_websocket_state = WebSocketGlobalState {
+        broadcast_channel: crate::access_log::STREAM.clone(),
+        global_state: globally_shared_state.clone()
+    };
 
     let mut router = Router::new()
 
@@ -103,7 +109,11 @@ pub async fn run(globally_shared_state: Arc<crate::global_state::GlobalState>,po
         
         // WEBSOCKET ROUTE FOR LOGS
         .route("/ws/live_logs", axum::routing::get( move|ws,user_agent,origin,addr,state|
-            ws_log_messages_handler(ws,user_agent,origin,addr,state, cors_env_var_cloned_for_ws)).with_state(websocket_state.clone()));
+            ws_log_messages_handler(ws,user_agent,origin,addr,state, cors_env_var_cloned_for_ws)).with_state(websocket_state.clone()))
+
+        // WEBSOCKET ROUTE FOR THE ACCESS LOG
+        .route("/ws/access_log", axum::routing::get( move|ws,user_agent,origin,addr,state|
+            ws_access_log_handler(ws,user_agent,origin,addr,state, cors_env_var_cloned_for_access_log_ws)).with_state(access_log_websocket_state));
 
 
     // in some cases one might want to allow CORS from a specific origin. this is not currently allowed to do from the config file
@@ -140,6 +150,25 @@ async fn script() -> impl IntoResponse {
         .expect("must be able to create response")
 }
 
+/// Websocket interface for the access log. Each message is a json formatted access log entry.
+/// Nothing is emitted unless access_log is configured.
+#[utoipa::path(
+    operation_id="access_log",
+    get,
+    tag = "Logs",
+    path = "/ws/access_log",
+)]
+async fn ws_access_log_handler(
+    ws: WebSocketUpgrade,
+    user_agent: Option<axum_extra::TypedHeader<axum_extra::headers::UserAgent>>,
+    origin: Option<axum_extra::TypedHeader<axum_extra::headers::Origin>>,
+    connect_info: axum::extract::ConnectInfo<SocketAddr> ,
+    state : State<WebSocketGlobalState>,
+    cors_env_var : Option<String>
+) -> impl axum::response::IntoResponse {
+    ws_log_messages_handler(ws,user_agent,origin,connect_info,state,cors_env_var).await
+}
+
 /// Simple websocket interface for log messages.
 /// Warning: The format of messages emitted is not guaranteed to be stable.
 #[utoipa::path(
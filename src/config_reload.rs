@@ -0,0 +1,184 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::configuration::{ConfigWrapper, OddBoxConfig};
+use crate::global_state::GlobalState;
+use crate::types::app_state::ProcState;
+use crate::types::proc_info::BgTaskInfo;
+
+// loads and validates a configuration file without touching the running configuration
+fn parse_and_validate(content:&str) -> anyhow::Result<crate::configuration::v2::OddBoxV2Config> {
+    let parsed = OddBoxConfig::parse(content).map_err(|e|anyhow::anyhow!(e))?;
+    let (config,_original_version) = parsed.try_upgrade_to_latest_version().map_err(|e|anyhow::anyhow!(e))?;
+    let wrapper = ConfigWrapper::new(config.clone());
+    wrapper.is_valid()?;
+    Ok(config)
+}
+
+// asks the worker loop of a hosted process to stop the process and exit
+async fn delete_hosted_process(state:&GlobalState, host_name:&str) {
+    let (tx,mut rx) = tokio::sync::mpsc::channel(1);
+    if let Err(e) = state.broadcaster.send(crate::http_proxy::ProcMessage::Delete(host_name.to_owned(),tx)) {
+        tracing::warn!("Failed to stop {host_name} while reloading the configuration: {e:?}");
+        return
+    }
+    match tokio::time::timeout(Duration::from_secs(60), rx.recv()).await {
+        Ok(Some(0)) => tracing::debug!("Received a confirmation that {host_name} was deleted"),
+        _ => tracing::warn!("Did not receive a confirmation that {host_name} was stopped while reloading the configuration.")
+    }
+}
+
+/// Applies the sites that have changed in a configuration file to the running configuration.
+/// Sites that have not changed are left alone, so their processes keep running.
+pub async fn apply_configuration(state:Arc<GlobalState>, mut new_config:crate::configuration::v2::OddBoxV2Config) -> anyhow::Result<String> {
+
+    let changes = {
+        let guard = state.config.read().await;
+
+        // unchanged processes keep their runtime state, such as the id of their worker loop and their selected port
+        for proc in new_config.hosted_process.iter_mut().flatten() {
+            if let Some(old) = guard.hosted_process.iter().flatten().find(|x| **x == *proc) {
+                proc.keep_runtime_state_of(old);
+            }
+        }
+
+        if guard.http_port != new_config.http_port || guard.tls_port != new_config.tls_port
+            || guard.ip != new_config.ip || guard.admin_api_port != new_config.admin_api_port {
+            tracing::warn!("Changes to ip, http_port, tls_port and admin_api_port will not take effect until odd-box is restarted.");
+        }
+
+        guard.site_changes(&new_config)
+    };
+
+    // processes must be stopped before we take the write lock as their worker loops may need the configuration to exit
+    let hosted = &changes.hosted_processes;
+    for host_name in hosted.removed.iter().chain(hosted.changed.iter()) {
+        tracing::info!("Stopping {host_name} as it was {} in the configuration file", if hosted.removed.contains(host_name) { "removed" } else { "changed" });
+        delete_hosted_process(&state, host_name).await;
+    }
+
+    let mut guard = state.config.write().await;
+    guard.replace_configuration(new_config);
+
+    let status_map = &state.app_state.site_status_map;
+    for host_name in hosted.removed.iter().chain(changes.remote_targets.removed.iter()) {
+        status_map.remove(host_name);
+    }
+    for host_name in &changes.remote_targets.added {
+        status_map.insert(host_name.clone(), ProcState::Remote);
+    }
+
+    for host_name in hosted.added.iter().chain(hosted.changed.iter()) {
+        let proc = match guard.hosted_process.iter().flatten().find(|x| &x.host_name == host_name) {
+            Some(p) => p.clone(),
+            None => continue
+        };
+        let resolved_proc = guard.resolve_process_configuration(&proc)?;
+        tokio::task::spawn(crate::proc_host::host(
+            resolved_proc,
+            state.broadcaster.subscribe(),
+            state.clone(),
+        ));
+        tracing::info!("Started a worker loop for {host_name} as it was {} in the configuration file", if hosted.added.contains(host_name) { "added" } else { "changed" });
+    }
+
+    drop(guard);
+    state.invalidate_cache();
+
+    Ok(if changes.hosted_processes.is_empty() && changes.remote_targets.is_empty() {
+        String::from("Reloaded. No sites were changed.")
+    } else {
+        format!("Reloaded. Hosted processes (added: {}, removed: {}, changed: {}) - Remote targets (added: {}, removed: {}, changed: {})",
+            hosted.added.len(), hosted.removed.len(), hosted.changed.len(),
+            changes.remote_targets.added.len(), changes.remote_targets.removed.len(), changes.remote_targets.changed.len())
+    })
+}
+
+pub async fn bg_worker_for_config_file_changes(state: Arc<GlobalState>) {
+    let liveness_token = Arc::new(true);
+
+    let path = state.config.read().await.path.clone();
+    let path = if let Some(p) = path { p } else {
+        tracing::warn!("Not watching the configuration file for changes as its path is unknown.");
+        return
+    };
+
+    let mut last_seen_content = std::fs::read_to_string(&path).ok();
+    let mut status = format!("Watching {path} for changes.");
+
+    loop {
+
+        crate::BG_WORKER_THREAD_MAP.insert("Config Reload".into(), BgTaskInfo {
+            liveness_ptr: Arc::downgrade(&liveness_token),
+            status: status.clone()
+        }); // we dont need to clean this up if we exit, there is a cleanup task that will do it.
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+
+        // the file is briefly missing while odd-box itself writes it to disk, in which case we just try again later
+        let content = match std::fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(_) => continue
+        };
+
+        if last_seen_content.as_ref() == Some(&content) {
+            continue
+        }
+        last_seen_content = Some(content.clone());
+
+        tracing::info!("The configuration file has changed, reloading..");
+
+        let new_config = match parse_and_validate(&content) {
+            Ok(c) => c,
+            Err(e) => {
+                tracing::error!("Ignoring the changes to {path} as the configuration is not valid. The running configuration has not been changed. {e}");
+                status = format!("Last change was rejected: {e}");
+                continue
+            }
+        };
+
+        status = match apply_configuration(state.clone(), new_config).await {
+            Ok(result) => {
+                tracing::info!("{result}");
+                result
+            },
+            Err(e) => {
+                tracing::error!("Failed to apply the changes to {path}: {e:?}");
+                format!("Last change could not be fully applied: {e}")
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::OddBoxConfiguration;
+
+    #[test]
+    fn unchanged_sites_keep_their_runtime_state_but_not_their_old_settings() {
+
+        let example = crate::configuration::v2::OddBoxV2Config::example();
+        let mut old = example.hosted_process.as_ref().expect("example has hosted processes")[0].clone();
+        old.active_port = Some(4321);
+        old.enable_lets_encrypt = Some(false);
+
+        let mut new = old.clone();
+        new.active_port = None;
+        new.enable_lets_encrypt = None;
+        assert!(new == old, "the runtime state does not count as a change to the site");
+
+        new.keep_runtime_state_of(&old);
+        assert_eq!(new.active_port, Some(4321));
+        assert_eq!(new.get_id(), old.get_id());
+        assert_eq!(new.enable_lets_encrypt, None);
+
+        // the process host reads these when the process is started, so the site must be restarted for them to apply
+        let mut changed = new.clone();
+        changed.enable_lets_encrypt = Some(true);
+        assert!(changed != old);
+        let mut changed = new.clone();
+        changed.exclude_from_start_all = Some(true);
+        assert!(changed != old);
+
+    }
+}
@@ -25,7 +25,7 @@ pub trait OddBoxConfiguration<T> {
 pub enum OddBoxConfig {
     #[allow(dead_code)]Legacy(legacy::OddBoxLegacyConfig),
     V1(v1::OddBoxV1Config),
-    V2(v2::OddBoxV2Config)
+    V2(Box<v2::OddBoxV2Config>)
 }
 
 
@@ -100,7 +100,7 @@ impl OddBoxConfig {
         
         let v2_result = toml::from_str::<v2::OddBoxV2Config>(content);
         if let Ok(v2_config) = v2_result {
-            return Ok(OddBoxConfig::V2(v2_config))
+            return Ok(OddBoxConfig::V2(Box::new(v2_config)))
         };
 
         let v1_result = toml::from_str::<v1::OddBoxV1Config>(content);
@@ -135,7 +135,7 @@ impl OddBoxConfig {
                 Ok((v2,OddBoxConfigVersion::V1))
             },
             OddBoxConfig::V2(v2) => {
-                Ok((v2.clone(),OddBoxConfigVersion::V2))
+                Ok((v2.as_ref().clone(),OddBoxConfigVersion::V2))
             },
         }
     }
@@ -143,6 +143,99 @@ impl OddBoxConfig {
 
 
 
+/// Host names of the sites that differ between two configurations
+#[derive(Debug,Default,PartialEq)]
+pub struct SiteChanges {
+    pub added : Vec<String>,
+    pub removed : Vec<String>,
+    pub changed : Vec<String>
+}
+
+impl SiteChanges {
+    fn between<T:PartialEq>(old:&[T], new:&[T], host_name:fn(&T)->&str) -> Self {
+        let mut changes = SiteChanges::default();
+        for n in new {
+            match old.iter().find(|o| host_name(o) == host_name(n)) {
+                None => changes.added.push(host_name(n).to_string()),
+                Some(o) if o != n => changes.changed.push(host_name(n).to_string()),
+                _ => {}
+            }
+        }
+        for o in old {
+            if !new.iter().any(|n| host_name(n) == host_name(o)) {
+                changes.removed.push(host_name(o).to_string());
+            }
+        }
+        changes
+    }
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+#[derive(Debug,Default,PartialEq)]
+pub struct ConfigChanges {
+    pub hosted_processes : SiteChanges,
+    pub remote_targets : SiteChanges
+}
+
+impl v2::OddBoxV2Config {
+    /// Compares the sites of this configuration with those of a newer version of it
+    pub fn site_changes(&self, new:&v2::OddBoxV2Config) -> ConfigChanges {
+        ConfigChanges {
+            hosted_processes: SiteChanges::between(
+                self.hosted_process.as_deref().unwrap_or_default(),
+                new.hosted_process.as_deref().unwrap_or_default(),
+                |x| &x.host_name
+            ),
+            remote_targets: SiteChanges::between(
+                self.remote_target.as_deref().unwrap_or_default(),
+                new.remote_target.as_deref().unwrap_or_default(),
+                |x| &x.host_name
+            )
+        }
+    }
+
+    /// Returns true if the client is one of the configured trusted_proxies
+    pub fn is_trusted_proxy(&self, client_ip:std::net::IpAddr) -> bool {
+        let client_ip = client_ip.to_canonical();
+        self.trusted_proxies.iter().flatten().any(|x| x.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.to_canonical() == client_ip))
+    }
+}
+
+/// True if the requested host name belongs to a site with the given host name, either exactly or as a captured subdomain.
+pub fn host_matches(req_host_name:&str, host_name:&str, capture_subdomains:Option<bool>) -> bool {
+    req_host_name == host_name 
+    || capture_subdomains.unwrap_or_default() && req_host_name.ends_with(&format!(".{}", host_name))
+}
+
+// returns the host names that form a dependency cycle, if there is one
+fn find_dependency_cycle(procs:&[v2::InProcessSiteConfig]) -> Option<Vec<String>> {
+    fn visit<'a>(host_name:&'a str, procs:&'a [v2::InProcessSiteConfig], path:&mut Vec<&'a str>, done:&mut std::collections::HashSet<&'a str>) -> Option<Vec<String>> {
+        if let Some(start) = path.iter().position(|x| *x == host_name) {
+            let mut cycle : Vec<String> = path[start..].iter().map(|x|x.to_string()).collect();
+            cycle.push(host_name.to_string());
+            return Some(cycle)
+        }
+        if done.contains(host_name) {
+            return None
+        }
+        path.push(host_name);
+        if let Some(proc) = procs.iter().find(|x| x.host_name == host_name) {
+            for dependency in proc.depends_on.iter().flatten() {
+                if let Some(cycle) = visit(dependency, procs, path, done) {
+                    return Some(cycle)
+                }
+            }
+        }
+        path.pop();
+        done.insert(host_name);
+        None
+    }
+    let mut done = std::collections::HashSet::new();
+    procs.iter().find_map(|x| visit(&x.host_name, procs, &mut vec![], &mut done))
+}
+
 #[derive(Debug,Clone)]
 pub struct ConfigWrapper {
     internal_configuration : v2::OddBoxV2Config,
@@ -219,6 +312,14 @@ impl ConfigWrapper {
 
     } 
 
+    /// Replaces the configuration with one that was loaded from disk, keeping the path of the current configuration.
+    pub fn replace_configuration(&mut self, config: v2::OddBoxV2Config) {
+        let path = self.internal_configuration.path.clone();
+        self.internal_configuration = config;
+        self.internal_configuration.path = path;
+        self.reload();
+    }
+
     /// Persists the current state of the DashMaps back into the config vectors.
     /// This method should be called before serialization.
     pub fn persist(&mut self) {
@@ -268,7 +369,7 @@ impl ConfigWrapper {
 
         for target in self.remote_target.iter().flatten() {
             if target.enable_lets_encrypt.unwrap_or(false) {
-                if !target.disable_tcp_tunnel_mode.unwrap_or(false) {
+                if !target.tcp_tunnel_mode_disabled() {
                     anyhow::bail!(format!("Invalid configuration for remote target '{}'. LetsEncrypt cannot be enabled when TCP tunnel mode is enabled.", target.host_name));
                 }
                 if target.capture_subdomains.unwrap_or_default() {
@@ -292,7 +393,7 @@ impl ConfigWrapper {
             }
 
             if process.enable_lets_encrypt.unwrap_or(false) {
-                if !process.disable_tcp_tunnel_mode.unwrap_or(false) {
+                if !process.tcp_tunnel_mode_disabled() {
                     anyhow::bail!(format!("Invalid configuration for hosted process '{}'. LetsEncrypt cannot be enabled when TCP tunnel mode is enabled.", process.host_name));
                 }
                 if process.capture_subdomains.unwrap_or_default() {
@@ -330,6 +431,157 @@ impl ConfigWrapper {
                 duplicate_host_names.join(", ")
             ));
         }
+
+        let known_sites : Vec<&String> = self.hosted_process.iter().flatten().map(|x| &x.host_name)
+            .chain(self.remote_target.iter().flatten().map(|x| &x.host_name))
+            .collect();
+
+        for site in self.static_site.iter().flatten() {
+            if known_sites.contains(&&site.host_name) || self.static_site.iter().flatten().filter(|x| x.host_name == site.host_name).count() > 1 {
+                anyhow::bail!("The static site '{}' has the same host name as another configured site.", site.host_name);
+            }
+            if site.dir.trim().is_empty() {
+                anyhow::bail!("The static site '{}' has no dir configured.", site.host_name);
+            }
+            if site.index_files().iter().any(|x| x.is_empty() || x.contains(|c:char| c == '/' || c == '\\')) {
+                anyhow::bail!("Invalid index_files for static site '{}'. Index files must be file names, not paths.", site.host_name);
+            }
+            if site.enable_lets_encrypt.unwrap_or(false) && site.capture_subdomains.unwrap_or_default() {
+                anyhow::bail!("Invalid configuration for static site '{}'. LetsEncrypt cannot be enabled when capture_subdomains is enabled as odd-box does not yet support wildcard certificates", site.host_name);
+            }
+        }
+
+        let known_sites : Vec<&String> = known_sites.into_iter()
+            .chain(self.static_site.iter().flatten().map(|x| &x.host_name))
+            .collect();
+
+        let sites_with_rules = 
+            self.hosted_process.iter().flatten().map(|x| (&x.host_name, &x.path_rules))
+            .chain(self.remote_target.iter().flatten().map(|x| (&x.host_name, &x.path_rules)));
+
+        for site in self.hosted_process.iter().flatten() {
+            if let Some(check) = &site.readiness_check {
+                if check.kind == v2::ReadinessCheckKind::LogLine {
+                    match &check.log_pattern {
+                        Some(pattern) => if let Err(e) = regex::Regex::new(pattern) {
+                            anyhow::bail!("The readiness check log_pattern for site '{}' is not a valid regular expression: {e}", site.host_name);
+                        },
+                        None => anyhow::bail!("The site '{}' uses a LogLine readiness check but has no log_pattern configured.", site.host_name)
+                    }
+                }
+            }
+        }
+
+        let hosted_processes = self.hosted_process.as_deref().unwrap_or_default();
+        for site in hosted_processes {
+            for dependency in site.depends_on.iter().flatten() {
+                if !hosted_processes.iter().any(|x| &x.host_name == dependency) {
+                    anyhow::bail!("The site '{}' depends on '{}' which is not a configured hosted process.", site.host_name, dependency);
+                }
+            }
+        }
+
+        if let Some(cycle) = find_dependency_cycle(hosted_processes) {
+            anyhow::bail!("Hosted processes cannot depend on each other in a cycle: {}", cycle.join(" -> "));
+        }
+
+        let header_rules = self.remote_target.iter().flatten().map(|x| (&x.host_name, &x.request_headers, &x.response_headers))
+            .chain(self.hosted_process.iter().flatten().map(|x| (&x.host_name, &x.request_headers, &x.response_headers)));
+        for (host_name, request_headers, response_headers) in header_rules {
+            for rule in request_headers.iter().flatten().chain(response_headers.iter().flatten()) {
+                if hyper::header::HeaderName::from_bytes(rule.name.as_bytes()).is_err() {
+                    anyhow::bail!("Invalid header rule for site '{host_name}'. '{}' is not a valid header name.", rule.name);
+                }
+                if rule.action != v2::HeaderAction::Remove && rule.value.is_none() {
+                    anyhow::bail!("Invalid header rule for site '{host_name}'. The header '{}' needs a value.", rule.name);
+                }
+            }
+        }
+
+        let rewrite_rules = self.remote_target.iter().flatten().map(|x| (&x.host_name, &x.rewrite_rules))
+            .chain(self.hosted_process.iter().flatten().map(|x| (&x.host_name, &x.rewrite_rules)))
+            .chain(self.static_site.iter().flatten().map(|x| (&x.host_name, &x.rewrite_rules)));
+        for (host_name, rules) in rewrite_rules {
+            for rule in rules.iter().flatten() {
+                if let Err(e) = rule.validate() {
+                    anyhow::bail!("Invalid rewrite rule for site '{host_name}'. {e}");
+                }
+            }
+        }
+
+        for site in self.redirect_site.iter().flatten() {
+            if known_sites.contains(&&site.host_name) {
+                anyhow::bail!("The redirect site '{}' has the same host name as another configured site.", site.host_name);
+            }
+            if let Some(code) = site.status_code {
+                if !v2::REDIRECT_STATUS_CODES.contains(&code) {
+                    anyhow::bail!("Invalid status code {code} for redirect site '{}'. Use 301, 302, 307 or 308.", site.host_name);
+                }
+            }
+            if hyper::Uri::try_from(site.redirect_to.as_str()).is_err() {
+                anyhow::bail!("Invalid redirect_to '{}' for redirect site '{}'.", site.redirect_to, site.host_name);
+            }
+        }
+
+        let auth_configs = self.remote_target.iter().flatten().map(|x| (&x.host_name, &x.auth))
+            .chain(self.hosted_process.iter().flatten().map(|x| (&x.host_name, &x.auth)))
+            .chain(self.static_site.iter().flatten().map(|x| (&x.host_name, &x.auth)));
+        for (host_name, auth) in auth_configs {
+            if let Some(auth) = auth {
+                if let Err(e) = crate::http_proxy::auth::validate(auth) {
+                    anyhow::bail!("Invalid auth configuration for site '{host_name}'. {e}");
+                }
+            }
+        }
+
+        let global_ranges = self.allow.iter().flatten().chain(self.deny.iter().flatten()).map(|x| (String::from("the global configuration"), x));
+        let site_ranges = self.remote_target.iter().flatten().map(|x| (&x.host_name, &x.allow, &x.deny))
+            .chain(self.hosted_process.iter().flatten().map(|x| (&x.host_name, &x.allow, &x.deny)))
+            .chain(self.static_site.iter().flatten().map(|x| (&x.host_name, &x.allow, &x.deny)))
+            .flat_map(|(host_name, allow, deny)| allow.iter().flatten().chain(deny.iter().flatten()).map(move |x| (format!("site '{host_name}'"), x)));
+        for (owner, range) in global_ranges.chain(site_ranges) {
+            if v2::cidr_contains(range, std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED)).is_none() {
+                anyhow::bail!("Invalid ip address or cidr range '{range}' in the allow/deny list of {owner}.");
+            }
+        }
+
+        let rate_limits = self.rate_limit.iter().map(|x| (String::from("the global configuration"), x))
+            .chain(self.remote_target.iter().flatten().filter_map(|x| x.rate_limit.as_ref().map(|r| (format!("site '{}'", x.host_name), r))))
+            .chain(self.hosted_process.iter().flatten().filter_map(|x| x.rate_limit.as_ref().map(|r| (format!("site '{}'", x.host_name), r))))
+            .chain(self.static_site.iter().flatten().filter_map(|x| x.rate_limit.as_ref().map(|r| (format!("site '{}'", x.host_name), r))));
+        for (owner, rate_limit) in rate_limits {
+            if rate_limit.requests == 0 {
+                anyhow::bail!("Invalid rate limit for {owner}. requests must be greater than 0.");
+            }
+            if let Some(header) = &rate_limit.per_header {
+                if hyper::header::HeaderName::from_bytes(header.as_bytes()).is_err() {
+                    anyhow::bail!("Invalid rate limit for {owner}. '{header}' is not a valid header name.");
+                }
+            }
+        }
+
+        for proxy in self.trusted_proxies.iter().flatten() {
+            if proxy.parse::<std::net::IpAddr>().is_err() {
+                anyhow::bail!("Invalid trusted proxy '{proxy}'. Trusted proxies must be ip addresses.");
+            }
+        }
+
+        for site in self.remote_target.iter().flatten() {
+            if site.load_balancing == Some(v2::LoadBalancing::CookieHash) && site.sticky_cookie.is_none() {
+                anyhow::bail!("The site '{}' uses CookieHash load balancing but has no sticky_cookie configured.", site.host_name);
+            }
+        }
+
+        for (host_name, rules) in sites_with_rules {
+            for rule in rules.iter().flatten() {
+                if !rule.path.starts_with('/') {
+                    anyhow::bail!("Invalid path rule '{}' for site '{}'. Paths must start with '/'.", rule.path, host_name);
+                }
+                if !known_sites.contains(&&rule.target) {
+                    anyhow::bail!("Invalid path rule '{}' for site '{}'. The target '{}' is not a configured site.", rule.path, host_name, rule.target);
+                }
+            }
+        }
     
         let duplicate_ports: Vec<(u16, Vec<String>)> = ports
             .into_iter()
@@ -352,6 +604,170 @@ impl ConfigWrapper {
     }
     
 
+    /// Returns the path rule that applies to a request, if the site configured for the
+    /// requested host name has any rules matching the path.
+    pub fn find_path_rule(&self, req_host_name: &str, req_path: &str) -> Option<v2::PathRule> {
+        let host_matches = |host_name: &str, capture_subdomains: Option<bool>| host_matches(req_host_name, host_name, capture_subdomains);
+        let rules = 
+            if let Some(p) = self.hosted_process.iter().flatten().find(|p| host_matches(&p.host_name, p.capture_subdomains)) {
+                p.path_rules.as_ref()
+            } else if let Some(r) = self.remote_target.iter().flatten().find(|r| host_matches(&r.host_name, r.capture_subdomains)) {
+                r.path_rules.as_ref()
+            } else {
+                None
+            };
+        rules.and_then(|rules| v2::find_path_rule(rules, req_path)).cloned()
+    }
+
+    /// Returns true if plain http requests for the host name should be redirected to https.
+    pub fn force_https(&self, req_host_name: &str) -> bool {
+        if let Some(p) = self.hosted_process.iter().flatten().find(|p| host_matches(req_host_name, &p.host_name, p.capture_subdomains)) {
+            p.force_https.unwrap_or_default()
+        } else if let Some(r) = self.remote_target.iter().flatten().find(|r| host_matches(req_host_name, &r.host_name, r.capture_subdomains)) {
+            r.force_https.unwrap_or_default()
+        } else if let Some(s) = self.static_site.iter().flatten().find(|s| host_matches(req_host_name, &s.host_name, s.capture_subdomains)) {
+            s.force_https.unwrap_or_default()
+        } else {
+            false
+        }
+    }
+
+    /// Checks the global allow and deny lists, followed by those of the site for the host name if one is given.
+    pub fn client_is_allowed(&self, client_ip: std::net::IpAddr, req_host_name: Option<&str>) -> bool {
+        if !v2::ip_is_allowed(self.allow.as_ref(), self.deny.as_ref(), client_ip) {
+            return false
+        }
+        let Some(req_host_name) = req_host_name else {
+            return true
+        };
+        if let Some(p) = self.hosted_process.iter().flatten().find(|p| host_matches(req_host_name, &p.host_name, p.capture_subdomains)) {
+            v2::ip_is_allowed(p.allow.as_ref(), p.deny.as_ref(), client_ip)
+        } else if let Some(r) = self.remote_target.iter().flatten().find(|r| host_matches(req_host_name, &r.host_name, r.capture_subdomains)) {
+            v2::ip_is_allowed(r.allow.as_ref(), r.deny.as_ref(), client_ip)
+        } else if let Some(s) = self.static_site.iter().flatten().find(|s| host_matches(req_host_name, &s.host_name, s.capture_subdomains)) {
+            v2::ip_is_allowed(s.allow.as_ref(), s.deny.as_ref(), client_ip)
+        } else {
+            true
+        }
+    }
+
+    /// Returns the global rate limit and the one of the site for the host name, along with the scope that their counters are kept under.
+    pub fn find_rate_limits(&self, req_host_name: &str) -> Vec<(String, v2::RateLimit)> {
+        let site_limit = 
+            if let Some(p) = self.hosted_process.iter().flatten().find(|p| host_matches(req_host_name, &p.host_name, p.capture_subdomains)) {
+                p.rate_limit.clone().map(|x| (p.host_name.clone(), x))
+            } else if let Some(r) = self.remote_target.iter().flatten().find(|r| host_matches(req_host_name, &r.host_name, r.capture_subdomains)) {
+                r.rate_limit.clone().map(|x| (r.host_name.clone(), x))
+            } else if let Some(s) = self.static_site.iter().flatten().find(|s| host_matches(req_host_name, &s.host_name, s.capture_subdomains)) {
+                s.rate_limit.clone().map(|x| (s.host_name.clone(), x))
+            } else {
+                None
+            };
+        self.rate_limit.clone().map(|x| (String::from("*"), x)).into_iter().chain(site_limit).collect()
+    }
+
+    /// Returns the auth configuration of the site for the host name, if it has one.
+    pub fn find_auth(&self, req_host_name: &str) -> Option<v2::AuthConfig> {
+        if let Some(p) = self.hosted_process.iter().flatten().find(|p| host_matches(req_host_name, &p.host_name, p.capture_subdomains)) {
+            p.auth.clone()
+        } else if let Some(r) = self.remote_target.iter().flatten().find(|r| host_matches(req_host_name, &r.host_name, r.capture_subdomains)) {
+            r.auth.clone()
+        } else if let Some(s) = self.static_site.iter().flatten().find(|s| host_matches(req_host_name, &s.host_name, s.capture_subdomains)) {
+            s.auth.clone()
+        } else {
+            None
+        }
+    }
+
+    /// Returns the static site for the host name, with $cfg_dir and ~ in its directory resolved.
+    /// Relative directories are resolved from the directory of the configuration file.
+    pub fn find_static_site(&self, req_host_name: &str) -> Option<v2::StaticSiteConfig> {
+        let mut site = self.static_site.iter().flatten().find(|s| host_matches(req_host_name, &s.host_name, s.capture_subdomains))?.clone();
+        site.dir = self.resolve_relative_path(&site.dir);
+        Some(site)
+    }
+
+    /// Returns the error page templates for the host name, using the global templates for formats that the site does not set.
+    pub fn find_error_pages(&self, req_host_name: &str) -> Option<v2::ErrorPagesConfig> {
+        let site_pages = 
+            if let Some(p) = self.hosted_process.iter().flatten().find(|p| host_matches(req_host_name, &p.host_name, p.capture_subdomains)) {
+                p.error_pages.as_ref()
+            } else if let Some(r) = self.remote_target.iter().flatten().find(|r| host_matches(req_host_name, &r.host_name, r.capture_subdomains)) {
+                r.error_pages.as_ref()
+            } else if let Some(s) = self.static_site.iter().flatten().find(|s| host_matches(req_host_name, &s.host_name, s.capture_subdomains)) {
+                s.error_pages.as_ref()
+            } else {
+                None
+            };
+        let global_pages = self.error_pages.as_ref();
+        if site_pages.is_none() && global_pages.is_none() {
+            return None
+        }
+        let template = |select: fn(&v2::ErrorPagesConfig) -> &Option<String>| 
+            site_pages.and_then(|x| select(x).clone())
+                .or(global_pages.and_then(|x| select(x).clone()))
+                .map(|x| self.resolve_relative_path(&x));
+        Some(v2::ErrorPagesConfig {
+            html: template(|x| &x.html),
+            json: template(|x| &x.json)
+        })
+    }
+
+    /// The timeouts to use for a backend. The global timeouts are used for anything the backend does not set.
+    pub fn upstream_timeouts(&self, backend: Option<&v2::Backend>) -> v2::UpstreamTimeouts {
+        let global_timeouts = self.timeouts.clone().unwrap_or_default();
+        match backend.and_then(|x| x.timeouts.as_ref()) {
+            Some(timeouts) => timeouts.or(&global_timeouts),
+            None => global_timeouts
+        }
+    }
+
+    /// Finds the connect timeout for a connection to the given host and port.
+    /// Subdomains of a backend address use the timeouts of that backend, since they are used when forwarding subdomains.
+    pub fn find_connect_timeout(&self, host: &str, port: u16) -> std::time::Duration {
+        let backend = self.remote_target.iter().flatten()
+            .flat_map(|x| x.backends.iter())
+            .find(|b| b.port == port && (b.address.eq_ignore_ascii_case(host) || host.to_lowercase().ends_with(&format!(".{}", b.address.to_lowercase()))));
+        self.upstream_timeouts(backend).connect()
+    }
+
+    // replaces $cfg_dir and ~, and resolves relative paths from the directory of the configuration file
+    fn resolve_relative_path(&self, path: &str) -> String {
+        let cfg_dir = self.path.as_ref()
+            .and_then(|p| std::path::Path::new(p).parent().map(|x| x.to_path_buf()))
+            .unwrap_or(std::path::PathBuf::from("."));
+        let path = path.replace("$cfg_dir", &cfg_dir.to_string_lossy());
+        // only a leading ~ is the home directory, anywhere else it is part of a file name
+        let path = match (path.strip_prefix('~'), dirs::home_dir()) {
+            (Some(""), Some(home_dir)) => home_dir,
+            (Some(rest), Some(home_dir)) if rest.starts_with('/') => home_dir.join(rest.trim_start_matches('/')),
+            _ => std::path::PathBuf::from(path)
+        };
+        cfg_dir.join(path).to_string_lossy().to_string()
+    }
+
+    /// Redirect sites are checked first, followed by the rewrite rules of the site for the host name.
+    pub fn find_rewrite(&self, req_host_name: &str, req_path: &str, req_query: Option<&str>) -> Option<v2::RewriteOutcome> {
+        if let Some(redirect_site) = self.redirect_site.iter().flatten().find(|r| host_matches(req_host_name, &r.host_name, r.capture_subdomains)) {
+            let path_and_query = match req_query {
+                Some(q) => format!("{req_path}?{q}"),
+                None => req_path.to_string()
+            };
+            return Some(redirect_site.redirect(&path_and_query))
+        }
+        let rules = 
+            if let Some(p) = self.hosted_process.iter().flatten().find(|p| host_matches(req_host_name, &p.host_name, p.capture_subdomains)) {
+                p.rewrite_rules.as_ref()
+            } else if let Some(r) = self.remote_target.iter().flatten().find(|r| host_matches(req_host_name, &r.host_name, r.capture_subdomains)) {
+                r.rewrite_rules.as_ref()
+            } else if let Some(s) = self.static_site.iter().flatten().find(|s| host_matches(req_host_name, &s.host_name, s.capture_subdomains)) {
+                s.rewrite_rules.as_ref()
+            } else {
+                None
+            };
+        rules.and_then(|rules| rules.iter().find_map(|rule| rule.evaluate(req_path, req_query)))
+    }
+
     pub fn get_parent_path(&mut self) -> anyhow::Result<String> {
         // todo - use cache and clear on path change
         // if let Some(pre_resolved) = &self.1 {
@@ -608,7 +1024,16 @@ impl ConfigWrapper {
             port: proc.port,
             https: proc.https,
             capture_subdomains: proc.capture_subdomains,
-            forward_subdomains: proc.forward_subdomains
+            forward_subdomains: proc.forward_subdomains,
+            readiness_check: proc.readiness_check.clone(),
+            liveness_check: proc.liveness_check.clone(),
+            restart_policy: proc.restart_policy.clone(),
+            max_restarts: proc.max_restarts,
+            restart_backoff: proc.restart_backoff.clone(),
+            stop_signal: proc.stop_signal.clone(),
+            stop_timeout_seconds: proc.stop_timeout_seconds,
+            depends_on: proc.depends_on.clone(),
+            stop_with_dependencies: proc.stop_with_dependencies
         };
 
         let resolved_home_dir_path = dirs::home_dir().ok_or(anyhow::anyhow!(String::from("Failed to resolve home directory.")))?;
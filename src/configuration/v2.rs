@@ -53,7 +53,67 @@ pub struct InProcessSiteConfig {
     pub exclude_from_start_all: Option<bool>,
     /// If you want to use lets-encrypt for generating certificates automatically for this site.
     /// Defaults to false. This feature will disable tcp tunnel mode.
-    pub enable_lets_encrypt: Option<bool>
+    pub enable_lets_encrypt: Option<bool>,
+    /// Path based routing rules, for example sending /api/* to another site.
+    /// Sites with path rules are always handled by the terminating proxy.
+    pub path_rules: Option<Vec<PathRule>>,
+    /// Decides when the process is ready to receive requests. Without a readiness check the process
+    /// is considered ready once it accepts tcp connections.
+    pub readiness_check: Option<ReadinessCheck>,
+    /// Periodically probes the running process and restarts it once unhealthy_threshold checks in a row have failed.
+    /// Useful for processes that can hang without exiting. The healthy_threshold setting is not used here.
+    pub liveness_check: Option<HealthCheck>,
+    /// Decides if the process should be started again after it stops on its own. Defaults to always.
+    pub restart_policy: Option<RestartPolicy>,
+    /// Gives up after this many restarts in a row without the process staying up for the crash loop window.
+    /// Defaults to restarting forever.
+    pub max_restarts: Option<u32>,
+    /// Delays between automatic restarts and when a process is considered to be crash looping.
+    pub restart_backoff: Option<RestartBackoff>,
+    /// Signal sent to the process group when stopping the process. Defaults to SIGTERM.
+    /// Only used on unix systems, on windows the process tree is stopped using taskkill.
+    pub stop_signal: Option<StopSignal>,
+    /// Seconds to wait for the process to exit after the stop signal before it is killed. Defaults to 10.
+    pub stop_timeout_seconds: Option<u64>,
+    /// Host names of hosted processes that must be running before this process is started.
+    /// They are started automatically when this site is started.
+    pub depends_on: Option<Vec<String>>,
+    /// Stops this process when one of its dependencies is stopped. Defaults to false.
+    pub stop_with_dependencies: Option<bool>,
+    /// Sends a PROXY protocol header with the address of the client when opening a tcp tunnel to this process.
+    /// Only used in tcp tunnel mode, the process must be configured to expect the header.
+    pub proxy_protocol: Option<ProxyProtocol>,
+    /// Adds Forwarded, X-Forwarded-For/-Proto/-Host/-Port and X-Real-IP headers to requests sent to this site.
+    /// Defaults to true. Only applies to requests handled by the terminating proxy.
+    pub forwarded_headers: Option<bool>,
+    /// Headers to set, append or remove on requests before they are sent to this site.
+    pub request_headers: Option<Vec<HeaderRule>>,
+    /// Headers to set, append or remove on responses from this site.
+    /// Defaults to setting the odd-box header, set this to an empty list to send responses without it.
+    pub response_headers: Option<Vec<HeaderRule>>,
+    /// Regex based rewrites and redirects, evaluated before path rules. The first matching rule is used.
+    /// Sites with rewrite rules are always handled by the terminating proxy.
+    pub rewrite_rules: Option<Vec<RewriteRule>>,
+    /// Redirects plain http requests to the tls port. Defaults to false.
+    pub force_https: Option<bool>,
+    /// Adds a Strict-Transport-Security header to https responses from this site.
+    /// Sites with hsts configured are always handled by the terminating proxy.
+    pub hsts: Option<HstsConfig>,
+    /// Requires requests to be authenticated before they are sent to this site.
+    /// Sites with auth are always handled by the terminating proxy.
+    pub auth: Option<AuthConfig>,
+    /// Ip addresses or cidr ranges, such as "192.168.1.0/24", that may connect to this site. Everyone is allowed if this is not set.
+    pub allow: Option<Vec<String>>,
+    /// Ip addresses or cidr ranges that may not connect to this site. Takes precedence over allow.
+    pub deny: Option<Vec<String>>,
+    /// Limits how many requests each client can make to this site.
+    pub rate_limit: Option<RateLimit>,
+    /// Compresses responses from this site for clients that support it.
+    /// Sites with compression are always handled by the terminating proxy.
+    pub compression: Option<CompressionConfig>,
+    /// Templates for error responses from odd-box, such as when the process cannot be reached.
+    /// Falls back to the global error_pages for formats that are not set here.
+    pub error_pages: Option<ErrorPagesConfig>
 }
 
 
@@ -75,12 +135,139 @@ pub struct FullyResolvedInProcessSiteConfig {
     pub https : Option<bool>,
     pub capture_subdomains : Option<bool>,
     pub forward_subdomains : Option<bool>,
+    pub readiness_check : Option<ReadinessCheck>,
+    pub liveness_check : Option<HealthCheck>,
+    pub restart_policy : Option<RestartPolicy>,
+    pub max_restarts : Option<u32>,
+    pub restart_backoff : Option<RestartBackoff>,
+    pub stop_signal : Option<StopSignal>,
+    pub stop_timeout_seconds : Option<u64>,
+    pub depends_on : Option<Vec<String>>,
+    pub stop_with_dependencies : Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub enum ReadinessCheckKind {
+    /// Waits until the port of the process accepts tcp connections
+    Tcp,
+    /// Waits until a GET request to the configured path returns a 2xx or 3xx response
+    Http,
+    /// Waits until the process writes a line matching log_pattern to stdout or stderr
+    LogLine
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub struct ReadinessCheck {
+    pub kind : ReadinessCheckKind,
+    /// Path to request for http checks. Defaults to "/".
+    pub path : Option<String>,
+    /// Regular expression used by LogLine checks, for example "Started .* in .* seconds".
+    pub log_pattern : Option<String>,
+    /// Seconds to wait for the process to become ready before it is restarted. Defaults to 60.
+    pub timeout_seconds : Option<u64>,
+}
+
+impl ReadinessCheck {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.timeout_seconds.unwrap_or(60).max(1))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Restarts the process no matter how it exited
+    #[default]
+    Always,
+    /// Only restarts the process if it exited with a non-zero exit code or failed its checks
+    OnFailure,
+    /// Never restarts the process automatically
+    Never
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema, Default)]
+pub struct RestartBackoff {
+    /// Seconds to wait before the first restart. The delay doubles for each restart in a row. Defaults to 5.
+    pub initial_delay_seconds : Option<u64>,
+    /// Upper limit for the delay between restarts. Defaults to 300.
+    pub max_delay_seconds : Option<u64>,
+    /// Number of failures within the crash loop window before the process is marked as crash looping. Defaults to 5.
+    pub crash_loop_threshold : Option<u32>,
+    /// Processes that stay up for longer than this many seconds are considered stable again. Defaults to 60.
+    pub crash_loop_window_seconds : Option<u64>,
+}
+
+impl RestartBackoff {
+    pub fn initial_delay(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.initial_delay_seconds.unwrap_or(5))
+    }
+    pub fn max_delay(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.max_delay_seconds.unwrap_or(300)).max(self.initial_delay())
+    }
+    pub fn crash_loop_threshold(&self) -> u32 {
+        self.crash_loop_threshold.unwrap_or(5).max(1)
+    }
+    pub fn crash_loop_window(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.crash_loop_window_seconds.unwrap_or(60))
+    }
+    /// Delay before the given restart in a row, starting at 1.
+    pub fn delay_for_attempt(&self, attempt:u32) -> std::time::Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_delay().saturating_mul(factor).min(self.max_delay())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema, Default)]
+pub enum StopSignal {
+    #[default]
+    #[serde(rename = "SIGTERM")]
+    Term,
+    #[serde(rename = "SIGINT")]
+    Int,
+    #[serde(rename = "SIGQUIT")]
+    Quit,
+    #[serde(rename = "SIGHUP")]
+    Hup,
+    /// Kills the process right away without giving it a chance to clean up
+    #[serde(rename = "SIGKILL")]
+    Kill
+}
+
+impl FullyResolvedInProcessSiteConfig {
+    pub fn stop_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.stop_timeout_seconds.unwrap_or(10))
+    }
+    /// How long requests to a cold-started site should be held while waiting for the process to become ready.
+    pub fn startup_timeout(&self) -> std::time::Duration {
+        self.readiness_check.as_ref().map(|x|x.timeout()).unwrap_or(std::time::Duration::from_secs(10))
+    }
 }
 
 impl InProcessSiteConfig {
     pub fn get_id(&self) -> &ProcId {
         &self.proc_id
     }
+    /// Copies the state that only exists at runtime (the id of the worker loop and the selected port)
+    /// from another instance of the same site, leaving everything read from the configuration file alone.
+    pub fn keep_runtime_state_of(&mut self, other: &InProcessSiteConfig) {
+        self.proc_id = other.proc_id.clone();
+        self.active_port = other.active_port;
+    }
+    /// How long requests to a cold-started site should be held while waiting for the process to become ready.
+    pub fn startup_timeout(&self) -> std::time::Duration {
+        self.readiness_check.as_ref().map(|x|x.timeout()).unwrap_or(std::time::Duration::from_secs(10))
+    }
+    /// Returns true if this site can only be served by the terminating proxy.
+    pub fn tcp_tunnel_mode_disabled(&self) -> bool {
+        self.disable_tcp_tunnel_mode.unwrap_or_default()
+        || self.path_rules.as_ref().is_some_and(|x| !x.is_empty())
+        || self.rewrite_rules.as_ref().is_some_and(|x| !x.is_empty())
+        || self.hsts.is_some()
+        || self.auth.is_some()
+        || self.compression.is_some()
+        || self.request_headers.as_ref().is_some_and(|x| !x.is_empty())
+        || self.response_headers.as_ref().is_some_and(|x| !x.is_empty())
+    }
 }
 
 impl PartialEq for InProcessSiteConfig {
@@ -98,7 +285,32 @@ impl PartialEq for InProcessSiteConfig {
         self.port == other.port &&
         self.https == other.https &&
         compare_option_bool(self.capture_subdomains, other.capture_subdomains) &&
-        compare_option_bool(self.forward_subdomains, other.forward_subdomains)
+        compare_option_bool(self.forward_subdomains, other.forward_subdomains) &&
+        self.path_rules == other.path_rules &&
+        self.readiness_check == other.readiness_check &&
+        self.liveness_check == other.liveness_check &&
+        self.restart_policy == other.restart_policy &&
+        self.max_restarts == other.max_restarts &&
+        self.restart_backoff == other.restart_backoff &&
+        self.stop_signal == other.stop_signal &&
+        self.stop_timeout_seconds == other.stop_timeout_seconds &&
+        self.depends_on == other.depends_on &&
+        compare_option_bool(self.stop_with_dependencies, other.stop_with_dependencies) &&
+        self.proxy_protocol == other.proxy_protocol &&
+        self.forwarded_headers.unwrap_or(true) == other.forwarded_headers.unwrap_or(true) &&
+        self.request_headers == other.request_headers &&
+        self.response_headers == other.response_headers &&
+        self.rewrite_rules == other.rewrite_rules &&
+        compare_option_bool(self.force_https, other.force_https) &&
+        self.hsts == other.hsts &&
+        self.auth == other.auth &&
+        self.allow == other.allow &&
+        self.deny == other.deny &&
+        self.rate_limit == other.rate_limit &&
+        self.compression == other.compression &&
+        self.error_pages == other.error_pages &&
+        compare_option_bool(self.exclude_from_start_all, other.exclude_from_start_all) &&
+        compare_option_bool(self.enable_lets_encrypt, other.enable_lets_encrypt)
     }
 }
 
@@ -108,7 +320,7 @@ fn compare_option_bool(a: Option<bool>, b: Option<bool>) -> bool {
         (None, Some(false)) | (Some(false), None) => true,
         _ => a == b,
     };
-    println!("Comparing Option<bool>: {:?} vs {:?} -- result: {result}", a, b);
+    tracing::trace!("Comparing Option<bool>: {:?} vs {:?} -- result: {result}", a, b);
     result
 }
 
@@ -117,7 +329,7 @@ fn compare_option_log_format(a: &Option<LogFormat>, b: &Option<LogFormat>) -> bo
         (None, Some(LogFormat::standard)) | (Some(LogFormat::standard), None) => true,
         _ => a == b,
     };
-    println!("Comparing Option<LogFormat>: {:?} vs {:?} -- result: {result}", a, b);
+    tracing::trace!("Comparing Option<LogFormat>: {:?} vs {:?} -- result: {result}", a, b);
     result
 }
 
@@ -140,6 +352,592 @@ pub struct Backend {
     pub https : Option<bool>,
     /// H2C,H2,H2CPK - used to signal use of prior knowledge http2 or http2 over clear text. 
     pub hints : Option<Vec<Hint>>,
+    /// Actively checks the backend and stops sending traffic to it while it is unhealthy.
+    pub health_check : Option<HealthCheck>,
+    /// Relative weight used by the WeightedRoundRobin load balancing strategy. Defaults to 1.
+    pub weight : Option<u32>,
+    /// Sends a PROXY protocol header with the address of the client when opening a tcp tunnel to this backend.
+    /// Only used in tcp tunnel mode, the backend must be configured to expect the header.
+    pub proxy_protocol : Option<ProxyProtocol>,
+    /// Overrides the global timeouts for this backend.
+    pub timeouts : Option<UpstreamTimeouts>,
+    /// Stops sending traffic to the backend for a while when requests to it keep failing.
+    pub outlier_detection : Option<OutlierDetection>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub enum ProxyProtocol {
+    /// The human readable version of the HAProxy PROXY protocol
+    V1,
+    /// The binary version of the HAProxy PROXY protocol
+    V2
+}
+
+impl Backend {
+    /// Identifies this backend in shared state such as health and connection tracking.
+    pub fn key(&self) -> String {
+        format!("{}:{}",self.address,self.port)
+    }
+    pub fn weight(&self) -> u32 {
+        self.weight.unwrap_or(1)
+    }
+}
+
+/// Limits how long odd-box waits for a backend. When a timeout expires, the terminating proxy responds with 504 and tcp tunnels are closed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub struct UpstreamTimeouts {
+    /// Seconds to wait for a connection to the backend. Defaults to 10.
+    pub connect_seconds : Option<u64>,
+    /// Seconds to wait for the response headers once the request has been sent. Not limited by default.
+    pub first_byte_seconds : Option<u64>,
+    /// Seconds that a whole response, including the body, may take. Not limited by default.
+    pub total_seconds : Option<u64>,
+    /// Seconds without any data being transferred before a response or tcp tunnel is closed. Not limited by default.
+    pub idle_seconds : Option<u64>,
+}
+
+impl UpstreamTimeouts {
+    /// Uses the values from the fallback for anything that is not set here.
+    pub fn or(&self, fallback:&UpstreamTimeouts) -> UpstreamTimeouts {
+        UpstreamTimeouts {
+            connect_seconds: self.connect_seconds.or(fallback.connect_seconds),
+            first_byte_seconds: self.first_byte_seconds.or(fallback.first_byte_seconds),
+            total_seconds: self.total_seconds.or(fallback.total_seconds),
+            idle_seconds: self.idle_seconds.or(fallback.idle_seconds)
+        }
+    }
+    pub fn connect(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.connect_seconds.unwrap_or(10).max(1))
+    }
+    pub fn first_byte(&self) -> Option<std::time::Duration> {
+        self.first_byte_seconds.map(|x| std::time::Duration::from_secs(x.max(1)))
+    }
+    pub fn total(&self) -> Option<std::time::Duration> {
+        self.total_seconds.map(|x| std::time::Duration::from_secs(x.max(1)))
+    }
+    pub fn idle(&self) -> Option<std::time::Duration> {
+        self.idle_seconds.map(|x| std::time::Duration::from_secs(x.max(1)))
+    }
+}
+
+/// Sends a failed request to another backend of the site.
+/// Only requests without a body are retried, since the body has already been sent to the first backend.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub struct RetryPolicy {
+    /// Number of attempts including the first one. Defaults to 2.
+    pub attempts: Option<u32>,
+    /// Milliseconds to wait before the first retry, doubled for every retry after that. Defaults to 0.
+    pub backoff_ms: Option<u64>,
+    /// Also retries idempotent requests (GET, HEAD, OPTIONS, PUT, DELETE and TRACE) that get a 502 or 503 response.
+    /// Connection failures are always retried. Defaults to false.
+    pub on_unavailable: Option<bool>
+}
+
+impl RetryPolicy {
+    pub fn attempts(&self) -> u32 {
+        self.attempts.unwrap_or(2).max(1)
+    }
+    /// How long to wait before the given retry, where the first retry is 1.
+    pub fn backoff_for_retry(&self, retry: u32) -> std::time::Duration {
+        let backoff_ms = self.backoff_ms.unwrap_or_default();
+        std::time::Duration::from_millis(backoff_ms.saturating_mul(1 << retry.saturating_sub(1).min(16)))
+    }
+    /// True if a request that got the given response status should be sent to another backend.
+    pub fn should_retry_status(&self, method: &hyper::Method, status: hyper::StatusCode) -> bool {
+        self.on_unavailable.unwrap_or_default()
+            && method.is_idempotent()
+            && (status == hyper::StatusCode::BAD_GATEWAY || status == hyper::StatusCode::SERVICE_UNAVAILABLE)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub enum HealthCheckKind {
+    /// Sends a GET request to the configured path and expects a 2xx or 3xx response
+    Http,
+    /// Only checks that the backend accepts tcp connections
+    Tcp
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub struct HealthCheck {
+    pub kind : HealthCheckKind,
+    /// Path to request for http checks. Defaults to "/".
+    pub path : Option<String>,
+    /// Seconds between checks. Defaults to 10.
+    pub interval_seconds : Option<u64>,
+    /// Seconds to wait for a response before the check is considered failed. Defaults to 5.
+    pub timeout_seconds : Option<u64>,
+    /// Number of failed checks in a row before the backend is marked as unhealthy. Defaults to 3.
+    pub unhealthy_threshold : Option<u32>,
+    /// Number of successful checks in a row before an unhealthy backend is used again. Defaults to 2.
+    pub healthy_threshold : Option<u32>,
+}
+
+impl HealthCheck {
+    pub fn interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.interval_seconds.unwrap_or(10).max(1))
+    }
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.timeout_seconds.unwrap_or(5).max(1))
+    }
+    pub fn unhealthy_threshold(&self) -> u32 {
+        self.unhealthy_threshold.unwrap_or(3).max(1)
+    }
+    pub fn healthy_threshold(&self) -> u32 {
+        self.healthy_threshold.unwrap_or(2).max(1)
+    }
+}
+
+/// Passive health checking based on the results of real requests and tcp tunnels, as a complement to the active health checks.
+/// Connection errors, timeouts and 5xx responses count as failures.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub struct OutlierDetection {
+    /// Number of failures in a row before the backend is ejected. Defaults to 5.
+    pub consecutive_failures : Option<u32>,
+    /// Seconds within which the failures must happen. Defaults to 30.
+    pub window_seconds : Option<u64>,
+    /// Seconds that an ejected backend gets no traffic. After that it is half-open: it gets a limited number of trial requests,
+    /// the first failure ejects it again and the first success brings it back. Defaults to 30.
+    pub cooldown_seconds : Option<u64>,
+    /// Set to false to only count connection errors and timeouts as failures. Defaults to true.
+    pub count_server_errors : Option<bool>,
+    /// Number of trial requests that a half-open backend may have in flight at the same time. Defaults to 1.
+    pub half_open_requests : Option<u32>,
+}
+
+impl OutlierDetection {
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures.unwrap_or(5).max(1)
+    }
+    pub fn window(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.window_seconds.unwrap_or(30).max(1))
+    }
+    pub fn cooldown(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.cooldown_seconds.unwrap_or(30).max(1))
+    }
+    pub fn half_open_requests(&self) -> u32 {
+        self.half_open_requests.unwrap_or(1).max(1)
+    }
+}
+
+/// Routes requests for a specific path prefix to another configured site.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub struct PathRule {
+    /// Path prefix to match, such as "/api" or "/api/*". When several rules match, the longest prefix wins.
+    pub path: String,
+    /// The host_name of the hosted_process or remote_target that should handle matching requests.
+    pub target: String,
+    /// Removes the matched prefix from the path before forwarding the request. Defaults to false.
+    pub strip_prefix: Option<bool>
+}
+
+impl PathRule {
+    fn prefix(&self) -> &str {
+        self.path.trim_end_matches('*').trim_end_matches('/')
+    }
+    pub fn matches(&self, path: &str) -> bool {
+        let prefix = self.prefix();
+        prefix.is_empty() 
+            || path == prefix 
+            || path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'))
+    }
+    /// Returns the path and query to forward for a request that matched this rule.
+    pub fn rewrite_path_and_query(&self, path_and_query: &str) -> String {
+        if !self.strip_prefix.unwrap_or_default() {
+            return path_and_query.to_string()
+        }
+        match path_and_query.strip_prefix(self.prefix()) {
+            Some(rest) if rest.starts_with('/') => rest.to_string(),
+            Some(rest) => format!("/{rest}"),
+            None => path_and_query.to_string()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub enum HeaderAction {
+    /// Sets the header, replacing any existing values
+    Set,
+    /// Adds a value to the header while keeping any existing values
+    Append,
+    /// Removes the header
+    Remove
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub enum AuthKind {
+    /// Http basic auth against the configured users
+    Basic,
+    /// Sends a request to the configured url, which decides if the request is allowed
+    Forward
+}
+
+/// Protects a site with http basic auth or forward auth.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub struct AuthConfig {
+    pub kind : AuthKind,
+    /// Basic only. Users in htpasswd format ("name:hash"), hashed with bcrypt or argon2.
+    pub users : Option<Vec<String>>,
+    /// Basic only. Realm shown by the browser when asking for credentials. Defaults to "odd-box".
+    pub realm : Option<String>,
+    /// Forward only. Each request is first sent here with the original headers and X-Forwarded-Method/-Uri.
+    /// A 2xx response allows the request, any other response is returned to the client.
+    pub url : Option<String>,
+    /// Forward only. Headers to copy from the auth response to the request, such as "Remote-User".
+    pub copy_headers : Option<Vec<String>>
+}
+
+/// Token bucket rate limit. Each client can make 'burst' requests at once, and regains 'requests' per 'period_seconds'.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub struct RateLimit {
+    /// Number of requests allowed per period
+    pub requests: u32,
+    /// Length of the period in seconds. Defaults to 1.
+    pub period_seconds: Option<u64>,
+    /// Number of requests that can be made at once. Defaults to the value of requests.
+    pub burst: Option<u32>,
+    /// Gives each value of this header a separate limit, such as "x-api-key".
+    pub per_header: Option<String>,
+    /// Gives each path a separate limit. Defaults to false.
+    pub per_path: Option<bool>,
+    /// Also counts new tcp tunnel connections, keyed by the client ip only. Defaults to false.
+    pub count_tunnels: Option<bool>
+}
+
+impl RateLimit {
+    pub fn capacity(&self) -> f64 {
+        self.burst.unwrap_or(self.requests).max(1) as f64
+    }
+    /// Number of tokens added to the bucket per second
+    pub fn refill_rate(&self) -> f64 {
+        self.requests.max(1) as f64 / self.period_seconds.unwrap_or(1).max(1) as f64
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub enum CompressionEncoding {
+    Gzip,
+    Brotli,
+    Zstd
+}
+
+impl CompressionEncoding {
+    /// The name used in the Accept-Encoding and Content-Encoding headers
+    pub fn token(&self) -> &'static str {
+        match self {
+            CompressionEncoding::Gzip => "gzip",
+            CompressionEncoding::Brotli => "br",
+            CompressionEncoding::Zstd => "zstd"
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub struct CompressionConfig {
+    /// Encodings in order of preference. Defaults to Zstd, Brotli and Gzip.
+    pub encodings: Option<Vec<CompressionEncoding>>,
+    /// Content types to compress, where "text/*" matches all text types.
+    /// Defaults to text/*, application/json, application/javascript, application/xml, application/wasm and image/svg+xml.
+    pub mime_types: Option<Vec<String>>,
+    /// Responses that are known to be smaller than this are sent as is. Defaults to 1024.
+    pub min_size_bytes: Option<u64>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub struct CacheConfig {
+    /// Size of all responses from this site that are kept in memory. Defaults to 64.
+    pub max_memory_mb: Option<u64>,
+    /// Responses larger than this are not cached. Responses without a content-length are read up to this size before giving up on caching them. Defaults to 10.
+    pub max_entry_size_mb: Option<u64>,
+    /// Directory where cached responses are also written, so that they survive restarts. Disabled by default.
+    pub disk_dir: Option<String>,
+    /// Size of all responses from this site that are kept on disk. Defaults to 512.
+    pub max_disk_mb: Option<u64>,
+    /// Serves cached responses, even when stale, if the site cannot be reached or responds with a server error. Defaults to true.
+    pub serve_stale_on_error: Option<bool>
+}
+
+impl CacheConfig {
+    pub fn max_memory_bytes(&self) -> u64 {
+        self.max_memory_mb.unwrap_or(64) * 1024 * 1024
+    }
+    pub fn max_entry_size_bytes(&self) -> u64 {
+        self.max_entry_size_mb.unwrap_or(10) * 1024 * 1024
+    }
+    pub fn max_disk_bytes(&self) -> u64 {
+        self.max_disk_mb.unwrap_or(512) * 1024 * 1024
+    }
+}
+
+/// Paths of the templates used for error responses created by odd-box. Relative paths are resolved from the directory of the configuration file.
+/// $status_code, $status_text, $message and $host are replaced with details about the error.
+/// Clients that accept neither html nor json get a plain text response.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub struct ErrorPagesConfig {
+    /// Template for clients that accept text/html
+    pub html: Option<String>,
+    /// Template for clients that accept application/json
+    pub json: Option<String>
+}
+
+/// Strict-Transport-Security header settings.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub struct HstsConfig {
+    /// Defaults to 31536000 (one year)
+    pub max_age_seconds: Option<u64>,
+    /// Defaults to false
+    pub include_subdomains: Option<bool>
+}
+
+impl HstsConfig {
+    pub fn header_value(&self) -> String {
+        let max_age = self.max_age_seconds.unwrap_or(31536000);
+        if self.include_subdomains.unwrap_or_default() {
+            format!("max-age={max_age}; includeSubDomains")
+        } else {
+            format!("max-age={max_age}")
+        }
+    }
+}
+
+/// Sets, appends or removes a header on requests or responses.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub struct HeaderRule {
+    pub name : String,
+    pub action : HeaderAction,
+    /// Required for Set and Append. Supports the placeholders $client_ip, $host, $site and $scheme.
+    pub value : Option<String>
+}
+
+/// Values for the placeholders that can be used in header rules.
+pub struct HeaderRuleContext {
+    pub client_ip : IpAddr,
+    /// The host name that the client requested
+    pub host : String,
+    /// The host_name of the site handling the request
+    pub site : String,
+    pub scheme : &'static str
+}
+
+impl HeaderRule {
+    pub fn resolve_value(&self, context: &HeaderRuleContext) -> Option<String> {
+        self.value.as_ref().map(|v| v
+            .replace("$client_ip", &context.client_ip.to_string())
+            .replace("$host", &context.host)
+            .replace("$site", &context.site)
+            .replace("$scheme", context.scheme))
+    }
+    pub fn apply(&self, headers: &mut hyper::HeaderMap, context: &HeaderRuleContext) {
+        // names are validated when the configuration is loaded
+        let name = match hyper::header::HeaderName::from_bytes(self.name.as_bytes()) {
+            Ok(n) => n,
+            Err(_) => return
+        };
+        if self.action == HeaderAction::Remove {
+            headers.remove(&name);
+            return
+        }
+        let value = match self.resolve_value(context).map(|v| hyper::header::HeaderValue::from_str(&v)) {
+            Some(Ok(v)) => v,
+            _ => {
+                tracing::debug!("Skipping the header rule for '{}' as its value is missing or invalid", self.name);
+                return
+            }
+        };
+        if self.action == HeaderAction::Append {
+            headers.append(name, value);
+        } else {
+            headers.insert(name, value);
+        }
+    }
+}
+
+pub fn apply_header_rules(rules: &[HeaderRule], headers: &mut hyper::HeaderMap, context: &HeaderRuleContext) {
+    for rule in rules {
+        rule.apply(headers, context)
+    }
+}
+
+/// Used for sites that do not configure response_headers
+pub fn default_response_header_rules() -> Vec<HeaderRule> {
+    vec![HeaderRule {
+        name: "odd-box".into(),
+        action: HeaderAction::Set,
+        value: Some("YEAH BABY YEAH".into())
+    }]
+}
+
+/// Rewrites the path of matching requests or redirects them, much like the nginx rewrite directive.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub struct RewriteRule {
+    /// Regular expression matched against the path of the request, such as "^/blog/(.*)$"
+    pub pattern : String,
+    /// Replaces the whole path when the pattern matches. Capture groups can be used with $1 or ${name}.
+    /// The query string of the request is kept unless the replacement has one.
+    pub replacement : String,
+    /// Responds with a redirect to the replacement rather than rewriting the request. Must be 301, 302, 307 or 308.
+    /// The replacement can be a full url when redirecting.
+    pub redirect : Option<u16>
+}
+
+/// Sends all requests for a host name to another url.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub struct RedirectSiteConfig {
+    pub host_name : String,
+    /// If you wish to redirect requests for any subdomain under the 'host_name'
+    pub capture_subdomains : Option<bool>,
+    /// The url to redirect to, such as "https://new.localtest.me"
+    pub redirect_to : String,
+    /// Defaults to 301. Must be 301, 302, 307 or 308.
+    pub status_code : Option<u16>,
+    /// Appends the path and query of the request to redirect_to. Defaults to true.
+    pub keep_path : Option<bool>
+}
+
+/// Serves the files in a directory directly from odd-box, without running a process for it.
+/// Static sites are always handled by the terminating proxy.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub struct StaticSiteConfig {
+    pub host_name : String,
+    /// If you wish to serve requests for any subdomain under the 'host_name'
+    pub capture_subdomains : Option<bool>,
+    /// Directory to serve files from. $cfg_dir and ~ can be used, relative paths are resolved from the directory of the configuration file.
+    pub dir : String,
+    /// Files to look for when a directory is requested. Defaults to index.html.
+    pub index_files : Option<Vec<String>>,
+    /// Lists the contents of directories without an index file. Defaults to false.
+    pub directory_listing : Option<bool>,
+    /// Serves the index file of the root directory for paths that do not exist, as needed by single page applications.
+    /// Defaults to false.
+    pub spa_fallback : Option<bool>,
+    /// If you want to use lets-encrypt for generating certificates automatically for this site. Defaults to false.
+    pub enable_lets_encrypt: Option<bool>,
+    /// Redirects plain http requests to the tls port. Defaults to false.
+    pub force_https: Option<bool>,
+    /// Adds a Strict-Transport-Security header to https responses from this site.
+    pub hsts: Option<HstsConfig>,
+    /// Requires requests to be authenticated before files are served.
+    pub auth: Option<AuthConfig>,
+    /// Ip addresses or cidr ranges, such as "192.168.1.0/24", that may connect to this site. Everyone is allowed if this is not set.
+    pub allow: Option<Vec<String>>,
+    /// Ip addresses or cidr ranges that may not connect to this site. Takes precedence over allow.
+    pub deny: Option<Vec<String>>,
+    /// Limits how many requests each client can make to this site.
+    pub rate_limit: Option<RateLimit>,
+    /// Compresses responses from this site for clients that support it.
+    pub compression: Option<CompressionConfig>,
+    /// Regex based rewrites and redirects, evaluated before the file is looked up. The first matching rule is used.
+    pub rewrite_rules: Option<Vec<RewriteRule>>,
+    /// Templates for error responses from this site, such as when a file is not found.
+    /// Falls back to the global error_pages for formats that are not set here.
+    pub error_pages: Option<ErrorPagesConfig>
+}
+
+impl StaticSiteConfig {
+    pub fn index_files(&self) -> Vec<String> {
+        self.index_files.clone().unwrap_or(vec![String::from("index.html")])
+    }
+}
+
+/// What a redirect site or rewrite rule does with a request.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub enum RewriteOutcome {
+    Redirect { status_code: u16, location: String },
+    Rewrite { path_and_query: String }
+}
+
+pub const REDIRECT_STATUS_CODES : [u16;4] = [301, 302, 307, 308];
+
+lazy_static::lazy_static! {
+    static ref COMPILED_REWRITE_PATTERNS : dashmap::DashMap<String,regex::Regex> = dashmap::DashMap::new();
+}
+
+impl RewriteRule {
+    // patterns are compiled once and then reused for all requests
+    fn regex(&self) -> Result<regex::Regex, regex::Error> {
+        if let Some(regex) = COMPILED_REWRITE_PATTERNS.get(&self.pattern) {
+            return Ok(regex.clone())
+        }
+        let regex = regex::Regex::new(&self.pattern)?;
+        COMPILED_REWRITE_PATTERNS.insert(self.pattern.clone(), regex.clone());
+        Ok(regex)
+    }
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.regex().map_err(|e| anyhow::anyhow!("'{}' is not a valid regular expression: {e}", self.pattern))?;
+        if let Some(code) = self.redirect {
+            if !REDIRECT_STATUS_CODES.contains(&code) {
+                bail!("{code} is not a redirect status code. Use 301, 302, 307 or 308.")
+            }
+        }
+        Ok(())
+    }
+    /// Returns None if the pattern does not match the path.
+    pub fn evaluate(&self, path: &str, query: Option<&str>) -> Option<RewriteOutcome> {
+        let regex = self.regex().ok()?;
+        let captures = regex.captures(path)?;
+        let mut target = String::new();
+        captures.expand(&self.replacement, &mut target);
+        if let Some(query) = query {
+            if !target.contains('?') {
+                target = format!("{target}?{query}");
+            }
+        }
+        Some(match self.redirect {
+            Some(status_code) => RewriteOutcome::Redirect { status_code, location: target },
+            None => RewriteOutcome::Rewrite { path_and_query: target }
+        })
+    }
+}
+
+impl RedirectSiteConfig {
+    pub fn redirect(&self, path_and_query: &str) -> RewriteOutcome {
+        let location = if self.keep_path.unwrap_or(true) {
+            format!("{}{}", self.redirect_to.trim_end_matches('/'), path_and_query)
+        } else {
+            self.redirect_to.clone()
+        };
+        RewriteOutcome::Redirect { status_code: self.status_code.unwrap_or(301), location }
+    }
+}
+
+/// Returns true if the ip is inside the range, such as "10.0.0.0/8" or "fd00::/8". A plain ip address only matches itself.
+/// Returns None if the range is not valid.
+pub fn cidr_contains(cidr: &str, ip: IpAddr) -> Option<bool> {
+    let (network, prefix) = match cidr.trim().split_once('/') {
+        Some((network, prefix)) => (network, Some(prefix.parse::<u32>().ok()?)),
+        None => (cidr.trim(), None)
+    };
+    let network : IpAddr = network.parse().ok()?;
+    let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+    let prefix = prefix.unwrap_or(max_prefix);
+    if prefix > max_prefix {
+        return None
+    }
+    Some(match (network, ip.to_canonical()) {
+        (IpAddr::V4(network), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(network) & mask == u32::from(ip) & mask
+        },
+        (IpAddr::V6(network), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(network) & mask == u128::from(ip) & mask
+        },
+        _ => false
+    })
+}
+
+/// Deny lists take precedence over allow lists. When an allow list is set, the ip must match one of its entries.
+pub fn ip_is_allowed(allow: Option<&Vec<String>>, deny: Option<&Vec<String>>, ip: IpAddr) -> bool {
+    let matches = |list: &Vec<String>| list.iter().any(|x| cidr_contains(x, ip).unwrap_or_default());
+    if deny.is_some_and(matches) {
+        return false
+    }
+    allow.map_or(true, matches)
+}
+
+/// Finds the rule with the longest matching prefix for the given path.
+pub fn find_path_rule<'a>(rules: &'a [PathRule], path: &str) -> Option<&'a PathRule> {
+    rules.iter().filter(|r| r.matches(path)).max_by_key(|r| r.prefix().len())
 }
 
 #[derive(Debug, Hash, Clone, Serialize, Deserialize, ToSchema, JsonSchema)]
@@ -157,7 +955,51 @@ pub struct RemoteSiteConfig{
     pub forward_subdomains : Option<bool>,
     /// If you want to use lets-encrypt for generating certificates automatically for this site.
     /// Defaults to false. This feature will disable tcp tunnel mode.
-    pub enable_lets_encrypt: Option<bool>
+    pub enable_lets_encrypt: Option<bool>,
+    /// Path based routing rules, for example sending /api/* to another site.
+    /// Sites with path rules are always handled by the terminating proxy.
+    pub path_rules: Option<Vec<PathRule>>,
+    /// How requests are distributed between the backends. Defaults to RoundRobin.
+    pub load_balancing: Option<LoadBalancing>,
+    /// Name of the cookie used by the CookieHash strategy, for example JSESSIONID.
+    /// Requests without the cookie are hashed on the client ip instead.
+    pub sticky_cookie: Option<String>,
+    /// Adds Forwarded, X-Forwarded-For/-Proto/-Host/-Port and X-Real-IP headers to requests sent to this site.
+    /// Defaults to true. Only applies to requests handled by the terminating proxy.
+    pub forwarded_headers: Option<bool>,
+    /// Headers to set, append or remove on requests before they are sent to this site.
+    pub request_headers: Option<Vec<HeaderRule>>,
+    /// Headers to set, append or remove on responses from this site.
+    /// Defaults to setting the odd-box header, set this to an empty list to send responses without it.
+    pub response_headers: Option<Vec<HeaderRule>>,
+    /// Regex based rewrites and redirects, evaluated before path rules. The first matching rule is used.
+    /// Sites with rewrite rules are always handled by the terminating proxy.
+    pub rewrite_rules: Option<Vec<RewriteRule>>,
+    /// Redirects plain http requests to the tls port. Defaults to false.
+    pub force_https: Option<bool>,
+    /// Adds a Strict-Transport-Security header to https responses from this site.
+    /// Sites with hsts configured are always handled by the terminating proxy.
+    pub hsts: Option<HstsConfig>,
+    /// Requires requests to be authenticated before they are sent to this site.
+    /// Sites with auth are always handled by the terminating proxy.
+    pub auth: Option<AuthConfig>,
+    /// Ip addresses or cidr ranges, such as "192.168.1.0/24", that may connect to this site. Everyone is allowed if this is not set.
+    pub allow: Option<Vec<String>>,
+    /// Ip addresses or cidr ranges that may not connect to this site. Takes precedence over allow.
+    pub deny: Option<Vec<String>>,
+    /// Limits how many requests each client can make to this site.
+    pub rate_limit: Option<RateLimit>,
+    /// Compresses responses from this site for clients that support it.
+    /// Sites with compression are always handled by the terminating proxy.
+    pub compression: Option<CompressionConfig>,
+    /// Caches responses from this site in memory, and optionally on disk.
+    /// Sites with a cache are always handled by the terminating proxy.
+    pub cache: Option<CacheConfig>,
+    /// Templates for error responses from odd-box, such as when no backend can be reached.
+    /// Falls back to the global error_pages for formats that are not set here.
+    pub error_pages: Option<ErrorPagesConfig>,
+    /// Retries failed requests on another backend. Only used by the terminating proxy.
+    pub retry: Option<RetryPolicy>
 }
 
 impl PartialEq for RemoteSiteConfig {
@@ -166,10 +1008,86 @@ impl PartialEq for RemoteSiteConfig {
         self.backends == other.backends &&
         compare_option_bool(self.capture_subdomains, other.capture_subdomains) &&
         compare_option_bool(self.disable_tcp_tunnel_mode, other.disable_tcp_tunnel_mode) &&
-        compare_option_bool(self.forward_subdomains, other.forward_subdomains)
+        compare_option_bool(self.forward_subdomains, other.forward_subdomains) &&
+        self.path_rules == other.path_rules &&
+        self.load_balancing == other.load_balancing &&
+        self.sticky_cookie == other.sticky_cookie &&
+        self.forwarded_headers.unwrap_or(true) == other.forwarded_headers.unwrap_or(true) &&
+        self.request_headers == other.request_headers &&
+        self.response_headers == other.response_headers &&
+        self.rewrite_rules == other.rewrite_rules &&
+        compare_option_bool(self.force_https, other.force_https) &&
+        self.hsts == other.hsts &&
+        self.auth == other.auth &&
+        self.allow == other.allow &&
+        self.deny == other.deny &&
+        self.rate_limit == other.rate_limit &&
+        self.compression == other.compression &&
+        self.cache == other.cache &&
+        self.error_pages == other.error_pages &&
+        self.retry == other.retry
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub enum LoadBalancing {
+    /// Sends requests to each backend in turn
+    RoundRobin,
+    /// Sends requests to the backend with the fewest active connections
+    LeastConnections,
+    /// Like RoundRobin but each backend receives requests in proportion to its weight
+    WeightedRoundRobin,
+    /// Sends requests to a randomly selected backend
+    Random,
+    /// Sends all requests from the same client ip to the same backend
+    IpHash,
+    /// Sends all requests with the same sticky_cookie value to the same backend.
+    /// This strategy disables tcp tunnel mode.
+    CookieHash
+}
+
+/// Information about the incoming request used by load balancing strategies when selecting a backend.
+#[derive(Debug, Default, Clone)]
+pub struct LoadBalancingContext {
+    pub client_ip : Option<IpAddr>,
+    /// All cookie header values of the request
+    pub cookies : Vec<String>,
+    /// Keys of backends that may not be selected, such as the ones that a retried request already failed on
+    pub excluded_backends : Vec<String>
+}
+
+impl LoadBalancingContext {
+    pub fn from_request(client_ip: IpAddr, headers: &hyper::HeaderMap) -> Self {
+        Self {
+            client_ip: Some(client_ip),
+            cookies: headers.get_all(hyper::header::COOKIE).iter()
+                .filter_map(|x| x.to_str().ok())
+                .map(|x| x.to_string())
+                .collect(),
+            excluded_backends: vec![]
+        }
+    }
+    fn cookie(&self, name: &str) -> Option<&str> {
+        self.cookies.iter()
+            .flat_map(|x| x.split(';'))
+            .filter_map(|x| x.trim().split_once('='))
+            .find(|(k,_)| *k == name)
+            .map(|(_,v)| v)
+    }
+}
+
+/// Picks a backend using rendezvous hashing so that the same key keeps going to the same backend
+/// and only keys that mapped to a removed backend are moved when the set of backends changes.
+pub fn pick_backend_by_hash<'a>(key: &str, backends: &[&'a Backend]) -> Option<&'a Backend> {
+    use std::hash::{Hash, Hasher};
+    backends.iter().max_by_key(|b| {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        key.hash(&mut hasher);
+        b.key().hash(&mut hasher);
+        hasher.finish()
+    }).copied()
+}
+
 impl Eq for RemoteSiteConfig {}
 
 pub enum BackendFilter {
@@ -187,27 +1105,120 @@ fn filter_backend(backend: &Backend, filter: &BackendFilter) -> bool {
 
 impl RemoteSiteConfig {
 
+    /// Returns true if this site can only be served by the terminating proxy.
+    pub fn tcp_tunnel_mode_disabled(&self) -> bool {
+        self.disable_tcp_tunnel_mode.unwrap_or_default()
+        || self.path_rules.as_ref().is_some_and(|x| !x.is_empty())
+        || self.rewrite_rules.as_ref().is_some_and(|x| !x.is_empty())
+        || self.hsts.is_some()
+        || self.auth.is_some()
+        || self.compression.is_some()
+        || self.cache.is_some()
+        || self.request_headers.as_ref().is_some_and(|x| !x.is_empty())
+        || self.response_headers.as_ref().is_some_and(|x| !x.is_empty())
+        || self.load_balancing == Some(LoadBalancing::CookieHash)
+    }
+
+    // shared by the tcp tunnel and the terminating proxy so that round robin does not depend on which one handled the previous request
+    fn next_request_number(&self,state:&GlobalState) -> u64 {
+        state.target_request_counts
+            .entry(self.host_name.clone())
+            .or_insert_with(|| std::sync::atomic::AtomicU64::new(0))
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Removes the backends that are not healthy. Backends that have not been checked yet are considered healthy,
+    /// and so are backends that have not been ejected by the outlier detection.
+    /// If all backends are unhealthy we still try them rather than failing every request.
+    pub fn prefer_healthy_backends<'a>(&self, backends: Vec<&'a Backend>, is_healthy: impl Fn(&Backend) -> bool) -> Vec<&'a Backend> {
+        let healthy_backends = backends.iter().filter(|b| is_healthy(b)).copied().collect::<Vec<&Backend>>();
+        if !healthy_backends.is_empty() {
+            healthy_backends
+        } else {
+            if !backends.is_empty() {
+                tracing::warn!("All backends for {} are unhealthy, using them anyway.",self.host_name);
+            }
+            backends
+        }
+    }
+
+    pub async fn next_backend(&self,state:&GlobalState, backend_filter: BackendFilter, context: &LoadBalancingContext) -> Option<Backend> {
+
+        // half-open backends only take a limited number of trial requests, and another request may have taken
+        // the last one since we checked, in which case we pick again without it.
+        let mut context = context.clone();
+        let mut fallback = None;
+        loop {
+            match self.select_backend(state, &backend_filter, &context) {
+                Some(b) if crate::outlier_detection::start_request(state, &self.host_name, &b) => return Some(b),
+                Some(b) => {
+                    context.excluded_backends.push(b.key());
+                    fallback.get_or_insert(b);
+                },
+                // like with unhealthy backends, we would rather try it than fail the request
+                None => return fallback
+            }
+        }
+    }
 
-    pub async fn next_backend(&self,state:&GlobalState, backend_filter: BackendFilter, tunnel_mode: bool) -> Option<Backend> {
+    fn select_backend(&self,state:&GlobalState, backend_filter: &BackendFilter, context: &LoadBalancingContext) -> Option<Backend> {
             
-        let filtered_backends = self.backends.iter().filter(|x|filter_backend(x,&backend_filter))
+        let filtered_backends = self.backends.iter()
+            .filter(|x|filter_backend(x,backend_filter) && !context.excluded_backends.contains(&x.key()))
             .collect::<Vec<&crate::configuration::v2::Backend>>();
 
+        let filtered_backends = self.prefer_healthy_backends(filtered_backends, |b| {
+            crate::health_checks::is_healthy(&state.app_state.backend_health, &self.host_name, b)
+                && !crate::outlier_detection::is_ejected(state, &self.host_name, b)
+        });
+
         if filtered_backends.len() == 1 { return Some(filtered_backends[0].clone()) };
         if filtered_backends.len() == 0 { return None };
         
-        let current_req_count_for_target_host_name = if tunnel_mode {
-            state.app_state.statistics.tunnelled_tcp_connections_per_hostname
-                .get(&self.host_name).and_then(|x|Some(x.load(std::sync::atomic::Ordering::SeqCst)))
-                .unwrap_or(0)
-        } else {
-            state.app_state.statistics.terminated_http_connections_per_hostname
-            .get(&self.host_name).and_then(|x|Some(x.load(std::sync::atomic::Ordering::SeqCst)))
-            .unwrap_or(0)
+        let round_robin = |n: u64| filtered_backends.get((n % filtered_backends.len() as u64) as usize).copied();
+
+        let selected_backend = match self.load_balancing.as_ref().unwrap_or(&LoadBalancing::RoundRobin) {
+            LoadBalancing::RoundRobin => round_robin(self.next_request_number(state)),
+            LoadBalancing::WeightedRoundRobin => {
+                let total_weight = filtered_backends.iter().map(|b| b.weight() as u64).sum::<u64>();
+                if total_weight == 0 {
+                    round_robin(self.next_request_number(state))
+                } else {
+                    let mut n = self.next_request_number(state) % total_weight;
+                    filtered_backends.iter().find(|b| {
+                        let weight = b.weight() as u64;
+                        if n < weight { true } else { n -= weight; false }
+                    }).copied()
+                }
+            },
+            LoadBalancing::LeastConnections => {
+                let mut connection_counts = std::collections::HashMap::new();
+                for c in state.app_state.statistics.active_connections.iter() {
+                    if let Some(k) = &c.value().backend_key {
+                        *connection_counts.entry(k.clone()).or_insert(0usize) += 1;
+                    }
+                }
+                // start at the round robin position so that ties are spread between backends
+                let offset = self.next_request_number(state) as usize;
+                (0..filtered_backends.len())
+                    .map(|i| filtered_backends[(offset + i) % filtered_backends.len()])
+                    .min_by_key(|b| connection_counts.get(&b.key()).copied().unwrap_or_default())
+            },
+            LoadBalancing::Random => round_robin(rand::random::<u64>()),
+            LoadBalancing::IpHash => match context.client_ip {
+                Some(ip) => pick_backend_by_hash(&ip.to_string(), &filtered_backends),
+                None => round_robin(self.next_request_number(state))
+            },
+            LoadBalancing::CookieHash => {
+                let cookie_value = self.sticky_cookie.as_ref().and_then(|name| context.cookie(name));
+                match (cookie_value, context.client_ip) {
+                    (Some(v),_) => pick_backend_by_hash(v, &filtered_backends),
+                    (None,Some(ip)) => pick_backend_by_hash(&ip.to_string(), &filtered_backends),
+                    (None,None) => round_robin(self.next_request_number(state))
+                }
+            }
         };
 
-        let selected_backend = filtered_backends.get((current_req_count_for_target_host_name % (filtered_backends.len() as usize)) as usize );
-
         if let Some(b) = selected_backend{
             Some((*b).clone())
         } else {
@@ -245,7 +1256,65 @@ pub struct OddBoxV2Config {
     pub hosted_process : Option<Vec<InProcessSiteConfig>>,
     pub admin_api_port : Option<u16>,
     pub path : Option<String>,
-    pub lets_encrypt_account_email: Option<String>
+    pub lets_encrypt_account_email: Option<String>,
+    /// Records every request handled by the terminating proxy and every tcp tunnel.
+    /// Entries are written to the configured file and streamed over the admin api websocket at /ws/access_log.
+    pub access_log: Option<AccessLogConfig>,
+    /// Ip addresses of proxies in front of odd-box, such as a load balancer or a CDN.
+    /// Forwarded and X-Forwarded-* headers are only kept and appended to for requests from these addresses,
+    /// for all other clients they are replaced.
+    pub trusted_proxies: Option<Vec<String>>,
+    /// Ip addresses or cidr ranges, such as "192.168.1.0/24", that may connect to odd-box. Everyone is allowed if this is not set.
+    /// Sites can further restrict access with their own allow and deny lists.
+    pub allow: Option<Vec<String>>,
+    /// Ip addresses or cidr ranges that may not connect to odd-box. Takes precedence over allow.
+    pub deny: Option<Vec<String>>,
+    /// Limits how many requests each client can make, across all sites.
+    /// Sites can have their own limits as well, in which case both apply.
+    pub rate_limit: Option<RateLimit>,
+    /// Templates for error responses from odd-box, such as when a site cannot be reached or no site matches the host name.
+    pub error_pages: Option<ErrorPagesConfig>,
+    /// How long to wait for backends, for remote targets as well as hosted processes. Backends can override these.
+    pub timeouts: Option<UpstreamTimeouts>,
+    /// Expects every connection to the http and tls ports to start with a PROXY protocol (v1 or v2) header,
+    /// such as when odd-box is behind a load balancer. Connections without a valid header are closed.
+    /// Defaults to false.
+    pub accept_proxy_protocol: Option<bool>,
+    /// Sites that only redirect requests to another url
+    pub redirect_site: Option<Vec<RedirectSiteConfig>>,
+    /// Sites that serve the files in a directory
+    pub static_site: Option<Vec<StaticSiteConfig>>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub enum AccessLogFormat {
+    /// The NCSA common log format
+    Common,
+    /// The NCSA combined log format, which adds the referer and user agent to the common format
+    Combined,
+    /// One json object per line, including upstream, latency and protocol details
+    Json
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Eq, PartialEq, Hash, JsonSchema)]
+pub struct AccessLogConfig {
+    /// Defaults to Combined. Entries streamed over the admin api are always json.
+    pub format : Option<AccessLogFormat>,
+    /// Path of the file to write entries to. When not set, entries are only streamed over the admin api.
+    pub file : Option<String>,
+    /// The file is rotated once it grows beyond this size. Defaults to 10.
+    pub max_file_size_mb : Option<u64>,
+    /// Number of rotated files to keep. Defaults to 5.
+    pub max_files : Option<u32>,
+}
+
+impl AccessLogConfig {
+    pub fn max_file_size_bytes(&self) -> u64 {
+        self.max_file_size_mb.unwrap_or(10).max(1) * 1024 * 1024
+    }
+    pub fn max_files(&self) -> u32 {
+        self.max_files.unwrap_or(5)
+    }
 }
 
 impl crate::configuration::OddBoxConfiguration<OddBoxV2Config> for OddBoxV2Config {
@@ -333,6 +1402,38 @@ impl crate::configuration::OddBoxConfiguration<OddBoxV2Config> for OddBoxV2Confi
             formatted_toml.push(format!("lets_encrypt_account_email = \"{email}\""));
         }
 
+        if let Some(access_log) = &self.access_log {
+            formatted_toml.push(format!("access_log = {}", to_inline_toml(access_log)?));
+        }
+
+        if let Some(trusted_proxies) = &self.trusted_proxies {
+            formatted_toml.push(format!("trusted_proxies = {}", to_inline_toml(trusted_proxies)?));
+        }
+
+        if let Some(allow) = &self.allow {
+            formatted_toml.push(format!("allow = {}", to_inline_toml(allow)?));
+        }
+
+        if let Some(deny) = &self.deny {
+            formatted_toml.push(format!("deny = {}", to_inline_toml(deny)?));
+        }
+
+        if let Some(rate_limit) = &self.rate_limit {
+            formatted_toml.push(format!("rate_limit = {}", to_inline_toml(rate_limit)?));
+        }
+
+        if let Some(error_pages) = &self.error_pages {
+            formatted_toml.push(format!("error_pages = {}", to_inline_toml(error_pages)?));
+        }
+
+        if let Some(timeouts) = &self.timeouts {
+            formatted_toml.push(format!("timeouts = {}", to_inline_toml(timeouts)?));
+        }
+
+        if let Some(true) = self.accept_proxy_protocol {
+            formatted_toml.push("accept_proxy_protocol = true".to_string());
+        }
+
         if &self.env_vars.len() > &0 {
             formatted_toml.push("env_vars = [".to_string());
             for env_var in &self.env_vars {
@@ -367,10 +1468,78 @@ impl crate::configuration::OddBoxConfiguration<OddBoxV2Config> for OddBoxV2Confi
                     formatted_toml.push(format!("enable_lets_encrypt = {}", true));
                 }
 
+                if let Some(rules) = &site.path_rules {
+                    formatted_toml.push(format!("path_rules = {}", to_inline_toml(rules)?));
+                }
+
+                if let Some(lb) = &site.load_balancing {
+                    formatted_toml.push(format!("load_balancing = \"{:?}\"", lb));
+                }
+
+                if let Some(cookie) = &site.sticky_cookie {
+                    formatted_toml.push(format!("sticky_cookie = {:?}", cookie));
+                }
+
+                if let Some(forwarded_headers) = site.forwarded_headers {
+                    formatted_toml.push(format!("forwarded_headers = {forwarded_headers}"));
+                }
+
+                if let Some(rules) = &site.request_headers {
+                    formatted_toml.push(format!("request_headers = {}", to_inline_toml(rules)?));
+                }
+
+                if let Some(rules) = &site.response_headers {
+                    formatted_toml.push(format!("response_headers = {}", to_inline_toml(rules)?));
+                }
+
+                if let Some(rules) = &site.rewrite_rules {
+                    formatted_toml.push(format!("rewrite_rules = {}", to_inline_toml(rules)?));
+                }
+
+                if let Some(true) = site.force_https {
+                    formatted_toml.push(String::from("force_https = true"));
+                }
+
+                if let Some(hsts) = &site.hsts {
+                    formatted_toml.push(format!("hsts = {}", to_inline_toml(hsts)?));
+                }
+
+                if let Some(auth) = &site.auth {
+                    formatted_toml.push(format!("auth = {}", to_inline_toml(auth)?));
+                }
+
+                if let Some(allow) = &site.allow {
+                    formatted_toml.push(format!("allow = {}", to_inline_toml(allow)?));
+                }
+
+                if let Some(deny) = &site.deny {
+                    formatted_toml.push(format!("deny = {}", to_inline_toml(deny)?));
+                }
+
+                if let Some(rate_limit) = &site.rate_limit {
+                    formatted_toml.push(format!("rate_limit = {}", to_inline_toml(rate_limit)?));
+                }
+
+                if let Some(compression) = &site.compression {
+                    formatted_toml.push(format!("compression = {}", to_inline_toml(compression)?));
+                }
+
+                if let Some(cache) = &site.cache {
+                    formatted_toml.push(format!("cache = {}", to_inline_toml(cache)?));
+                }
+
+                if let Some(error_pages) = &site.error_pages {
+                    formatted_toml.push(format!("error_pages = {}", to_inline_toml(error_pages)?));
+                }
+
+                if let Some(retry) = &site.retry {
+                    formatted_toml.push(format!("retry = {}", to_inline_toml(retry)?));
+                }
+
 
                 formatted_toml.push("backends = [".to_string());
 
-                let backend_strings = site.backends.iter().map(|b| {
+                let backend_strings = site.backends.iter().map(|b| -> anyhow::Result<String> {
                     let https = if let Some(true) = b.https { format!("https = true, ") } else { format!("") };
                     
                     let hints = if let Some(hints) = &b.hints {
@@ -379,9 +1548,31 @@ impl crate::configuration::OddBoxConfiguration<OddBoxV2Config> for OddBoxV2Confi
                         String::new()
                     };
                     
-                    format!("\t{{ {}address=\"{}\", port={}{hints}}}",https,b.address, b.port)}
+                    let weight = if let Some(w) = b.weight { format!(", weight={w}") } else { String::new() };
 
-                ).collect::<Vec<String>>();
+                    let health_check = if let Some(hc) = &b.health_check {
+                        format!(", health_check = {}",to_inline_toml(hc)?)
+                    } else {
+                        String::new()
+                    };
+
+                    let proxy_protocol = if let Some(pp) = &b.proxy_protocol { format!(", proxy_protocol=\"{pp:?}\"") } else { String::new() };
+
+                    let timeouts = if let Some(t) = &b.timeouts {
+                        format!(", timeouts = {}",to_inline_toml(t)?)
+                    } else {
+                        String::new()
+                    };
+
+                    let outlier_detection = if let Some(od) = &b.outlier_detection {
+                        format!(", outlier_detection = {}",to_inline_toml(od)?)
+                    } else {
+                        String::new()
+                    };
+                    
+                    Ok(format!("\t{{ {}address=\"{}\", port={}{weight}{hints}{health_check}{proxy_protocol}{timeouts}{outlier_detection}}}",https,b.address, b.port))}
+
+                ).collect::<anyhow::Result<Vec<String>>>()?;
 
                 formatted_toml.push(backend_strings.join(",\n"));
 
@@ -390,6 +1581,73 @@ impl crate::configuration::OddBoxConfiguration<OddBoxV2Config> for OddBoxV2Confi
             }
         }
 
+        if let Some(redirect_sites) = &self.redirect_site {
+            for site in redirect_sites {
+                formatted_toml.push("\n[[redirect_site]]".to_string());
+                formatted_toml.push(format!("host_name = {:?}", site.host_name));
+                formatted_toml.push(format!("redirect_to = {:?}", site.redirect_to));
+                if let Some(true) = site.capture_subdomains {
+                    formatted_toml.push(String::from("capture_subdomains = true"));
+                }
+                if let Some(status_code) = site.status_code {
+                    formatted_toml.push(format!("status_code = {status_code}"));
+                }
+                if let Some(keep_path) = site.keep_path {
+                    formatted_toml.push(format!("keep_path = {keep_path}"));
+                }
+            }
+        }
+
+        if let Some(static_sites) = &self.static_site {
+            for site in static_sites {
+                formatted_toml.push("\n[[static_site]]".to_string());
+                formatted_toml.push(format!("host_name = {:?}", site.host_name));
+                formatted_toml.push(format!("dir = {:?}", site.dir));
+                if let Some(true) = site.capture_subdomains {
+                    formatted_toml.push(String::from("capture_subdomains = true"));
+                }
+                if let Some(index_files) = &site.index_files {
+                    formatted_toml.push(format!("index_files = {}", to_inline_toml(index_files)?));
+                }
+                if let Some(directory_listing) = site.directory_listing {
+                    formatted_toml.push(format!("directory_listing = {directory_listing}"));
+                }
+                if let Some(spa_fallback) = site.spa_fallback {
+                    formatted_toml.push(format!("spa_fallback = {spa_fallback}"));
+                }
+                if let Some(true) = site.enable_lets_encrypt {
+                    formatted_toml.push(String::from("enable_lets_encrypt = true"));
+                }
+                if let Some(force_https) = site.force_https {
+                    formatted_toml.push(format!("force_https = {force_https}"));
+                }
+                if let Some(hsts) = &site.hsts {
+                    formatted_toml.push(format!("hsts = {}", to_inline_toml(hsts)?));
+                }
+                if let Some(auth) = &site.auth {
+                    formatted_toml.push(format!("auth = {}", to_inline_toml(auth)?));
+                }
+                if let Some(allow) = &site.allow {
+                    formatted_toml.push(format!("allow = {}", to_inline_toml(allow)?));
+                }
+                if let Some(deny) = &site.deny {
+                    formatted_toml.push(format!("deny = {}", to_inline_toml(deny)?));
+                }
+                if let Some(rate_limit) = &site.rate_limit {
+                    formatted_toml.push(format!("rate_limit = {}", to_inline_toml(rate_limit)?));
+                }
+                if let Some(compression) = &site.compression {
+                    formatted_toml.push(format!("compression = {}", to_inline_toml(compression)?));
+                }
+                if let Some(rules) = &site.rewrite_rules {
+                    formatted_toml.push(format!("rewrite_rules = {}", to_inline_toml(rules)?));
+                }
+                if let Some(error_pages) = &site.error_pages {
+                    formatted_toml.push(format!("error_pages = {}", to_inline_toml(error_pages)?));
+                }
+            }
+        }
+
         if let Some(processes) = &self.hosted_process {
             for process in processes {
                 formatted_toml.push("\n[[hosted_process]]".to_string());
@@ -433,6 +1691,98 @@ impl crate::configuration::OddBoxConfiguration<OddBoxV2Config> for OddBoxV2Confi
                     formatted_toml.push(format!("capture_subdomains = {}", "true"));
                 }
 
+                if let Some(rules) = &process.path_rules {
+                    formatted_toml.push(format!("path_rules = {}", to_inline_toml(rules)?));
+                }
+
+                if let Some(check) = &process.readiness_check {
+                    formatted_toml.push(format!("readiness_check = {}", to_inline_toml(check)?));
+                }
+
+                if let Some(check) = &process.liveness_check {
+                    formatted_toml.push(format!("liveness_check = {}", to_inline_toml(check)?));
+                }
+
+                if let Some(policy) = &process.restart_policy {
+                    formatted_toml.push(format!("restart_policy = {}", to_inline_toml(policy)?));
+                }
+
+                if let Some(max_restarts) = process.max_restarts {
+                    formatted_toml.push(format!("max_restarts = {}", max_restarts));
+                }
+
+                if let Some(backoff) = &process.restart_backoff {
+                    formatted_toml.push(format!("restart_backoff = {}", to_inline_toml(backoff)?));
+                }
+
+                if let Some(signal) = &process.stop_signal {
+                    formatted_toml.push(format!("stop_signal = {}", to_inline_toml(signal)?));
+                }
+
+                if let Some(timeout) = process.stop_timeout_seconds {
+                    formatted_toml.push(format!("stop_timeout_seconds = {}", timeout));
+                }
+
+                if let Some(dependencies) = &process.depends_on {
+                    formatted_toml.push(format!("depends_on = {}", to_inline_toml(dependencies)?));
+                }
+
+                if let Some(true) = process.stop_with_dependencies {
+                    formatted_toml.push(format!("stop_with_dependencies = {}", "true"));
+                }
+
+                if let Some(proxy_protocol) = &process.proxy_protocol {
+                    formatted_toml.push(format!("proxy_protocol = \"{proxy_protocol:?}\""));
+                }
+
+                if let Some(forwarded_headers) = process.forwarded_headers {
+                    formatted_toml.push(format!("forwarded_headers = {forwarded_headers}"));
+                }
+
+                if let Some(rules) = &process.request_headers {
+                    formatted_toml.push(format!("request_headers = {}", to_inline_toml(rules)?));
+                }
+
+                if let Some(rules) = &process.response_headers {
+                    formatted_toml.push(format!("response_headers = {}", to_inline_toml(rules)?));
+                }
+
+                if let Some(rules) = &process.rewrite_rules {
+                    formatted_toml.push(format!("rewrite_rules = {}", to_inline_toml(rules)?));
+                }
+
+                if let Some(true) = process.force_https {
+                    formatted_toml.push(String::from("force_https = true"));
+                }
+
+                if let Some(hsts) = &process.hsts {
+                    formatted_toml.push(format!("hsts = {}", to_inline_toml(hsts)?));
+                }
+
+                if let Some(auth) = &process.auth {
+                    formatted_toml.push(format!("auth = {}", to_inline_toml(auth)?));
+                }
+
+                if let Some(allow) = &process.allow {
+                    formatted_toml.push(format!("allow = {}", to_inline_toml(allow)?));
+                }
+
+                if let Some(deny) = &process.deny {
+                    formatted_toml.push(format!("deny = {}", to_inline_toml(deny)?));
+                }
+
+                if let Some(rate_limit) = &process.rate_limit {
+                    formatted_toml.push(format!("rate_limit = {}", to_inline_toml(rate_limit)?));
+                }
+
+                if let Some(compression) = &process.compression {
+                    formatted_toml.push(format!("compression = {}", to_inline_toml(compression)?));
+                }
+
+                if let Some(error_pages) = &process.error_pages {
+                    formatted_toml.push(format!("error_pages = {}", to_inline_toml(error_pages)?));
+                }
+
                 if let Some(evars) = &process.env_vars {
                     formatted_toml.push("env_vars = [".to_string());
                     for env_var in evars {
@@ -452,6 +1802,16 @@ impl crate::configuration::OddBoxConfiguration<OddBoxV2Config> for OddBoxV2Confi
     fn example() -> OddBoxV2Config {
         OddBoxV2Config {
             lets_encrypt_account_email: None,
+            access_log: None,
+            trusted_proxies: None,
+            allow: None,
+            deny: None,
+            rate_limit: None,
+            accept_proxy_protocol: None,
+            redirect_site: None,
+            error_pages: None,
+            timeouts: None,
+            static_site: None,
             path: None,
             admin_api_port: None,
             version: super::OddBoxConfigVersion::V2,
@@ -468,6 +1828,29 @@ impl crate::configuration::OddBoxConfiguration<OddBoxV2Config> for OddBoxV2Confi
             port_range_start: 4200,
             hosted_process: Some(vec![
                 InProcessSiteConfig {
+                    readiness_check: None,
+                    liveness_check: None,
+                    restart_policy: None,
+                    max_restarts: None,
+                    restart_backoff: None,
+                    stop_signal: None,
+                    stop_timeout_seconds: None,
+                    depends_on: None,
+                    stop_with_dependencies: None,
+                    proxy_protocol: None,
+                    forwarded_headers: None,
+                    request_headers: None,
+                    response_headers: None,
+                    rewrite_rules: None,
+                    force_https: None,
+                    hsts: None,
+                    auth: None,
+                    allow: None,
+                    deny: None,
+                    rate_limit: None,
+                    compression: None,
+                    error_pages: None,
+                    path_rules: None,
                     enable_lets_encrypt: Some(false),
                     proc_id: ProcId::new(),
                     active_port: None,
@@ -493,6 +1876,23 @@ impl crate::configuration::OddBoxConfiguration<OddBoxV2Config> for OddBoxV2Confi
             ]),
             remote_target: Some(vec![
                 RemoteSiteConfig { 
+                    load_balancing: None,
+                    sticky_cookie: None,
+                    forwarded_headers: None,
+                    request_headers: None,
+                    response_headers: None,
+                    rewrite_rules: None,
+                    force_https: None,
+                    hsts: None,
+                    auth: None,
+                    allow: None,
+                    deny: None,
+                    rate_limit: None,
+                    compression: None,
+                    cache: None,
+                    error_pages: None,
+                    retry: None,
+                    path_rules: None,
                     enable_lets_encrypt: Some(false),
                     forward_subdomains: None,
                     host_name: "lobsters.local".into(), 
@@ -501,13 +1901,35 @@ impl crate::configuration::OddBoxConfiguration<OddBoxV2Config> for OddBoxV2Confi
                             hints: None, 
                             address: "lobste.rs".into(), 
                             port: 443, 
-                            https: Some(true)
+                            https: Some(true),
+                            health_check: None,
+                            weight: None,
+                            proxy_protocol: None,
+                            timeouts: None,
+                            outlier_detection: None
                         }
                     ], 
                     capture_subdomains: Some(false), 
                     disable_tcp_tunnel_mode: Some(false)
                 },
                 RemoteSiteConfig { 
+                    load_balancing: None,
+                    sticky_cookie: None,
+                    forwarded_headers: None,
+                    request_headers: None,
+                    response_headers: None,
+                    rewrite_rules: None,
+                    force_https: None,
+                    hsts: None,
+                    auth: None,
+                    allow: None,
+                    deny: None,
+                    rate_limit: None,
+                    compression: None,
+                    cache: None,
+                    error_pages: None,
+                    retry: None,
+                    path_rules: None,
                     enable_lets_encrypt: Some(false),
                     forward_subdomains: Some(true),                    
                     host_name: "google.local".into(), 
@@ -516,7 +1938,12 @@ impl crate::configuration::OddBoxConfiguration<OddBoxV2Config> for OddBoxV2Confi
                             hints: None, 
                             address: "google.com".into(), 
                             port: 443, 
-                            https: Some(true)
+                            https: Some(true),
+                            health_check: None,
+                            weight: None,
+                            proxy_protocol: None,
+                            timeouts: None,
+                            outlier_detection: None
                         }
                     ], 
                     capture_subdomains: Some(false), 
@@ -531,6 +1958,11 @@ impl crate::configuration::OddBoxConfiguration<OddBoxV2Config> for OddBoxV2Confi
 
  }
 
+// renders nested settings as inline toml so that each site can keep its flat format in the file
+fn to_inline_toml<T: Serialize>(value: &T) -> anyhow::Result<String> {
+    Ok(toml::Value::try_from(value)?.to_string())
+}
+
 fn default_log_level() -> Option<LogLevel> {
     Some(LogLevel::Info)
 }
@@ -583,6 +2015,16 @@ impl TryFrom<super::v1::OddBoxV1Config> for super::v2::OddBoxV2Config{
     fn try_from(old_config: super::v1::OddBoxV1Config) -> Result<Self, Self::Error> {
         let new_config = super::v2::OddBoxV2Config {
             lets_encrypt_account_email: None,
+            access_log: None,
+            trusted_proxies: None,
+            allow: None,
+            deny: None,
+            rate_limit: None,
+            accept_proxy_protocol: None,
+            redirect_site: None,
+            error_pages: None,
+            timeouts: None,
+            static_site: None,
             path: None,
             version: super::OddBoxConfigVersion::V2,
             admin_api_port: None,
@@ -596,6 +2038,29 @@ impl TryFrom<super::v1::OddBoxV1Config> for super::v2::OddBoxV2Config{
             port_range_start: old_config.port_range_start,
             hosted_process: Some(old_config.hosted_process.unwrap_or_default().into_iter().map(|x|{
                 super::v2::InProcessSiteConfig {
+                    readiness_check: None,
+                    liveness_check: None,
+                    restart_policy: None,
+                    max_restarts: None,
+                    restart_backoff: None,
+                    stop_signal: None,
+                    stop_timeout_seconds: None,
+                    depends_on: None,
+                    stop_with_dependencies: None,
+                    proxy_protocol: None,
+                    forwarded_headers: None,
+                    request_headers: None,
+                    response_headers: None,
+                    rewrite_rules: None,
+                    force_https: None,
+                    hsts: None,
+                    auth: None,
+                    allow: None,
+                    deny: None,
+                    rate_limit: None,
+                    compression: None,
+                    error_pages: None,
+                    path_rules: None,
                     enable_lets_encrypt: Some(false),
                     exclude_from_start_all: None,
                     proc_id: ProcId::new(),
@@ -633,6 +2098,23 @@ impl TryFrom<super::v1::OddBoxV1Config> for super::v2::OddBoxV2Config{
             }).collect()),
             remote_target: Some(old_config.remote_target.unwrap_or_default().iter().map(|x|{
                 super::v2::RemoteSiteConfig {
+                    load_balancing: None,
+                    sticky_cookie: None,
+                    forwarded_headers: None,
+                    request_headers: None,
+                    response_headers: None,
+                    rewrite_rules: None,
+                    force_https: None,
+                    hsts: None,
+                    auth: None,
+                    allow: None,
+                    deny: None,
+                    rate_limit: None,
+                    compression: None,
+                    cache: None,
+                    error_pages: None,
+                    retry: None,
+                    path_rules: None,
                     enable_lets_encrypt: Some(false),
                     disable_tcp_tunnel_mode: x.disable_tcp_tunnel_mode,
                     capture_subdomains: x.capture_subdomains,
@@ -648,7 +2130,12 @@ impl TryFrom<super::v1::OddBoxV1Config> for super::v2::OddBoxV2Config{
                             port: if let Some(p) = x.port {p} else {
                                 if x.https.unwrap_or_default() { 443 } else { 80 }
                             },
-                            https: x.https
+                            https: x.https,
+                            health_check: None,
+                            weight: None,
+                            proxy_protocol: None,
+                            timeouts: None,
+                            outlier_detection: None
                         }
                     ],
                     host_name: x.host_name.clone(),                    
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use dashmap::DashMap;
+
+use crate::configuration::v2::{Backend, HealthCheck, HealthCheckKind};
+use crate::global_state::GlobalState;
+use crate::types::backend_health::BackendHealth;
+use crate::types::proc_info::BgTaskInfo;
+
+lazy_static::lazy_static! {
+    // health checks only care about the backend being reachable, so we do not validate certificates here.
+    static ref HEALTH_CHECK_CLIENT : reqwest::Client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("must be able to create the health check client");
+}
+
+/// Succeeds if a tcp connection can be established within the timeout.
+pub async fn tcp_probe(address:&str, port:u16, timeout:Duration) -> anyhow::Result<()> {
+    match tokio::time::timeout(timeout, tokio::net::TcpStream::connect((address,port))).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => anyhow::bail!("connection failed: {e}"),
+        Err(_) => anyhow::bail!("connection timed out after {}s",timeout.as_secs())
+    }
+}
+
+/// Succeeds if a GET request to the url returns a 2xx or 3xx status within the timeout.
+pub async fn http_probe(url:&str, timeout:Duration) -> anyhow::Result<()> {
+    let response = HEALTH_CHECK_CLIENT.get(url).timeout(timeout).send().await?;
+    let status = response.status();
+    if status.is_success() || status.is_redirection() {
+        Ok(())
+    } else {
+        anyhow::bail!("unexpected status code: {status}")
+    }
+}
+
+pub fn probe_url(https:bool, address:&str, port:u16, path:Option<&str>) -> String {
+    let scheme = if https { "https" } else { "http" };
+    let path = path.unwrap_or("/");
+    if path.starts_with('/') {
+        format!("{scheme}://{address}:{port}{path}")
+    } else {
+        format!("{scheme}://{address}:{port}/{path}")
+    }
+}
+
+pub async fn check_backend(backend:&Backend, check:&HealthCheck) -> anyhow::Result<()> {
+    match check.kind {
+        HealthCheckKind::Tcp => tcp_probe(&backend.address, backend.port, check.timeout()).await,
+        HealthCheckKind::Http => {
+            let url = probe_url(backend.https.unwrap_or_default(), &backend.address, backend.port, check.path.as_deref());
+            http_probe(&url, check.timeout()).await
+        }
+    }
+}
+
+/// Identifies a backend of a specific site in the health state and the circuit breakers of the outlier detection,
+/// since two sites may use the same backend with different settings.
+pub fn health_key(host_name:&str, backend:&Backend) -> String {
+    format!("{host_name}/{}",backend.key())
+}
+
+/// Backends without an entry have not been checked yet and are considered healthy.
+pub fn is_healthy(health:&DashMap<String,BackendHealth>, host_name:&str, backend:&Backend) -> bool {
+    health.get(&health_key(host_name,backend)).map(|h| h.healthy).unwrap_or(true)
+}
+
+fn record_result(health:&DashMap<String,BackendHealth>, key:&str, check:&HealthCheck, result:anyhow::Result<()>) {
+    
+    let mut health = health.entry(key.to_owned()).or_default();
+    
+    health.last_check = Some(chrono::Local::now());
+
+    match result {
+        Ok(()) => {
+            health.consecutive_failures = 0;
+            health.consecutive_successes = health.consecutive_successes.saturating_add(1);
+            health.last_error = None;
+            if !health.healthy && health.consecutive_successes >= check.healthy_threshold() {
+                health.healthy = true;
+                tracing::info!("Backend {key} is healthy again");
+            }
+        },
+        Err(e) => {
+            tracing::debug!("Health check failed for backend {key}: {e:?}");
+            health.consecutive_successes = 0;
+            health.consecutive_failures = health.consecutive_failures.saturating_add(1);
+            health.last_error = Some(e.to_string());
+            if health.healthy && health.consecutive_failures >= check.unhealthy_threshold() {
+                health.healthy = false;
+                tracing::warn!("Backend {key} is unhealthy and will not receive any traffic until it recovers: {e}");
+            }
+        }
+    }
+}
+
+pub async fn bg_worker_for_backend_health_checks(state: Arc<GlobalState>) {
+    let liveness_token = Arc::new(true);
+    
+    let mut next_check_at : HashMap<String,Instant> = HashMap::new();
+
+    // NOTE: We re-read the configuration on each iteration since sites can be added and changed at runtime.
+    loop {
+        
+        let cfg_guard = state.config.read().await;
+        let mut monitored_backends : HashMap<String,(Backend,HealthCheck)> = HashMap::new();
+        for site in cfg_guard.remote_target.iter().flatten() {
+            for backend in site.backends.iter() {
+                if let Some(check) = &backend.health_check {
+                    monitored_backends.entry(health_key(&site.host_name,backend)).or_insert_with(||(backend.clone(),check.clone()));
+                }
+            }
+        }
+        drop(cfg_guard);
+
+        // backends that are no longer monitored should not stay marked as unhealthy
+        state.app_state.backend_health.retain(|k,_| monitored_backends.contains_key(k));
+        next_check_at.retain(|k,_| monitored_backends.contains_key(k));
+
+        let now = Instant::now();
+        for (key,(backend,check)) in monitored_backends.iter() {
+            if next_check_at.get(key).is_some_and(|t| *t > now) {
+                continue;
+            }
+            next_check_at.insert(key.clone(), now + check.interval());
+            let health = state.app_state.backend_health.clone();
+            let key = key.clone();
+            let backend = backend.clone();
+            let check = check.clone();
+            // checks run in their own tasks so that a slow backend does not delay the others
+            tokio::spawn(async move {
+                let result = check_backend(&backend,&check).await;
+                record_result(&health,&key,&check,result);
+            });
+        }
+
+        let unhealthy_count = state.app_state.backend_health.iter().filter(|x| !x.healthy).count();
+        
+        crate::BG_WORKER_THREAD_MAP.insert("Health Checks".into(), BgTaskInfo {
+            liveness_ptr: Arc::downgrade(&liveness_token),
+            status: if monitored_backends.is_empty() {
+                "Idle. No backends have health checks configured.".to_string()
+            } else {
+                format!("Monitoring: {} - Unhealthy: {unhealthy_count}.",monitored_backends.len())
+            }
+        }); // we dont need to clean this up if we exit, there is a cleanup task that will do it.
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::configuration::OddBoxConfiguration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn backend(address:&str, port:u16) -> Backend {
+        Backend {
+            address: address.into(),
+            port,
+            https: None,
+            hints: None,
+            health_check: None,
+            weight: None,
+            proxy_protocol: None,
+            timeouts: None,
+            outlier_detection: None
+        }
+    }
+
+    fn check(kind:HealthCheckKind) -> HealthCheck {
+        HealthCheck {
+            kind,
+            path: Some("/health".into()),
+            interval_seconds: None,
+            timeout_seconds: Some(1),
+            unhealthy_threshold: Some(3),
+            healthy_threshold: Some(2)
+        }
+    }
+
+    /// Accepts a single connection and answers it with the given status line.
+    async fn respond_once_with(status_line:&'static str) -> u16 {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("should bind");
+        let port = listener.local_addr().expect("should have an address").port();
+        tokio::spawn(async move {
+            if let Ok((mut stream,_)) = listener.accept().await {
+                let mut buf = [0u8;1024];
+                _ = stream.read(&mut buf).await;
+                _ = stream.write_all(format!("{status_line}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n").as_bytes()).await;
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn backends_change_state_after_the_thresholds_are_reached() {
+
+        let health : DashMap<String,BackendHealth> = DashMap::new();
+        let check = check(HealthCheckKind::Tcp);
+        let key = "site.local/backend.local:80";
+        let is_healthy = || health.get(key).expect("should have an entry").healthy;
+
+        record_result(&health, key, &check, Ok(()));
+        assert!(is_healthy());
+
+        record_result(&health, key, &check, Err(anyhow::anyhow!("refused")));
+        record_result(&health, key, &check, Err(anyhow::anyhow!("refused")));
+        assert!(is_healthy(), "two failures are below the unhealthy threshold");
+        record_result(&health, key, &check, Err(anyhow::anyhow!("refused")));
+        assert!(!is_healthy());
+        assert_eq!(health.get(key).unwrap().last_error.as_deref(), Some("refused"));
+
+        record_result(&health, key, &check, Ok(()));
+        assert!(!is_healthy(), "one success is below the healthy threshold");
+        record_result(&health, key, &check, Err(anyhow::anyhow!("refused")));
+        record_result(&health, key, &check, Ok(()));
+        assert!(!is_healthy(), "a failure resets the successes in a row");
+        record_result(&health, key, &check, Ok(()));
+        assert!(is_healthy());
+        assert!(health.get(key).unwrap().last_error.is_none());
+
+    }
+
+    #[tokio::test]
+    async fn tcp_probes_fail_when_nothing_is_listening() {
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("should bind");
+        let port = listener.local_addr().expect("should have an address").port();
+        assert!(check_backend(&backend("127.0.0.1", port), &check(HealthCheckKind::Tcp)).await.is_ok());
+
+        drop(listener);
+        assert!(check_backend(&backend("127.0.0.1", port), &check(HealthCheckKind::Tcp)).await.is_err());
+
+    }
+
+    #[tokio::test]
+    async fn http_probes_accept_success_and_redirects_only() {
+
+        let http = check(HealthCheckKind::Http);
+
+        let port = respond_once_with("HTTP/1.1 204 No Content").await;
+        assert!(check_backend(&backend("127.0.0.1", port), &http).await.is_ok());
+
+        let port = respond_once_with("HTTP/1.1 302 Found").await;
+        assert!(check_backend(&backend("127.0.0.1", port), &http).await.is_ok());
+
+        let port = respond_once_with("HTTP/1.1 503 Service Unavailable").await;
+        let error = check_backend(&backend("127.0.0.1", port), &http).await.expect_err("503 should fail the check");
+        assert!(error.to_string().contains("503"));
+
+        assert_eq!(probe_url(false, "10.0.0.1", 8080, Some("health")), "http://10.0.0.1:8080/health");
+        assert_eq!(probe_url(true, "10.0.0.1", 443, None), "https://10.0.0.1:443/");
+
+    }
+
+    #[test]
+    fn unhealthy_backends_are_skipped_for_the_site_they_belong_to() {
+
+        let example = crate::configuration::v2::OddBoxV2Config::example();
+        let mut site = example.remote_target.expect("example has remote targets")[0].clone();
+        site.host_name = "site.local".into();
+        site.backends = vec![backend("a.local",80), backend("b.local",80)];
+        let mut other_site = site.clone();
+        other_site.host_name = "other.local".into();
+
+        let health : DashMap<String,BackendHealth> = DashMap::new();
+        let check = check(HealthCheckKind::Tcp);
+        for _ in 0..3 {
+            record_result(&health, &health_key(&site.host_name, &site.backends[0]), &check, Err(anyhow::anyhow!("refused")));
+        }
+
+        let usable = |site:&crate::configuration::v2::RemoteSiteConfig| {
+            site.prefer_healthy_backends(site.backends.iter().collect(), |b| is_healthy(&health, &site.host_name, b))
+                .into_iter().map(|b| b.address.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(usable(&site), vec!["b.local".to_string()]);
+        assert_eq!(usable(&other_site), vec!["a.local".to_string(), "b.local".to_string()]);
+
+        // when every backend is unhealthy they are all used rather than failing every request
+        for _ in 0..3 {
+            record_result(&health, &health_key(&site.host_name, &site.backends[1]), &check, Err(anyhow::anyhow!("refused")));
+        }
+        assert_eq!(usable(&site), vec!["a.local".to_string(), "b.local".to_string()]);
+
+    }
+}
@@ -0,0 +1,574 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::body::Frame;
+use hyper::{Method, Request, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use utoipa::ToSchema;
+
+use crate::configuration::v2::CacheConfig;
+use crate::http_proxy::{create_epic_full_body, create_epic_string_full_body, create_response_channel, create_stream_response, EpicBody, EpicResponse};
+use crate::CustomError;
+
+lazy_static::lazy_static! {
+    // keyed by site and cache key, see memory_key
+    static ref ENTRIES : dashmap::DashMap<String,CachedResponse> = dashmap::DashMap::new();
+}
+
+/// Status codes that can be cached without the backend explicitly allowing it, as listed in RFC 9110.
+const CACHEABLE_STATUS_CODES : [u16;8] = [200, 203, 204, 300, 301, 308, 404, 410];
+
+/// Tells the client if a response came from the cache.
+pub const CACHE_STATUS_HEADER : &str = "x-odd-box-cache";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub site : String,
+    pub key : String,
+    pub status : u16,
+    pub headers : Vec<(String,String)>,
+    /// Values of the request headers listed in the Vary header of the response
+    pub vary : Vec<(String,Option<String>)>,
+    /// Unix timestamp in seconds
+    pub stored_at : u64,
+    /// Unix timestamp in seconds
+    pub expires_at : u64,
+    #[serde(skip)]
+    pub body : Bytes,
+    #[serde(skip)]
+    last_used : u64
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CacheEntryInfo {
+    pub site : String,
+    pub key : String,
+    pub status : u16,
+    pub size_bytes : u64,
+    pub age_seconds : u64,
+    /// Negative when the response is stale and must be revalidated before it is used again
+    pub expires_in_seconds : i64
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or_default()
+}
+
+pub fn cache_key(req_host_name:&str, uri:&hyper::Uri) -> String {
+    format!("{req_host_name}{}", uri.path_and_query().map(|x| x.as_str()).unwrap_or("/"))
+}
+
+fn memory_key(site:&str, key:&str) -> String {
+    format!("{site} {key}")
+}
+
+// all values of a header, joined as a single lowercase list
+fn header_list(headers:&HeaderMap, name:HeaderName) -> Vec<String> {
+    headers.get_all(name).iter()
+        .filter_map(|x| x.to_str().ok())
+        .flat_map(|x| x.split(','))
+        .map(|x| x.trim().to_lowercase())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+pub fn parse_http_date(value:&str) -> Option<u64> {
+    chrono::DateTime::parse_from_rfc2822(value).ok().map(|x| x.timestamp().max(0) as u64)
+}
+
+/// Only GET requests without credentials are cached, unless the client asked us not to store anything.
+pub fn request_is_cacheable<B>(req:&Request<B>) -> bool {
+    req.method() == Method::GET
+    && !req.headers().contains_key(hyper::header::AUTHORIZATION)
+    && !header_list(req.headers(), hyper::header::CACHE_CONTROL).iter().any(|x| x == "no-store")
+}
+
+/// True if the client wants the response to be revalidated with the site even if we have a fresh copy.
+pub fn request_wants_revalidation(headers:&HeaderMap) -> bool {
+    header_list(headers, hyper::header::CACHE_CONTROL).iter().any(|x| x == "no-cache" || x == "max-age=0")
+    || header_list(headers, hyper::header::PRAGMA).iter().any(|x| x == "no-cache")
+}
+
+/// Returns the number of seconds that a response stays fresh, or None if it may not be cached at all.
+/// Responses that may be cached but have no freshness information get a lifetime of zero, so they are always revalidated.
+pub fn freshness_lifetime(status:StatusCode, headers:&HeaderMap, now:u64) -> Option<u64> {
+
+    if !CACHEABLE_STATUS_CODES.contains(&status.as_u16()) {
+        return None
+    }
+    if headers.contains_key(hyper::header::SET_COOKIE) || header_list(headers, hyper::header::VARY).iter().any(|x| x == "*") {
+        return None
+    }
+
+    let cache_control = header_list(headers, hyper::header::CACHE_CONTROL);
+    if cache_control.iter().any(|x| x == "no-store" || x == "private") {
+        return None
+    }
+
+    let directive = |name:&str| cache_control.iter()
+        .find_map(|x| x.strip_prefix(name).and_then(|x| x.strip_prefix('=')))
+        .and_then(|x| x.trim_matches('"').parse::<u64>().ok());
+
+    let lifetime = if cache_control.iter().any(|x| x == "no-cache") {
+        0
+    } else if let Some(seconds) = directive("s-maxage").or(directive("max-age")) {
+        seconds
+    } else if let Some(expires) = headers.get(hyper::header::EXPIRES).and_then(|x| x.to_str().ok()) {
+        let date = headers.get(hyper::header::DATE).and_then(|x| x.to_str().ok()).and_then(parse_http_date).unwrap_or(now);
+        parse_http_date(expires).map(|x| x.saturating_sub(date)).unwrap_or(0)
+    } else {
+        0
+    };
+
+    // there is no point in keeping a response that can never be reused or revalidated
+    if lifetime == 0 && !headers.contains_key(hyper::header::ETAG) && !headers.contains_key(hyper::header::LAST_MODIFIED) {
+        return None
+    }
+
+    Some(lifetime)
+}
+
+impl CachedResponse {
+
+    fn header_map(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name,value) in &self.headers {
+            if let (Ok(name),Ok(value)) = (HeaderName::from_bytes(name.as_bytes()),HeaderValue::from_str(value)) {
+                headers.append(name, value);
+            }
+        }
+        headers
+    }
+
+    fn header(&self, name:&str) -> Option<&str> {
+        self.headers.iter().find(|(x,_)| x == name).map(|(_,x)| x.as_str())
+    }
+
+    pub fn is_fresh(&self) -> bool {
+        now() < self.expires_at
+    }
+
+    /// True if the request sends the same values for the headers that the response varies on.
+    pub fn matches_vary(&self, headers:&HeaderMap) -> bool {
+        self.vary.iter().all(|(name,value)| headers.get(name.as_str()).and_then(|x| x.to_str().ok()) == value.as_deref())
+    }
+
+    /// Adds If-None-Match and If-Modified-Since headers so that the site can respond with 304 if the response is unchanged.
+    /// Requests that already have conditional headers are left alone, since the client is revalidating its own copy
+    /// which may not be the one we have. Returns true if the validators were added.
+    pub fn add_validators(&self, headers:&mut HeaderMap) -> bool {
+        if headers.contains_key(hyper::header::IF_NONE_MATCH) || headers.contains_key(hyper::header::IF_MODIFIED_SINCE) {
+            return false
+        }
+        if let Some(value) = self.header("etag").and_then(|x| HeaderValue::from_str(x).ok()) {
+            headers.insert(hyper::header::IF_NONE_MATCH, value);
+        }
+        if let Some(value) = self.header("last-modified").and_then(|x| HeaderValue::from_str(x).ok()) {
+            headers.insert(hyper::header::IF_MODIFIED_SINCE, value);
+        }
+        true
+    }
+
+    pub fn to_response(&self, cache_status:&'static str, client_headers:&HeaderMap) -> EpicResponse {
+
+        let etag = self.header("etag");
+        let not_modified = etag.is_some_and(|etag| header_list(client_headers, hyper::header::IF_NONE_MATCH).iter()
+            .any(|x| x == "*" || x.trim_start_matches("w/") == etag.to_lowercase().trim_start_matches("w/")));
+
+        let mut response = if not_modified {
+            let mut response = EpicResponse::new(create_epic_string_full_body(""));
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+            response
+        } else {
+            let mut response = EpicResponse::new(create_epic_full_body(self.body.clone()));
+            *response.status_mut() = StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK);
+            response
+        };
+
+        *response.headers_mut() = self.header_map();
+        if not_modified {
+            response.headers_mut().remove(hyper::header::CONTENT_LENGTH);
+        }
+        response.headers_mut().insert(hyper::header::AGE, HeaderValue::from(now().saturating_sub(self.stored_at)));
+        response.headers_mut().insert(CACHE_STATUS_HEADER, HeaderValue::from_static(cache_status));
+        response
+    }
+
+    fn info(&self) -> CacheEntryInfo {
+        let now = now();
+        CacheEntryInfo {
+            site: self.site.clone(),
+            key: self.key.clone(),
+            status: self.status,
+            size_bytes: self.body.len() as u64,
+            age_seconds: now.saturating_sub(self.stored_at),
+            expires_in_seconds: self.expires_at as i64 - now as i64
+        }
+    }
+}
+
+/// Finds a cached response for the request, loading it from disk if it is not in memory.
+pub async fn lookup(config:&CacheConfig, site:&str, key:&str, headers:&HeaderMap) -> Option<CachedResponse> {
+    let memory_key = memory_key(site, key);
+    let cached = match ENTRIES.get_mut(&memory_key) {
+        Some(mut entry) => {
+            entry.last_used = now();
+            Some(entry.clone())
+        },
+        None => match &config.disk_dir {
+            Some(dir) => {
+                let entry = read_from_disk(dir, site, key).await?;
+                ENTRIES.insert(memory_key, entry.clone());
+                evict_from_memory(config, site);
+                Some(entry)
+            },
+            None => None
+        }
+    };
+    cached.filter(|x| x.matches_vary(headers))
+}
+
+/// Caches the response if it is allowed to, and returns it with the body read into memory.
+/// Responses that cannot be cached are returned as they are. Responses without a content-length are read
+/// until they grow larger than the max entry size, after which the part already read is sent on followed by the rest of the body.
+pub async fn store_response(config:&CacheConfig, site:&str, key:&str, client_headers:&HeaderMap, response:EpicResponse) -> EpicResponse {
+
+    let now = now();
+    let Some(lifetime) = freshness_lifetime(response.status(), response.headers(), now) else {
+        return response
+    };
+    let content_length = response.headers().get(hyper::header::CONTENT_LENGTH)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.parse::<u64>().ok());
+    if content_length.is_some_and(|x| x > config.max_entry_size_bytes()) {
+        return response
+    }
+    // we can only store headers that are valid strings
+    if response.headers().values().any(|x| x.to_str().is_err()) {
+        return response
+    }
+
+    let (mut parts, mut body) = response.into_parts();
+    let mut frames = vec![];
+    let mut size = 0;
+    while let Some(frame) = body.frame().await {
+        match frame {
+            Ok(frame) => {
+                size += frame.data_ref().map(|x| x.len() as u64).unwrap_or_default();
+                // trailers are not kept in the cache
+                let give_up = frame.is_trailers() || size > config.max_entry_size_bytes();
+                frames.push(frame);
+                if give_up {
+                    tracing::trace!("Not caching {key} from {site} as it is too large or has trailers");
+                    return replay(parts, frames, body)
+                }
+            },
+            Err(e) => {
+                tracing::warn!("Failed to read the response for {key} from {site} while caching it: {e:?}");
+                let mut response = EpicResponse::new(create_epic_string_full_body("502 Bad Gateway - failed to read the response from the site"));
+                *response.status_mut() = StatusCode::BAD_GATEWAY;
+                return response
+            }
+        }
+    }
+    let body = Bytes::from(frames.into_iter().filter_map(|x| x.into_data().ok()).collect::<Vec<_>>().concat());
+
+    let entry = CachedResponse {
+        site: site.to_string(),
+        key: key.to_string(),
+        status: parts.status.as_u16(),
+        headers: parts.headers.iter().filter_map(|(name,value)| value.to_str().ok().map(|x| (name.to_string(), x.to_string()))).collect(),
+        vary: header_list(&parts.headers, hyper::header::VARY).into_iter()
+            .map(|name| {
+                let value = client_headers.get(name.as_str()).and_then(|x| x.to_str().ok()).map(|x| x.to_string());
+                (name, value)
+            })
+            .collect(),
+        stored_at: now,
+        expires_at: now + lifetime,
+        body: body.clone(),
+        last_used: now
+    };
+    store(config, entry).await;
+
+    parts.headers.insert(CACHE_STATUS_HEADER, HeaderValue::from_static("MISS"));
+    EpicResponse::from_parts(parts, create_epic_full_body(body))
+}
+
+/// Sends the frames that were already read from the body followed by the rest of it.
+fn replay(parts:hyper::http::response::Parts, frames:Vec<Frame<Bytes>>, mut body:EpicBody) -> EpicResponse {
+    let (tx, rx) = create_response_channel(16);
+    tokio::spawn(async move {
+        for frame in frames {
+            if tx.send(Ok(frame)).await.is_err() {
+                return
+            }
+        }
+        while let Some(frame) = body.frame().await {
+            let frame = frame.map_err(|e| CustomError(format!("{e:?}")));
+            if tx.send(frame).await.is_err() {
+                // the client went away
+                break
+            }
+        }
+    });
+    let (_, body) = create_stream_response(rx).into_parts();
+    EpicResponse::from_parts(parts, body)
+}
+
+/// Updates a cached response with the headers of a 304 response from the site.
+pub async fn refresh(config:&CacheConfig, mut entry:CachedResponse, not_modified_headers:&HeaderMap) -> CachedResponse {
+    for name in [hyper::header::CACHE_CONTROL, hyper::header::EXPIRES, hyper::header::DATE, hyper::header::ETAG, hyper::header::LAST_MODIFIED] {
+        if let Some(value) = not_modified_headers.get(&name).and_then(|x| x.to_str().ok()) {
+            entry.headers.retain(|(x,_)| x != name.as_str());
+            entry.headers.push((name.to_string(), value.to_string()));
+        }
+    }
+    let now = now();
+    let status = StatusCode::from_u16(entry.status).unwrap_or(StatusCode::OK);
+    match freshness_lifetime(status, &entry.header_map(), now) {
+        Some(lifetime) => {
+            entry.stored_at = now;
+            entry.expires_at = now + lifetime;
+            store(config, entry.clone()).await;
+        },
+        None => {
+            ENTRIES.remove(&memory_key(&entry.site, &entry.key));
+        }
+    }
+    entry
+}
+
+async fn store(config:&CacheConfig, entry:CachedResponse) {
+    if let Some(dir) = &config.disk_dir {
+        if let Err(e) = write_to_disk(dir, &entry, config.max_disk_bytes()).await {
+            tracing::warn!("Failed to write {} to the cache directory {dir}: {e:?}", entry.key);
+        }
+    }
+    let site = entry.site.clone();
+    ENTRIES.insert(memory_key(&entry.site, &entry.key), entry);
+    evict_from_memory(config, &site);
+}
+
+// drops the least recently used responses of the site until it is within its memory limit
+fn evict_from_memory(config:&CacheConfig, site:&str) {
+    let mut entries : Vec<(String,u64,u64)> = ENTRIES.iter()
+        .filter(|x| x.site == site)
+        .map(|x| (x.key().clone(), x.last_used, x.body.len() as u64))
+        .collect();
+    let mut total : u64 = entries.iter().map(|(_,_,size)| size).sum();
+    entries.sort_by_key(|(_,last_used,_)| *last_used);
+    for (key,_,size) in entries {
+        if total <= config.max_memory_bytes() {
+            break
+        }
+        ENTRIES.remove(&key);
+        total -= size;
+    }
+}
+
+fn disk_paths(dir:&str, site:&str, key:&str) -> (PathBuf,PathBuf) {
+    let name = format!("{:x}", sha2::Sha256::digest(memory_key(site, key)));
+    (Path::new(dir).join(format!("{name}.json")), Path::new(dir).join(format!("{name}.body")))
+}
+
+async fn read_from_disk(dir:&str, site:&str, key:&str) -> Option<CachedResponse> {
+    let (meta_path, body_path) = disk_paths(dir, site, key);
+    let meta = tokio::fs::read(&meta_path).await.ok()?;
+    let mut entry : CachedResponse = serde_json::from_slice(&meta).ok()?;
+    entry.body = Bytes::from(tokio::fs::read(&body_path).await.ok()?);
+    entry.last_used = now();
+    Some(entry)
+}
+
+async fn write_to_disk(dir:&str, entry:&CachedResponse, max_bytes:u64) -> anyhow::Result<()> {
+    let (meta_path, body_path) = disk_paths(dir, &entry.site, &entry.key);
+    tokio::fs::create_dir_all(dir).await?;
+    tokio::fs::write(&body_path, &entry.body).await?;
+    tokio::fs::write(&meta_path, serde_json::to_vec(entry)?).await?;
+    let dir = dir.to_string();
+    tokio::task::spawn_blocking(move || enforce_disk_limit(&dir, max_bytes)).await??;
+    Ok(())
+}
+
+// removes the oldest responses in the directory until the total size is within the limit
+fn enforce_disk_limit(dir:&str, max_bytes:u64) -> anyhow::Result<()> {
+    let mut bodies = vec![];
+    for file in std::fs::read_dir(dir)? {
+        let file = file?;
+        let path = file.path();
+        if path.extension().is_some_and(|x| x == "body") {
+            let metadata = file.metadata()?;
+            bodies.push((path, metadata.len(), metadata.modified()?));
+        }
+    }
+    let mut total : u64 = bodies.iter().map(|(_,size,_)| size).sum();
+    bodies.sort_by_key(|(_,_,modified)| *modified);
+    for (path,size,_) in bodies {
+        if total <= max_bytes {
+            break
+        }
+        _ = std::fs::remove_file(path.with_extension("json"));
+        std::fs::remove_file(&path)?;
+        total -= size;
+    }
+    Ok(())
+}
+
+/// Lists the responses that are currently cached in memory.
+pub fn entries() -> Vec<CacheEntryInfo> {
+    let mut entries : Vec<CacheEntryInfo> = ENTRIES.iter().map(|x| x.info()).collect();
+    entries.sort_by(|a,b| (&a.site,&a.key).cmp(&(&b.site,&b.key)));
+    entries
+}
+
+/// Removes cached responses from memory and from the given cache directories.
+/// Leaving out the site or key removes the responses of all sites or all keys.
+pub async fn purge(site:Option<&str>, key:Option<&str>, disk_dirs:Vec<String>) -> usize {
+
+    let matches = |entry_site:&str, entry_key:&str| site.map_or(true, |x| x == entry_site) && key.map_or(true, |x| x == entry_key);
+
+    let before = ENTRIES.len();
+    ENTRIES.retain(|_,x| !matches(&x.site, &x.key));
+    let mut removed = before.saturating_sub(ENTRIES.len());
+
+    for dir in disk_dirs {
+        let Ok(mut files) = tokio::fs::read_dir(&dir).await else { continue };
+        while let Ok(Some(file)) = files.next_entry().await {
+            let path = file.path();
+            if !path.extension().is_some_and(|x| x == "json") {
+                continue
+            }
+            let Some(entry) = tokio::fs::read(&path).await.ok().and_then(|x| serde_json::from_slice::<CachedResponse>(&x).ok()) else {
+                continue
+            };
+            if matches(&entry.site, &entry.key) {
+                _ = tokio::fs::remove_file(path.with_extension("body")).await;
+                if tokio::fs::remove_file(&path).await.is_ok() && !ENTRIES.contains_key(&memory_key(&entry.site, &entry.key)) {
+                    removed += 1;
+                }
+            }
+        }
+    }
+
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn cache_freshness_follows_cache_control_and_expires() {
+
+        let headers = |pairs:&[(&'static str,&'static str)]| {
+            let mut headers = hyper::HeaderMap::new();
+            for (name,value) in pairs {
+                headers.append(*name, value.parse().unwrap());
+            }
+            headers
+        };
+        let ok = hyper::StatusCode::OK;
+        let now = 1_700_000_000;
+
+        assert_eq!(freshness_lifetime(ok, &headers(&[("cache-control", "public, max-age=60")]), now), Some(60));
+        assert_eq!(freshness_lifetime(ok, &headers(&[("cache-control", "max-age=60, s-maxage=300")]), now), Some(300));
+        assert_eq!(freshness_lifetime(ok, &headers(&[
+            ("date", "Tue, 14 Nov 2023 22:13:20 GMT"),
+            ("expires", "Tue, 14 Nov 2023 22:23:20 GMT")
+        ]), now), Some(600));
+        // no-cache responses may be stored, but only if they can be revalidated
+        assert_eq!(freshness_lifetime(ok, &headers(&[("cache-control", "no-cache"), ("etag", "\"abc\"")]), now), Some(0));
+        assert_eq!(freshness_lifetime(ok, &headers(&[("cache-control", "no-cache")]), now), None);
+        assert_eq!(freshness_lifetime(ok, &headers(&[("cache-control", "no-store")]), now), None);
+        assert_eq!(freshness_lifetime(ok, &headers(&[("cache-control", "private, max-age=60")]), now), None);
+        assert_eq!(freshness_lifetime(ok, &headers(&[("cache-control", "max-age=60"), ("set-cookie", "a=b")]), now), None);
+        assert_eq!(freshness_lifetime(ok, &headers(&[("cache-control", "max-age=60"), ("vary", "*")]), now), None);
+        assert_eq!(freshness_lifetime(hyper::StatusCode::INTERNAL_SERVER_ERROR, &headers(&[("cache-control", "max-age=60")]), now), None);
+
+        let request = |method:hyper::Method, pairs:&[(&'static str,&'static str)]| {
+            let mut request = hyper::Request::builder().method(method).uri("/").body(()).unwrap();
+            *request.headers_mut() = headers(pairs);
+            request
+        };
+        assert!(request_is_cacheable(&request(hyper::Method::GET, &[])));
+        assert!(!request_is_cacheable(&request(hyper::Method::POST, &[])));
+        assert!(!request_is_cacheable(&request(hyper::Method::GET, &[("authorization", "Basic YTpi")])));
+        assert!(!request_is_cacheable(&request(hyper::Method::GET, &[("cache-control", "no-store")])));
+        assert!(request_wants_revalidation(&headers(&[("cache-control", "max-age=0")])));
+        assert!(request_wants_revalidation(&headers(&[("pragma", "no-cache")])));
+        assert!(!request_wants_revalidation(&headers(&[("cache-control", "max-stale")])));
+
+    }
+
+    #[test]
+    fn cached_responses_are_only_used_for_matching_vary_headers() {
+
+        let entry = CachedResponse {
+            site: "site.local".into(),
+            key: "site.local/".into(),
+            status: 200,
+            headers: vec![(String::from("etag"), String::from("\"abc\""))],
+            vary: vec![(String::from("accept-language"), Some(String::from("sv"))), (String::from("x-theme"), None)],
+            stored_at: now(),
+            expires_at: now() + 60,
+            body: Bytes::new(),
+            last_used: 0
+        };
+        let headers = |pairs:&[(&'static str,&'static str)]| {
+            let mut headers = hyper::HeaderMap::new();
+            for (name,value) in pairs {
+                headers.append(*name, value.parse().unwrap());
+            }
+            headers
+        };
+
+        assert!(entry.is_fresh());
+        assert!(entry.matches_vary(&headers(&[("accept-language", "sv")])));
+        assert!(!entry.matches_vary(&headers(&[("accept-language", "en")])));
+        assert!(!entry.matches_vary(&headers(&[("accept-language", "sv"), ("x-theme", "dark")])));
+        assert!(!entry.matches_vary(&headers(&[])));
+
+        let mut validators = headers(&[]);
+        assert!(entry.add_validators(&mut validators));
+        assert_eq!(validators.get(hyper::header::IF_NONE_MATCH).unwrap(), "\"abc\"");
+
+        let mut validators = headers(&[("if-none-match", "\"client\"")]);
+        assert!(!entry.add_validators(&mut validators));
+        assert_eq!(validators.get(hyper::header::IF_NONE_MATCH).unwrap(), "\"client\"");
+
+    }
+
+    #[tokio::test]
+    async fn responses_without_a_content_length_are_cached_up_to_the_max_entry_size() {
+
+        let config = CacheConfig { max_memory_mb: None, max_entry_size_mb: Some(1), disk_dir: None, max_disk_mb: None, serve_stale_on_error: None };
+        let chunked_response = |chunks:Vec<Bytes>| {
+            let (tx, rx) = create_response_channel(chunks.len());
+            for chunk in chunks {
+                tx.try_send(Ok(Frame::data(chunk))).unwrap();
+            }
+            let mut response = create_stream_response(rx);
+            response.headers_mut().insert(hyper::header::CACHE_CONTROL, HeaderValue::from_static("max-age=60"));
+            response
+        };
+
+        let response = chunked_response(vec![Bytes::from("hello "), Bytes::from("world")]);
+        let response = store_response(&config, "chunked.local", "small", &HeaderMap::new(), response).await;
+        assert_eq!(response.into_body().collect().await.unwrap().to_bytes(), "hello world");
+        assert_eq!(lookup(&config, "chunked.local", "small", &HeaderMap::new()).await.unwrap().body, "hello world");
+
+        // too large to cache, but the client still gets all of it
+        let chunk = Bytes::from(vec![b'x'; 600 * 1024]);
+        let response = chunked_response(vec![chunk.clone(), chunk.clone(), chunk.clone()]);
+        let response = store_response(&config, "chunked.local", "large", &HeaderMap::new(), response).await;
+        assert_eq!(response.into_body().collect().await.unwrap().to_bytes().len(), 3 * chunk.len());
+        assert!(lookup(&config, "chunked.local", "large", &HeaderMap::new()).await.is_none());
+
+    }
+}
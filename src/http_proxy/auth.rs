@@ -0,0 +1,219 @@
+use std::net::IpAddr;
+use std::time::Duration;
+use base64::Engine;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::{Request, StatusCode};
+use sha2::Digest;
+use crate::configuration::v2::{AuthConfig, AuthKind};
+use super::{create_epic_string_full_body, EpicResponse};
+
+lazy_static::lazy_static! {
+    static ref FORWARD_AUTH_CLIENT : reqwest::Client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("must be able to create the forward auth client");
+    // password hashes are slow to verify on purpose, so we remember credentials that have already been accepted
+    static ref VERIFIED_CREDENTIALS : dashmap::DashSet<String> = dashmap::DashSet::new();
+}
+
+const MAX_VERIFIED_CREDENTIALS : usize = 1000;
+
+pub enum AuthResult {
+    /// Contains the headers that should be copied to the request
+    Allowed(Vec<(HeaderName,HeaderValue)>),
+    /// Contains the response that should be sent to the client instead of proxying the request
+    Denied(EpicResponse)
+}
+
+pub fn validate(auth:&AuthConfig) -> anyhow::Result<()> {
+    match auth.kind {
+        AuthKind::Basic => {
+            let users = auth.users.as_ref().filter(|x| !x.is_empty())
+                .ok_or(anyhow::anyhow!("Basic auth needs at least one user."))?;
+            for user in users {
+                let (name, hash) = user.split_once(':')
+                    .ok_or(anyhow::anyhow!("Users must be in the format name:hash."))?;
+                if !["$2a$","$2b$","$2y$","$argon2"].iter().any(|x| hash.starts_with(x)) {
+                    anyhow::bail!("The password of '{name}' must be hashed with bcrypt or argon2.");
+                }
+            }
+        },
+        AuthKind::Forward => {
+            let url = auth.url.as_ref().ok_or(anyhow::anyhow!("Forward auth needs a url."))?;
+            reqwest::Url::parse(url).map_err(|e| anyhow::anyhow!("'{url}' is not a valid url: {e}"))?;
+            for name in auth.copy_headers.iter().flatten() {
+                if HeaderName::from_bytes(name.as_bytes()).is_err() {
+                    anyhow::bail!("'{name}' is not a valid header name.");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if the password matches a bcrypt or argon2 hash.
+pub fn verify_password(password:&str, hash:&str) -> bool {
+    if hash.starts_with("$argon2") {
+        use argon2::PasswordVerifier;
+        argon2::PasswordHash::new(hash)
+            .is_ok_and(|parsed| argon2::Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+    } else {
+        bcrypt::verify(password, hash).unwrap_or(false)
+    }
+}
+
+pub async fn authorize<B>(
+    auth:&AuthConfig,
+    req:&Request<B>,
+    client_ip:IpAddr,
+    is_https:bool,
+    req_host_name:&str
+) -> AuthResult {
+    match auth.kind {
+        AuthKind::Basic => basic_auth(auth, req.headers()).await,
+        AuthKind::Forward => forward_auth(auth, req, client_ip, is_https, req_host_name).await
+    }
+}
+
+/// Replaces the configured copy_headers of a request with the ones returned by forward auth,
+/// so that clients cannot send their own values for them.
+pub fn apply_copied_headers(auth:&AuthConfig, headers:&mut HeaderMap, copied:Vec<(HeaderName,HeaderValue)>) {
+    for name in auth.copy_headers.iter().flatten() {
+        headers.remove(name.as_str());
+    }
+    for (name,value) in copied {
+        headers.append(name, value);
+    }
+}
+
+async fn basic_auth(auth:&AuthConfig, headers:&HeaderMap) -> AuthResult {
+
+    let credentials = headers.get(hyper::header::AUTHORIZATION)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Basic "))
+        .and_then(|x| base64::engine::general_purpose::STANDARD.decode(x.trim()).ok())
+        .and_then(|x| String::from_utf8(x).ok());
+
+    if let Some((name, password)) = credentials.as_ref().and_then(|x| x.split_once(':')) {
+        if let Some((_, hash)) = auth.users.iter().flatten().filter_map(|x| x.split_once(':')).find(|(user,_)| *user == name) {
+            let cache_key = format!("{:x}", sha2::Sha256::digest(format!("{hash}:{password}")));
+            if VERIFIED_CREDENTIALS.contains(&cache_key) {
+                return AuthResult::Allowed(vec![])
+            }
+            let (password, hash) = (password.to_string(), hash.to_string());
+            let verified = tokio::task::spawn_blocking(move || verify_password(&password, &hash)).await.unwrap_or(false);
+            if verified {
+                if VERIFIED_CREDENTIALS.len() >= MAX_VERIFIED_CREDENTIALS {
+                    VERIFIED_CREDENTIALS.clear();
+                }
+                VERIFIED_CREDENTIALS.insert(cache_key);
+                return AuthResult::Allowed(vec![])
+            }
+            tracing::debug!("Basic auth failed for user '{name}'");
+        }
+    }
+
+    let realm = auth.realm.as_deref().unwrap_or("odd-box").replace('"', "");
+    let mut response = EpicResponse::new(create_epic_string_full_body("401 Unauthorized"));
+    *response.status_mut() = StatusCode::UNAUTHORIZED;
+    if let Ok(value) = HeaderValue::from_str(&format!("Basic realm=\"{realm}\"")) {
+        response.headers_mut().insert(hyper::header::WWW_AUTHENTICATE, value);
+    }
+    AuthResult::Denied(response)
+}
+
+async fn forward_auth<B>(
+    auth:&AuthConfig,
+    req:&Request<B>,
+    client_ip:IpAddr,
+    is_https:bool,
+    req_host_name:&str
+) -> AuthResult {
+
+    let mut headers = req.headers().clone();
+    for name in [hyper::header::HOST, hyper::header::CONTENT_LENGTH, hyper::header::TRANSFER_ENCODING, hyper::header::CONNECTION, hyper::header::UPGRADE] {
+        headers.remove(name);
+    }
+    let forwarded = [
+        ("x-forwarded-method", req.method().to_string()),
+        ("x-forwarded-proto", String::from(if is_https { "https" } else { "http" })),
+        ("x-forwarded-host", req_host_name.to_string()),
+        ("x-forwarded-uri", req.uri().path_and_query().map(|x| x.to_string()).unwrap_or(String::from("/"))),
+        ("x-forwarded-for", client_ip.to_canonical().to_string()),
+    ];
+    for (name, value) in forwarded {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(HeaderName::from_static(name), value);
+        }
+    }
+
+    let url = auth.url.clone().unwrap_or_default();
+    let response = match FORWARD_AUTH_CLIENT.get(&url).headers(headers).send().await {
+        Ok(x) => x,
+        Err(e) => {
+            tracing::warn!("Forward auth request to {url} failed: {e:?}");
+            let mut response = EpicResponse::new(create_epic_string_full_body("502 Bad Gateway - the auth server could not be reached"));
+            *response.status_mut() = StatusCode::BAD_GATEWAY;
+            return AuthResult::Denied(response)
+        }
+    };
+
+    if response.status().is_success() {
+        let copied = auth.copy_headers.iter().flatten()
+            .filter_map(|name| HeaderName::from_bytes(name.as_bytes()).ok())
+            .flat_map(|name| response.headers().get_all(&name).iter().map(|value| (name.clone(), value.clone())).collect::<Vec<_>>())
+            .collect();
+        return AuthResult::Allowed(copied)
+    }
+
+    // anything else, such as a redirect to a login page, is passed on to the client as is
+    let mut denied = EpicResponse::new(create_epic_string_full_body(""));
+    *denied.status_mut() = response.status();
+    for (name, value) in response.headers() {
+        if name != hyper::header::CONTENT_LENGTH && name != hyper::header::TRANSFER_ENCODING && name != hyper::header::CONNECTION {
+            denied.headers_mut().append(name.clone(), value.clone());
+        }
+    }
+    let body = response.bytes().await.unwrap_or_default();
+    *denied.body_mut() = create_epic_string_full_body(&String::from_utf8_lossy(&body));
+    AuthResult::Denied(denied)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn basic_auth_passwords_can_be_verified_with_bcrypt_and_argon2() {
+
+        use crate::configuration::v2::AuthKind;
+        use argon2::PasswordHasher;
+
+        let bcrypt_hash = bcrypt::hash("secret", 4).unwrap();
+        let salt = argon2::password_hash::SaltString::from_b64("c29tZXNhbHQ").unwrap();
+        let argon2_hash = argon2::Argon2::default().hash_password(b"secret", &salt).unwrap().to_string();
+
+        for hash in [&bcrypt_hash, &argon2_hash] {
+            assert!(verify_password("secret", hash));
+            assert!(!verify_password("wrong", hash));
+        }
+
+        let mut auth = AuthConfig {
+            kind: AuthKind::Basic,
+            users: Some(vec![format!("alice:{bcrypt_hash}")]),
+            realm: None,
+            url: None,
+            copy_headers: None
+        };
+        assert!(validate(&auth).is_ok());
+        auth.users = Some(vec![String::from("alice:secret")]);
+        assert!(validate(&auth).is_err());
+        auth.kind = AuthKind::Forward;
+        assert!(validate(&auth).is_err());
+        auth.url = Some(String::from("http://auth.localtest.me/verify"));
+        assert!(validate(&auth).is_ok());
+
+    }
+}
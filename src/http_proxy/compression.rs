@@ -0,0 +1,278 @@
+use bytes::Bytes;
+use http_body::Frame;
+use http_body_util::BodyExt;
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::{Method, Request, StatusCode};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio_stream::StreamExt;
+use crate::configuration::v2::{CompressionConfig, CompressionEncoding};
+use crate::CustomError;
+use super::{create_response_channel, create_stream_response, EpicBody, EpicResponse};
+
+const DEFAULT_MIME_TYPES : [&str;6] = [
+    "text/*",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/wasm",
+    "image/svg+xml"
+];
+
+/// The encoding that was negotiated for a request, along with the compression settings of its site.
+/// The encoding is None when the client accepts none of the configured encodings.
+pub struct ResponseCompression {
+    pub encoding : Option<CompressionEncoding>,
+    config : CompressionConfig
+}
+
+/// Returns the quality that the Accept-Encoding header gives the encoding, either by name or through a wildcard.
+/// Encodings that are not accepted have a quality of 0.
+pub fn encoding_quality(accept_encoding:&str, token:&str) -> f32 {
+    let mut wildcard = 0.0;
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';').map(|x| x.trim());
+        let name = parts.next().unwrap_or_default();
+        let quality = parts
+            .find_map(|x| x.strip_prefix("q="))
+            .and_then(|x| x.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(token) {
+            return quality
+        }
+        if name == "*" {
+            wildcard = quality;
+        }
+    }
+    wildcard
+}
+
+impl ResponseCompression {
+
+    /// Picks the configured encoding that the client gives the highest quality, using the configured order for encodings of the same quality.
+    /// HEAD requests are never compressed since they have no body.
+    pub fn negotiate<B>(config:Option<CompressionConfig>, req:&Request<B>) -> Option<Self> {
+        let config = config?;
+        let accept_encoding = req.headers().get(hyper::header::ACCEPT_ENCODING)
+            .and_then(|x| x.to_str().ok())
+            .unwrap_or_default();
+        let mut encoding = None;
+        let mut best_quality = 0.0;
+        if req.method() != Method::HEAD {
+            let encodings = config.encodings.clone()
+                .unwrap_or(vec![CompressionEncoding::Zstd, CompressionEncoding::Brotli, CompressionEncoding::Gzip]);
+            for candidate in encodings {
+                let quality = encoding_quality(accept_encoding, candidate.token());
+                if quality > best_quality {
+                    best_quality = quality;
+                    encoding = Some(candidate);
+                }
+            }
+        }
+        Some(Self { encoding, config })
+    }
+
+    /// Responses that are already encoded, event streams, partial content and responses without a body are left alone.
+    pub fn should_compress(&self, status:StatusCode, headers:&HeaderMap) -> bool {
+        if status.is_informational() || status == StatusCode::NO_CONTENT || status == StatusCode::NOT_MODIFIED || status == StatusCode::PARTIAL_CONTENT {
+            return false
+        }
+        let header = |name:hyper::header::HeaderName| headers.get(name).and_then(|x| x.to_str().ok()).map(|x| x.to_lowercase());
+        if header(hyper::header::CONTENT_ENCODING).is_some_and(|x| x != "identity") {
+            return false
+        }
+        if header(hyper::header::CACHE_CONTROL).is_some_and(|x| x.contains("no-transform")) {
+            return false
+        }
+        let min_size = self.config.min_size_bytes.unwrap_or(1024);
+        if header(hyper::header::CONTENT_LENGTH).and_then(|x| x.parse::<u64>().ok()).is_some_and(|x| x < min_size) {
+            return false
+        }
+        let content_type = match header(hyper::header::CONTENT_TYPE) {
+            Some(x) => x.split(';').next().unwrap_or_default().trim().to_string(),
+            None => return false
+        };
+        if content_type == "text/event-stream" {
+            return false
+        }
+        let matches = |pattern:&str| match pattern.strip_suffix("/*") {
+            Some(prefix) => content_type.split('/').next() == Some(prefix),
+            None => content_type == pattern
+        };
+        match &self.config.mime_types {
+            Some(mime_types) => mime_types.iter().any(|x| matches(&x.to_lowercase())),
+            None => DEFAULT_MIME_TYPES.iter().any(|x| matches(x))
+        }
+    }
+
+    /// Compresses the body while it is being streamed to the client. Responses that would have been compressed for
+    /// another Accept-Encoding are marked as varying on it even when they are sent uncompressed, so that caches keep them apart.
+    pub fn apply(self, mut response:EpicResponse) -> EpicResponse {
+
+        if !self.should_compress(response.status(), response.headers()) {
+            return response
+        }
+        response.headers_mut().append(hyper::header::VARY, HeaderValue::from_static("accept-encoding"));
+        let Some(encoding) = self.encoding else {
+            return response
+        };
+
+        let (mut parts, body) = response.into_parts();
+        parts.headers.remove(hyper::header::CONTENT_LENGTH);
+        parts.headers.remove(hyper::header::ACCEPT_RANGES);
+        parts.headers.insert(hyper::header::CONTENT_ENCODING, HeaderValue::from_static(encoding.token()));
+
+        // the compressed body is not byte for byte the same as the original, so strong etags are made weak
+        let weak_etag = parts.headers.get(hyper::header::ETAG)
+            .and_then(|x| x.to_str().ok())
+            .filter(|x| !x.starts_with("W/"))
+            .and_then(|x| HeaderValue::from_str(&format!("W/{x}")).ok());
+        if let Some(etag) = weak_etag {
+            parts.headers.insert(hyper::header::ETAG, etag);
+        }
+
+        let (tx, rx) = create_response_channel(16);
+        match encoding {
+            CompressionEncoding::Gzip => tokio::spawn(compress_body(
+                async_compression::tokio::write::GzipEncoder::new(Vec::new()), |x| std::mem::take(x.get_mut()), body, tx)),
+            CompressionEncoding::Brotli => tokio::spawn(compress_body(
+                async_compression::tokio::write::BrotliEncoder::new(Vec::new()), |x| std::mem::take(x.get_mut()), body, tx)),
+            CompressionEncoding::Zstd => tokio::spawn(compress_body(
+                async_compression::tokio::write::ZstdEncoder::new(Vec::new()), |x| std::mem::take(x.get_mut()), body, tx))
+        };
+
+        let (_, body) = create_stream_response(rx).into_parts();
+        EpicResponse::from_parts(parts, body)
+    }
+}
+
+/// Compresses the body one frame at a time. The encoder is flushed after every frame so that streamed responses,
+/// such as event streams and long polling, reach the client as soon as the backend sends them rather than when the encoder buffer is full.
+async fn compress_body<E:AsyncWrite + Unpin>(
+    mut encoder:E,
+    take_output:fn(&mut E) -> Vec<u8>,
+    body:EpicBody,
+    tx:tokio::sync::mpsc::Sender<Result<Frame<Bytes>,CustomError>>
+) {
+    let mut data = body.into_data_stream();
+    let mut done = false;
+    while !done {
+        let written = match data.next().await {
+            Some(Ok(bytes)) => match encoder.write_all(&bytes).await {
+                Ok(()) => encoder.flush().await,
+                Err(e) => Err(e)
+            },
+            Some(Err(e)) => Err(std::io::Error::new(std::io::ErrorKind::Other, e)),
+            None => {
+                done = true;
+                encoder.shutdown().await
+            }
+        };
+        let frame = match written {
+            Ok(()) => {
+                let output = take_output(&mut encoder);
+                if output.is_empty() {
+                    continue
+                }
+                Ok(Frame::data(Bytes::from(output)))
+            },
+            Err(e) => {
+                done = true;
+                Err(CustomError(format!("{e:?}")))
+            }
+        };
+        if tx.send(frame).await.is_err() {
+            // the client went away
+            break
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[tokio::test]
+    async fn compression_is_negotiated_and_skips_encoded_responses() {
+
+        assert_eq!(encoding_quality("gzip, deflate, br", "br"), 1.0);
+        assert_eq!(encoding_quality("gzip, br;q=0", "br"), 0.0);
+        assert_eq!(encoding_quality("gzip;q=0.5, *;q=0.2", "zstd"), 0.2);
+        assert_eq!(encoding_quality("identity", "gzip"), 0.0);
+
+        let config = CompressionConfig { encodings: Some(vec![CompressionEncoding::Brotli, CompressionEncoding::Gzip]), mime_types: None, min_size_bytes: None };
+        let request = |method:hyper::Method, accept_encoding:&str| hyper::Request::builder().method(method).header("accept-encoding", accept_encoding).body(()).unwrap();
+        let negotiate = |method:hyper::Method, accept_encoding:&str| ResponseCompression::negotiate(Some(config.clone()), &request(method, accept_encoding)).unwrap().encoding;
+        assert_eq!(negotiate(hyper::Method::GET, "gzip, br"), Some(CompressionEncoding::Brotli));
+        assert_eq!(negotiate(hyper::Method::GET, "gzip, br;q=0.5"), Some(CompressionEncoding::Gzip));
+        assert_eq!(negotiate(hyper::Method::GET, "zstd, deflate"), None);
+        assert_eq!(negotiate(hyper::Method::HEAD, "gzip, br"), None);
+        assert!(ResponseCompression::negotiate(None, &request(hyper::Method::GET, "gzip")).is_none());
+
+        let compression = ResponseCompression::negotiate(Some(config.clone()), &request(hyper::Method::GET, "gzip, br")).unwrap();
+        let headers = |pairs:&[(&'static str,&'static str)]| {
+            let mut headers = hyper::HeaderMap::new();
+            for (name,value) in pairs {
+                headers.insert(*name, value.parse().unwrap());
+            }
+            headers
+        };
+        let ok = hyper::StatusCode::OK;
+        assert!(compression.should_compress(ok, &headers(&[("content-type", "text/html; charset=utf-8")])));
+        assert!(compression.should_compress(ok, &headers(&[("content-type", "application/json"), ("content-length", "4096")])));
+        assert!(!compression.should_compress(ok, &headers(&[("content-type", "application/json"), ("content-length", "10")])));
+        assert!(!compression.should_compress(ok, &headers(&[("content-type", "text/html"), ("content-encoding", "gzip")])));
+        assert!(!compression.should_compress(ok, &headers(&[("content-type", "text/event-stream")])));
+        assert!(!compression.should_compress(ok, &headers(&[("content-type", "Text/Event-Stream; charset=utf-8")])));
+        assert!(!compression.should_compress(ok, &headers(&[("content-type", "image/png")])));
+        assert!(!compression.should_compress(hyper::StatusCode::NOT_MODIFIED, &headers(&[("content-type", "text/html")])));
+
+        // responses sent uncompressed still depend on the accept-encoding of the request
+        let html = || {
+            let mut response = EpicResponse::new(super::super::create_epic_string_full_body("<html></html>"));
+            response.headers_mut().insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
+            response
+        };
+        let uncompressed = ResponseCompression::negotiate(Some(config.clone()), &request(hyper::Method::GET, "identity")).unwrap().apply(html());
+        assert!(uncompressed.headers().get(hyper::header::CONTENT_ENCODING).is_none());
+        assert_eq!(uncompressed.headers().get(hyper::header::VARY).unwrap(), "accept-encoding");
+        let compressed = ResponseCompression::negotiate(Some(config), &request(hyper::Method::GET, "gzip")).unwrap().apply(html());
+        assert_eq!(compressed.headers().get(hyper::header::CONTENT_ENCODING).unwrap(), "gzip");
+        assert_eq!(compressed.headers().get_all(hyper::header::VARY).iter().count(), 1);
+
+    }
+
+    #[tokio::test]
+    async fn streamed_frames_are_sent_without_waiting_for_more_data() {
+
+        use tokio::io::AsyncReadExt;
+
+        let config = CompressionConfig { encodings: Some(vec![CompressionEncoding::Gzip]), mime_types: Some(vec!["application/x-ndjson".into()]), min_size_bytes: None };
+        let request = hyper::Request::builder().header("accept-encoding", "gzip").body(()).unwrap();
+        let compression = ResponseCompression::negotiate(Some(config), &request).unwrap();
+
+        let (upstream, rx) = create_response_channel(1);
+        let mut response = create_stream_response(rx);
+        response.headers_mut().insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("application/x-ndjson"));
+        let mut body = compression.apply(response).into_body();
+
+        // the first frame can be decoded before the backend sends anything else
+        upstream.send(Ok(Frame::data(Bytes::from("{\"n\":1}\n")))).await.unwrap();
+        let first = body.frame().await.unwrap().unwrap().into_data().unwrap();
+        let mut decoder = async_compression::tokio::bufread::GzipDecoder::new(&first[..]);
+        let mut decoded = vec![0;8];
+        decoder.read_exact(&mut decoded).await.unwrap();
+        assert_eq!(decoded, b"{\"n\":1}\n");
+
+        upstream.send(Ok(Frame::data(Bytes::from("{\"n\":2}\n")))).await.unwrap();
+        drop(upstream);
+        let mut compressed = first.to_vec();
+        while let Some(frame) = body.frame().await {
+            compressed.extend_from_slice(&frame.unwrap().into_data().unwrap());
+        }
+        let mut decompressed = String::new();
+        async_compression::tokio::bufread::GzipDecoder::new(&compressed[..]).read_to_string(&mut decompressed).await.unwrap();
+        assert_eq!(decompressed, "{\"n\":1}\n{\"n\":2}\n");
+
+    }
+}
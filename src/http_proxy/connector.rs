@@ -0,0 +1,48 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use hyper::Uri;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpStream;
+use crate::global_state::GlobalState;
+
+/// Connects to backends like the regular HttpConnector, but with the connect timeout
+/// of the backend being connected to, so that it follows the current configuration.
+#[derive(Debug, Clone)]
+pub struct TimeoutConnector {
+    inner: HttpConnector,
+    state: Arc<GlobalState>
+}
+
+impl TimeoutConnector {
+    pub fn new(state: Arc<GlobalState>) -> Self {
+        let mut inner = HttpConnector::new();
+        // https is handled by the HttpsConnector wrapping this one
+        inner.enforce_http(false);
+        Self { inner, state }
+    }
+}
+
+impl tower_service::Service<Uri> for TimeoutConnector {
+    type Response = TokioIo<TcpStream>;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(|e| e.into())
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let mut inner = self.inner.clone();
+        let state = self.state.clone();
+        Box::pin(async move {
+            let host = uri.host().unwrap_or_default().trim_start_matches('[').trim_end_matches(']').to_string();
+            let port = uri.port_u16().unwrap_or(if uri.scheme() == Some(&hyper::http::uri::Scheme::HTTPS) { 443 } else { 80 });
+            let timeout = state.config.read().await.find_connect_timeout(&host, port);
+            inner.set_connect_timeout(Some(timeout));
+            inner.call(uri).await.map_err(|e| e.into())
+        })
+    }
+}
@@ -0,0 +1,154 @@
+use hyper::header::HeaderValue;
+use hyper::{Request, StatusCode};
+use crate::configuration::v2::ErrorPagesConfig;
+use super::{create_epic_string_full_body, EpicResponse};
+
+const DEFAULT_HTML_TEMPLATE : &str = "<!DOCTYPE html>
+<html>
+<head><meta charset=\"utf-8\"><title>$status_code $status_text</title></head>
+<body>
+<h1>$status_code $status_text</h1>
+<p>$message</p>
+<hr>
+<p>odd-box</p>
+</body>
+</html>
+";
+
+const DEFAULT_JSON_TEMPLATE : &str = "{\"status\":$status_code,\"error\":\"$status_text\",\"message\":\"$message\",\"host\":\"$host\"}";
+
+lazy_static::lazy_static! {
+    static ref PLACEHOLDERS : regex::Regex = regex::Regex::new(r"\$(status_code|status_text|message|host)").expect("the placeholder pattern is valid");
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorFormat {
+    Html,
+    Json,
+    Text
+}
+
+/// Picks the format with the highest quality in the Accept header. The first one wins if several have the same quality.
+pub fn preferred_format(accept:Option<&str>) -> ErrorFormat {
+    let mut preferred = (ErrorFormat::Text, 0.0);
+    for item in accept.unwrap_or_default().split(',') {
+        let mut parts = item.split(';').map(|x| x.trim());
+        let media_type = parts.next().unwrap_or_default().to_lowercase();
+        let quality = parts
+            .find_map(|x| x.strip_prefix("q="))
+            .and_then(|x| x.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        let format = match media_type.as_str() {
+            "text/html" | "application/xhtml+xml" => ErrorFormat::Html,
+            "application/json" => ErrorFormat::Json,
+            x if x.ends_with("+json") => ErrorFormat::Json,
+            "text/plain" | "text/*" | "*/*" => ErrorFormat::Text,
+            _ => continue
+        };
+        if quality > preferred.1 {
+            preferred = (format, quality);
+        }
+    }
+    preferred.0
+}
+
+fn json_escape(text:&str) -> String {
+    let quoted = serde_json::to_string(text).unwrap_or_default();
+    quoted.strip_prefix('"').and_then(|x| x.strip_suffix('"')).unwrap_or_default().to_string()
+}
+
+/// Replaces the placeholders in a template. Values are escaped, so they cannot add markup to the page.
+pub fn render(template:&str, status:StatusCode, message:&str, host:&str, escape:fn(&str) -> String) -> String {
+    PLACEHOLDERS.replace_all(template, |captures:&regex::Captures| match &captures[1] {
+        "status_code" => status.as_str().to_string(),
+        "status_text" => escape(status.canonical_reason().unwrap_or_default()),
+        "message" => escape(message),
+        _ => escape(host)
+    }).to_string()
+}
+
+/// What is needed to create an error response for a request, kept separately since the request itself is sent on to the site.
+#[derive(Debug, Clone)]
+pub struct ErrorPageContext {
+    host : String,
+    format : ErrorFormat,
+    pages : Option<ErrorPagesConfig>
+}
+
+impl ErrorPageContext {
+
+    pub fn new<B>(req:&Request<B>, host:&str, pages:Option<ErrorPagesConfig>) -> Self {
+        Self {
+            host: host.to_string(),
+            format: preferred_format(req.headers().get(hyper::header::ACCEPT).and_then(|x| x.to_str().ok())),
+            pages
+        }
+    }
+
+    /// Creates an error response in the format that the client prefers.
+    /// The message is shown to the client, so it should not contain any details about the site or the request.
+    pub async fn response(&self, status:StatusCode, message:&str) -> EpicResponse {
+
+        let template_path = match self.format {
+            ErrorFormat::Html => self.pages.as_ref().and_then(|x| x.html.clone()),
+            ErrorFormat::Json => self.pages.as_ref().and_then(|x| x.json.clone()),
+            ErrorFormat::Text => None
+        };
+        let template = match template_path {
+            Some(path) => match tokio::fs::read_to_string(&path).await {
+                Ok(template) => Some(template),
+                Err(e) => {
+                    tracing::warn!("Failed to read the error page template {path}, using the default template instead: {e}");
+                    None
+                }
+            },
+            None => None
+        };
+
+        let (body, content_type) = match self.format {
+            ErrorFormat::Html => (
+                render(template.as_deref().unwrap_or(DEFAULT_HTML_TEMPLATE), status, message, &self.host, super::static_files::html_escape),
+                "text/html; charset=utf-8"
+            ),
+            ErrorFormat::Json => (
+                render(template.as_deref().unwrap_or(DEFAULT_JSON_TEMPLATE), status, message, &self.host, json_escape),
+                "application/json"
+            ),
+            ErrorFormat::Text => (
+                format!("{} {}\n{message}\n", status.as_str(), status.canonical_reason().unwrap_or_default()),
+                "text/plain; charset=utf-8"
+            )
+        };
+
+        let mut response = EpicResponse::new(create_epic_string_full_body(&body));
+        *response.status_mut() = status;
+        response.headers_mut().insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn error_pages_follow_the_accept_header_and_escape_values() {
+
+        use crate::http_proxy::static_files::html_escape;
+
+        assert_eq!(preferred_format(None), ErrorFormat::Text);
+        assert_eq!(preferred_format(Some("text/html,application/xhtml+xml,*/*;q=0.8")), ErrorFormat::Html);
+        assert_eq!(preferred_format(Some("application/json")), ErrorFormat::Json);
+        assert_eq!(preferred_format(Some("application/problem+json")), ErrorFormat::Json);
+        assert_eq!(preferred_format(Some("text/html;q=0.5, application/json;q=0.9")), ErrorFormat::Json);
+        assert_eq!(preferred_format(Some("image/png")), ErrorFormat::Text);
+
+        let page = render("<h1>$status_code $status_text</h1><p>$message</p><p>$host</p>", hyper::StatusCode::BAD_GATEWAY, "a & b", "<script>.localtest.me", html_escape);
+        assert_eq!(page, "<h1>502 Bad Gateway</h1><p>a &amp; b</p><p>&lt;script&gt;.localtest.me</p>");
+
+        let error = crate::http_proxy::ProxyError::ForwardHeaderError;
+        assert_eq!(error.status_code(), hyper::StatusCode::BAD_REQUEST);
+
+    }
+}
@@ -1,11 +1,16 @@
 mod websockets;
 mod service;
 mod utils;
+pub mod auth;
+pub mod compression;
+pub mod static_files;
+pub mod error_pages;
+mod connector;
 use std::sync::Arc;
 
-use hyper::body::Incoming;
 use hyper_rustls::HttpsConnector;
-use hyper_util::client::legacy::{connect::HttpConnector, Client};
+use hyper_util::client::legacy::Client;
+pub use connector::TimeoutConnector;
 pub use service::*;
 use tokio::sync::mpsc::Sender;
 pub use utils::*;
@@ -26,7 +31,9 @@ pub struct ReverseProxyService {
     pub remote_addr : Option<std::net::SocketAddr>,
     pub tx: std::sync::Arc<tokio::sync::broadcast::Sender<ProcMessage>>,
     pub is_https_only:bool,
-    pub client: Client<HttpsConnector<HttpConnector>, Incoming>,
-    pub h2_client: Client<HttpsConnector<HttpConnector>, Incoming>,
+    /// Port of the tls listener, used when redirecting plain http requests to https
+    pub tls_port:u16,
+    pub client: Client<HttpsConnector<TimeoutConnector>, UpstreamBody>,
+    pub h2_client: Client<HttpsConnector<TimeoutConnector>, UpstreamBody>,
     pub resolved_target : Option<Arc<ReverseTcpProxyTarget>>
 }
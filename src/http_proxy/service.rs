@@ -1,20 +1,17 @@
 use std::borrow::Cow;
 use std::sync::Arc;
 use std::time::Duration;
-use axum::http::HeaderValue;
 use bytes::Bytes;
 use http_body::Frame;
 
 use http_body_util::{Either, Full, StreamBody};
 use hyper::service::Service;
-use hyper::{body::Incoming as IncomingBody, Request, Response};
-use hyper_rustls::HttpsConnector;
-use hyper_util::client::legacy::connect::HttpConnector;
-use hyper_util::client::legacy::Client;
+use hyper::{body::Incoming as IncomingBody, Request};
 use hyper_util::rt::TokioExecutor;
 use tokio_stream::wrappers::ReceiverStream;
 use std::future::Future;
 use std::pin::Pin;
+use crate::configuration::host_matches;
 use crate::global_state::GlobalState;
 use crate::tcp_proxy::{ManagedStream, ReverseTcpProxyTarget};
 use crate::types::app_state::ProcState;
@@ -77,6 +74,9 @@ pub type EpicBody =
 
 pub type EpicResponse = hyper::Response<EpicBody>;
 
+/// Body of the requests sent to sites. Retried requests have no body, so they are sent again with an empty one.
+pub type UpstreamBody = Either<IncomingBody, Full<Bytes>>;
+
 
 pub fn create_response_channel(buf_size:usize) -> (
     tokio::sync::mpsc::Sender<Result<Frame<Bytes>, CustomError>>,
@@ -87,6 +87,10 @@ pub fn create_epic_string_full_body(text:&str) -> EpicBody {
     EpicBody::Right(FullOrStreamBody::Left(Full::new(Bytes::from(text.to_owned()))))
 }
 
+pub fn create_epic_full_body(bytes:Bytes) -> EpicBody {
+    EpicBody::Right(FullOrStreamBody::Left(Full::new(bytes)))
+}
+
 pub fn create_stream_response(rx:tokio::sync::mpsc::Receiver<Result<Frame<Bytes>, CustomError>>) -> EpicResponse {
     EpicResponse::new(EpicBody::Right(Either::Right(StreamBody::new(ReceiverStream::new(rx)))))
 }
@@ -97,12 +101,52 @@ pub async fn create_simple_response_from_incoming(res:WrappedNormalResponse) ->
     Ok(EpicResponse::from_parts(p,Either::Left(b)))
 }
 
-fn handle_ws(svc:ReverseProxyService,mut req:hyper::Request<hyper::body::Incoming>) -> Result<EpicResponse,CustomError> {
+async fn handle_ws(svc:ReverseProxyService,mut req:hyper::Request<hyper::body::Incoming>) -> Result<EpicResponse,CustomError> {
+
+    let req_host_name = match request_host_name(&req, None) {
+        Ok(x) => x,
+        Err(e) => return Ok(invalid_host_response(&req, &svc.state, e).await)
+    };
+
+    let ws_target = crate::http_proxy::websockets::find_target(&req, &req_host_name, &svc.state).await;
+    let mut ws_target = match ws_target {
+        Some(x) => x,
+        None => {
+            tracing::debug!("No target is configured to handle websocket requests to {req_host_name}");
+            let error_pages = svc.state.config.read().await.find_error_pages(&req_host_name);
+            let error_page = super::error_pages::ErrorPageContext::new(&req, &req_host_name, error_pages);
+            return Ok(error_page.response(StatusCode::MISDIRECTED_REQUEST, "No site is configured for this host name.").await)
+        }
+    };
+
+    let client_addr = svc.remote_addr.expect("there must always be a client");
+    for host_name in [ws_target.req_host_name.as_str(), ws_target.target.site_name()] {
+        if !svc.state.config.read().await.client_is_allowed(client_addr.ip(), Some(host_name)) {
+            return Ok(forbidden_response(&svc.state, client_addr, host_name))
+        }
+    }
+
+    let rate_limits = svc.state.config.read().await.find_rate_limits(ws_target.target.site_name());
+    if let Err(retry_after) = crate::rate_limit::check(&rate_limits, client_addr.ip(), req.headers(), req.uri().path()) {
+        return Ok(too_many_requests_response(client_addr, ws_target.target.site_name(), retry_after))
+    }
+
+    if let Some(auth) = ws_target.target.auth() {
+        match super::auth::authorize(&auth, &req, client_addr.ip(), svc.is_https_only, &ws_target.req_host_name).await {
+            super::auth::AuthResult::Allowed(copied_headers) => ws_target.auth_headers = copied_headers,
+            super::auth::AuthResult::Denied(response) => return Ok(response)
+        }
+    }
 
-    let (response, websocket) = hyper_tungstenite::upgrade(&mut req, None)
+    let (mut response, websocket) = hyper_tungstenite::upgrade(&mut req, None)
         .map_err(|e|CustomError(format!("{e:?}")))?;
+
+    if let Some(client_addr) = svc.remote_addr {
+        let context = ws_target.target.header_rule_context(client_addr.ip(), &ws_target.req_host_name, svc.is_https_only);
+        crate::configuration::v2::apply_header_rules(&ws_target.target.response_header_rules(), response.headers_mut(), &context);
+    }
     
-    tokio::spawn(async move {crate::http_proxy::websockets::handle_ws(req, svc,websocket).await });
+    tokio::spawn(async move {crate::http_proxy::websockets::handle_ws(req, svc,websocket,ws_target).await });
     let (p,b) = response.into_parts();
     Ok(EpicResponse::from_parts(p,Either::Right(Either::Left(b))))
 }
@@ -118,10 +162,13 @@ impl<'a> Service<Request<hyper::body::Incoming>> for ReverseProxyService {
         tracing::trace!("INCOMING REQ: {:?}",req);
         tracing::trace!("VERSION: {:?}",req.version());
 
+        let client_addr = self.remote_addr.expect("there must always be a client");
+
         // handle websocket upgrades separately
         if hyper_tungstenite::is_upgrade_request(&req) {
+            let access_log = pending_request(&req, client_addr, None);
             let res =  handle_ws(self.clone(),req);
-            return Box::pin(async move { res })         
+            return Box::pin(async move { res.await.map(|x| access_log.complete(x)) })         
         }
 
         //handle h2 stream handler test req
@@ -179,23 +226,16 @@ impl<'a> Service<Request<hyper::body::Incoming>> for ReverseProxyService {
         }
         
         // handle normal proxy path
+        let access_log = pending_request(&req, client_addr, self.resolved_target.as_deref());
         let f = handle_http_request(
-            self.remote_addr.expect("there must always be a client"),
+            client_addr,
             req,
-            self.tx.clone(),
-            self.state.clone(),
-            self.is_https_only,
-            self.client.clone(),
-            self.h2_client.clone(),
-            self.resolved_target.clone()
+            self.clone()
         );
         
         return Box::pin(async move {
             match f.await {
-                Ok(mut x) => {
-                    x.headers_mut().insert("odd-box", HeaderValue::from_static("YEAH BABY YEAH"));
-                    Ok(x)
-                },
+                Ok(x) => Ok(access_log.complete(x)),
                 Err(e) => {
                     Err(CustomError(format!("{e:?}")))
                 },
@@ -206,28 +246,47 @@ impl<'a> Service<Request<hyper::body::Incoming>> for ReverseProxyService {
     }
 }
 
+fn request_host_name<B>(req:&Request<B>, peeked_target:Option<&ReverseTcpProxyTarget>) -> Result<String,CustomError> {
+    Ok(if let Some(t) = peeked_target {
+        t.host_name.to_string()
+    } else if let Some(hh) = req.headers().get("host") { 
+        let hostname_and_port = hh.to_str().map_err(|e|CustomError(format!("{e:?}")))?.to_string();
+        hostname_and_port.split(':').collect::<Vec<&str>>()[0].to_owned()
+    } else { 
+        req.uri().authority().ok_or(CustomError(String::from("No hostname and no Authority found")))?.host().to_string()
+    })
+}
+
+// every response to a request is logged, including the ones that odd-box creates itself such as redirects and denied requests.
+// requests without a valid host name are logged without one.
+fn pending_request<B>(req:&Request<B>, client_addr:std::net::SocketAddr, peeked_target:Option<&ReverseTcpProxyTarget>) -> crate::access_log::PendingRequest {
+    let host_name = request_host_name(req, peeked_target).unwrap_or(String::from("-"));
+    crate::access_log::PendingRequest::new(req, &host_name, client_addr.ip())
+}
+
+// requests without a usable host name cannot be matched to a site, so only the global error pages apply
+async fn invalid_host_response<B>(req:&Request<B>, state:&GlobalState, error:CustomError) -> EpicResponse {
+    tracing::debug!("Rejecting request without a valid host name: {error:?}");
+    let error_pages = state.config.read().await.error_pages.clone();
+    let error_page = super::error_pages::ErrorPageContext::new(req, "-", error_pages);
+    error_page.response(StatusCode::BAD_REQUEST, "The request does not have a valid host name.").await
+}
+
 #[allow(dead_code)]
 async fn handle_http_request(
     client_ip: std::net::SocketAddr, 
-    req: Request<hyper::body::Incoming>,
-    tx: Arc<tokio::sync::broadcast::Sender<ProcMessage>>,
-    state: Arc<GlobalState>,
-    is_https:bool,
-    client:  Client<HttpsConnector<HttpConnector>, hyper::body::Incoming>,
-    h2_client: Client<HttpsConnector<HttpConnector>, hyper::body::Incoming>,
-    peeked_target: Option<Arc<ReverseTcpProxyTarget>>
-
+    mut req: Request<hyper::body::Incoming>,
+    svc: ReverseProxyService
 ) -> Result<EpicResponse, CustomError> {
     
-    let req_host_name = 
-        if let Some(t) = &peeked_target {
-            t.host_name.to_string()
-        } else if let Some(hh) = req.headers().get("host") { 
-            let hostname_and_port = hh.to_str().map_err(|e|CustomError(format!("{e:?}")))?.to_string();
-            hostname_and_port.split(":").collect::<Vec<&str>>()[0].to_owned()
-        } else { 
-            req.uri().authority().ok_or(CustomError(format!("No hostname and no Authority found")))?.host().to_string()
-        };
+    let state = svc.state.clone();
+    let is_https = svc.is_https_only;
+    let peeked_target = svc.resolved_target.clone();
+
+    let req_host_name = match request_host_name(&req, peeked_target.as_deref()) {
+        Ok(x) => x,
+        Err(e) => return Ok(invalid_host_response(&req, &state, e).await)
+    };
 
 
     
@@ -258,28 +317,115 @@ async fn handle_http_request(
         })
         .unwrap_or_else(std::collections::HashMap::new);
 
-    if let Some(r) = intercept_local_commands(&req_host_name,&params,req_path,tx.clone()).await {
+    if !state.config.read().await.client_is_allowed(client_ip.ip(), Some(&req_host_name)) {
+        return Ok(forbidden_response(&state, client_ip, &req_host_name))
+    }
+
+    if let Some(r) = intercept_local_commands(&req_host_name,&params,req_path,svc.tx.clone()).await {
         return Ok(r)
     }
+
+    // plain http requests for sites that require tls are sent to the tls port
+    if !is_https && state.config.read().await.force_https(&req_host_name) {
+        let path_and_query = req.uri().path_and_query().map(|x| x.as_str()).unwrap_or("/");
+        let location = super::utils::https_redirect_location(&req_host_name, svc.tls_port, path_and_query);
+        // 308 keeps the method and body of the request, which 301 does not guarantee
+        let status = if req.method() == Method::GET || req.method() == Method::HEAD { StatusCode::MOVED_PERMANENTLY } else { StatusCode::PERMANENT_REDIRECT };
+        tracing::trace!("Redirecting plain http request for {req_host_name} to {location}");
+        return hyper::Response::builder()
+            .status(status)
+            .header(hyper::header::LOCATION, location)
+            .body(create_epic_string_full_body(""))
+            .map_err(|e|CustomError(format!("{e:?}")))
+    }
+
+    // redirect sites and rewrite rules are handled before we decide which site should serve the request
+    let rewrite = state.config.read().await.find_rewrite(&req_host_name, req.uri().path(), req.uri().query());
+    match rewrite {
+        Some(crate::configuration::v2::RewriteOutcome::Redirect { status_code, location }) => {
+            tracing::trace!("Redirecting request for {req_host_name} to {location} ({status_code})");
+            return hyper::Response::builder()
+                .status(status_code)
+                .header(hyper::header::LOCATION, location)
+                .body(create_epic_string_full_body(""))
+                .map_err(|e|CustomError(format!("{e:?}")))
+        },
+        Some(crate::configuration::v2::RewriteOutcome::Rewrite { path_and_query }) => {
+            tracing::trace!("Rewrote request for {req_host_name} to {path_and_query}");
+            if let Err(e) = set_path_and_query(&mut req, &path_and_query) {
+                return Ok(invalid_path_response(&req, &state, &req_host_name, e).await)
+            }
+        },
+        None => {}
+    }
+
+    // path rules can send the request to another site than the one matching the host name
+    let path_rule = state.config.read().await.find_path_rule(&req_host_name, req.uri().path());
+    let client_is_trusted_proxy = state.config.read().await.is_trusted_proxy(client_ip.ip());
+    let (site_host_name,peeked_target) = if let Some(rule) = path_rule {
+        tracing::trace!("Path rule '{}' matched request for {req_host_name}, routing to {}",rule.path,rule.target);
+        if rule.strip_prefix.unwrap_or_default() {
+            let original_path_and_query = req.uri().path_and_query().map(|x| x.as_str()).unwrap_or("/");
+            let new_path_and_query = rule.rewrite_path_and_query(original_path_and_query);
+            if let Err(e) = set_path_and_query(&mut req, &new_path_and_query) {
+                return Ok(invalid_path_response(&req, &state, &req_host_name, e).await)
+            }
+        }
+        (rule.target,None)
+    } else {
+        (req_host_name.clone(),peeked_target)
+    };
+
+    let error_pages = state.config.read().await.find_error_pages(&site_host_name);
+    let error_page = super::error_pages::ErrorPageContext::new(&req, &req_host_name, error_pages);
+
+    // allow/deny lists and auth belong to the site that handles the request, so path rules cannot be used to get around them
+    if site_host_name != req_host_name && !state.config.read().await.client_is_allowed(client_ip.ip(), Some(&site_host_name)) {
+        return Ok(forbidden_response(&state, client_ip, &site_host_name))
+    }
+    let rate_limits = state.config.read().await.find_rate_limits(&site_host_name);
+    if let Err(retry_after) = crate::rate_limit::check(&rate_limits, client_ip.ip(), req.headers(), req.uri().path()) {
+        return Ok(too_many_requests_response(client_ip, &site_host_name, retry_after))
+    }
+
+    let auth = state.config.read().await.find_auth(&site_host_name);
+    if let Some(auth) = auth {
+        match super::auth::authorize(&auth, &req, client_ip.ip(), is_https, &req_host_name).await {
+            super::auth::AuthResult::Allowed(copied_headers) => super::auth::apply_copied_headers(&auth, req.headers_mut(), copied_headers),
+            super::auth::AuthResult::Denied(response) => return Ok(response)
+        }
+    }
+
+    let static_site = state.config.read().await.find_static_site(&site_host_name);
+    if let Some(site) = static_site {
+        // only the head of the request is needed, the body is never read
+        let mut head = Request::new(());
+        *head.method_mut() = req.method().clone();
+        *head.version_mut() = req.version();
+        *head.uri_mut() = req.uri().clone();
+        *head.headers_mut() = req.headers().clone();
+        let compression = super::compression::ResponseCompression::negotiate(site.compression.clone(), &head);
+        let mut response = super::static_files::serve(&site, &head, &error_page).await;
+        if is_https {
+            if let Some(value) = site.hsts.as_ref().and_then(|x| hyper::header::HeaderValue::from_str(&x.header_value()).ok()) {
+                response.headers_mut().insert(hyper::header::STRICT_TRANSPORT_SECURITY, value);
+            }
+        }
+        response.extensions_mut().insert(crate::access_log::Upstream { address: site.dir.clone(), responded: false });
+        return Ok(match compression {
+            Some(compression) => compression.apply(response),
+            None => response
+        })
+    }
     
     let found_hosted_target = 
         if let Some(p) = peeked_target.as_ref().and_then(|x| x.hosted_target_config.clone()) {
             Some(p)
         } else {
             let cfg_guard = state.config.read().await;
-            if let Some(processes) = &cfg_guard.hosted_process {
-                if let Some(pp) = processes.iter().find(|p| {
-                    req_host_name == p.host_name
-                    || p.capture_subdomains.unwrap_or_default() 
-                    && req_host_name.ends_with(&format!(".{}",p.host_name))
-                }) {
-                    Some(pp.clone())
-                } else {
-                    None
-                }
-            } else {
-                None
-            }
+            cfg_guard.hosted_process.iter().flatten()
+                .find(|p| host_matches(&site_host_name, &p.host_name, p.capture_subdomains))
+                .cloned()
         };
 
 
@@ -296,35 +442,32 @@ async fn handle_http_request(
         };
 
         match current_target_status {
-            Some(ProcState::Running) | Some(ProcState::Faulty) | Some(ProcState::Starting) => {},
+            Some(ProcState::Running) | Some(ProcState::Ready) | Some(ProcState::Faulty) | Some(ProcState::CrashLooping) | Some(ProcState::Starting) => {},
             _ => {
                 // auto start site in case its been disabled by other requests
-                _ = tx.send(super::ProcMessage::Start(target_proc_cfg.host_name.to_owned())).map_err(|e|format!("{e:?}"));
+                _ = svc.tx.send(super::ProcMessage::Start(target_proc_cfg.host_name.to_owned())).map_err(|e|format!("{e:?}"));
             }
         }
 
         
         if let Some(cts) = current_target_status {
-            if cts == crate::ProcState::Stopped || cts == crate::ProcState::Starting || cts == crate::ProcState::Faulty {
-                match req.method() {
-                    &Method::GET => {
-                        if let Some(ua) = req.headers().get("user-agent") {
-                            let hv = ua.to_str().unwrap_or_default().to_uppercase() ;
-                            // we only want to risk showing the please wait page to browsers..
-                            // not perfect but should be good enough for now..?
-                            // todo: opt in/out thru config ?
-                            if hv.contains("MOZILLA") || hv.contains("SAFARI") || hv.contains("CHROME") || hv.contains("EDGE") {
-                                return Ok(EpicResponse::new(create_epic_string_full_body(&please_wait_response())))
-                            }
-                        } else {
-                            tokio::time::sleep(Duration::from_secs(5)).await
+            if cts == crate::ProcState::Stopped || cts == crate::ProcState::Starting || cts == crate::ProcState::Faulty || cts == crate::ProcState::CrashLooping {
+                if req.method() == Method::GET {
+                    if let Some(ua) = req.headers().get("user-agent") {
+                        let hv = ua.to_str().unwrap_or_default().to_uppercase() ;
+                        // we only want to risk showing the please wait page to browsers..
+                        // not perfect but should be good enough for now..?
+                        // todo: opt in/out thru config ?
+                        if hv.contains("MOZILLA") || hv.contains("SAFARI") || hv.contains("CHROME") || hv.contains("EDGE") {
+                            return Ok(EpicResponse::new(create_epic_string_full_body(&please_wait_response())))
                         }
-                    }  
-                    _ => {
-                        // we do this to give services some time to wake up instead of failing requests while cold-starting sites
-                        tokio::time::sleep(Duration::from_secs(5)).await
-                    }           
-                }                 
+                    }
+                }
+                // hold the request until the site is ready instead of failing it while cold-starting the site
+                if crate::proc_host::wait_until_ready(&state, &target_proc_cfg.host_name, target_proc_cfg.get_id(), target_proc_cfg.startup_timeout()).await.is_none() {
+                    tracing::warn!("{} did not become ready in time.",target_proc_cfg.host_name);
+                    return Ok(error_page.response(StatusCode::SERVICE_UNAVAILABLE, "The site is starting, please try again shortly.").await)
+                }
             }
         }
 
@@ -332,7 +475,8 @@ async fn handle_http_request(
         let mut wait_count = 0;
         loop {
             if wait_count > 100 {
-                return Err(CustomError(format!("No active port found for {req_host_name}.")))
+                tracing::warn!("No active port found for {req_host_name}.");
+                return Ok(error_page.response(StatusCode::SERVICE_UNAVAILABLE, "The site is not available right now.").await)
             }
             if let Some(info) = crate::PROC_THREAD_MAP.get(target_proc_cfg.get_id()) {
                 if info.pid.is_some() {
@@ -342,7 +486,8 @@ async fn handle_http_request(
                     }
                 };
             } else {
-                return Err(CustomError("No site info found!".to_string()))
+                tracing::warn!("No site info found for {}.",target_proc_cfg.host_name);
+                return Ok(error_page.response(StatusCode::SERVICE_UNAVAILABLE, "The site is not available right now.").await)
             };
             wait_count += 1;
             tokio::time::sleep(Duration::from_millis(100)).await;
@@ -387,30 +532,37 @@ async fn handle_http_request(
 
         let target_cfg = target_proc_cfg.clone();
         let hints = target_cfg.hints.clone();
-        let target = crate::http_proxy::Target::Proc(target_cfg);
+        let target = crate::http_proxy::Target::Proc(Box::new(target_cfg));
+        let compression = super::compression::ResponseCompression::negotiate(target.compression(), &req);
 
         let result = 
             proxy(
                 &parsed_host_name,
-                is_https,
                 state.clone(),
-                req,
-                &skip_dns_for_local_target_url,
+                req.map(Either::Left),
                 target,
-                client_ip,
-                client,
-                h2_client,
-                &target_url,
-                enforce_https,
-                crate::configuration::v2::Backend {
-                    hints: hints,
-                    address: parsed_host_name.to_string(),
-                    port: port,
-                    https: Some(enforce_https)
-                }
+                super::ProxyRequestContext {
+                    client_ip,
+                    client_is_trusted_proxy,
+                    original_connection_is_https: is_https,
+                    target_url: skip_dns_for_local_target_url,
+                    backend: crate::configuration::v2::Backend {
+                        hints,
+                        address: parsed_host_name.to_string(),
+                        port,
+                        https: Some(enforce_https),
+                        health_check: None,
+                        weight: None,
+                        proxy_protocol: None,
+                        timeouts: None,
+                        outlier_detection: None
+                    }
+                },
+                svc.client.clone(),
+                svc.h2_client.clone()
             ).await;
 
-        map_result(&target_url,result).await
+        map_result(&target_url,result,compression,&error_page).await
     }
 
     else {
@@ -418,39 +570,66 @@ async fn handle_http_request(
         if let Some(peeked_remote_config) = peeked_target.and_then(|x| x.remote_target_config.clone() ) {
 
             return perform_remote_forwarding(
-                req_host_name,is_https,
-                state.clone(),
+                &svc,
+                req_host_name,
                 client_ip,
                 &peeked_remote_config,
-                req,client.clone(),
-                h2_client.clone()
+                req,
+                client_is_trusted_proxy,
+                &error_page
             ).await
         }
-        else if let Some(remote_target_cfg) = &state.config.read().await.remote_target.iter().flatten().find(|p|{
-            //tracing::info!("comparing incoming req: {} vs {} ",req_host_name,p.host_name);
-            req_host_name == p.host_name
-            || p.capture_subdomains.unwrap_or_default() && req_host_name.ends_with(&format!(".{}",p.host_name))
-        }) {
+        else if let Some(remote_target_cfg) = &state.config.read().await.remote_target.iter().flatten().find(|p| host_matches(&site_host_name, &p.host_name, p.capture_subdomains)) {
             return perform_remote_forwarding(
-                req_host_name,is_https,
-                state.clone(),
+                &svc,
+                req_host_name,
                 client_ip,
                 remote_target_cfg,
-                req,client.clone(),
-                h2_client.clone()
+                req,
+                client_is_trusted_proxy,
+                &error_page
             ).await
         }
 
         tracing::warn!("Received request that does not match any known target: {:?}", req_host_name);
-        let body_str = format!("Sorry, I don't know how to proxy this request.. {:?}", req);
-        
-        let mut response = EpicResponse::new(create_epic_string_full_body(&body_str));
-        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
-        Ok(response)
+        Ok(error_page.response(StatusCode::MISDIRECTED_REQUEST, "No site is configured for this host name.").await)
     }
 
 }
 
+fn forbidden_response(state:&GlobalState, client_ip:std::net::SocketAddr, host_name:&str) -> EpicResponse {
+    tracing::info!("Denied request from {client_ip} to {host_name} as it is not allowed by the allow/deny lists");
+    state.app_state.statistics.denied_connections.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    let mut response = EpicResponse::new(create_epic_string_full_body("403 Forbidden"));
+    *response.status_mut() = StatusCode::FORBIDDEN;
+    response
+}
+
+fn too_many_requests_response(client_ip:std::net::SocketAddr, host_name:&str, retry_after:Duration) -> EpicResponse {
+    tracing::debug!("Rate limited request from {client_ip} to {host_name}");
+    let mut response = EpicResponse::new(create_epic_string_full_body("429 Too Many Requests"));
+    *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+    // retry-after is in whole seconds, so we round up to avoid clients retrying too early
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    response.headers_mut().insert(hyper::header::RETRY_AFTER, hyper::header::HeaderValue::from(seconds));
+    response
+}
+
+// rewrite rules and stripped path prefixes can produce a path that is not a valid uri
+async fn invalid_path_response<B>(req:&Request<B>, state:&GlobalState, req_host_name:&str, error:CustomError) -> EpicResponse {
+    tracing::debug!("Rejecting request for {req_host_name} with an invalid rewritten path: {error:?}");
+    let error_pages = state.config.read().await.find_error_pages(req_host_name);
+    let error_page = super::error_pages::ErrorPageContext::new(req, req_host_name, error_pages);
+    error_page.response(StatusCode::BAD_REQUEST, "The requested path is not valid.").await
+}
+
+fn set_path_and_query(req:&mut Request<IncomingBody>, path_and_query:&str) -> Result<(),CustomError> {
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse().map_err(|e|CustomError(format!("{e:?}")))?);
+    *req.uri_mut() = hyper::Uri::from_parts(parts).map_err(|e|CustomError(format!("{e:?}")))?;
+    Ok(())
+}
+
 fn get_subdomain(requested_hostname: &str, backend_hostname: &str) -> Option<String> {
     if requested_hostname == backend_hostname { return None };
     if requested_hostname.to_uppercase().ends_with(&backend_hostname.to_uppercase()) {
@@ -464,125 +643,229 @@ fn get_subdomain(requested_hostname: &str, backend_hostname: &str) -> Option<Str
 }
 
 async fn perform_remote_forwarding(
+    svc:&ReverseProxyService,
+    req_host_name:String,
+    client_ip:std::net::SocketAddr,
+    remote_target_config:&crate::configuration::v2::RemoteSiteConfig,
+    mut req:hyper::Request<IncomingBody>,
+    client_is_trusted_proxy: bool,
+    error_page: &super::error_pages::ErrorPageContext
+) -> Result<EpicResponse,CustomError> {
+
+    // compression is applied last so that the cache only holds the responses as sent by the site
+    let compression = super::compression::ResponseCompression::negotiate(remote_target_config.compression.clone(), &req);
+    let apply_compression = |response:EpicResponse| match compression {
+        Some(compression) => compression.apply(response),
+        None => response
+    };
+
+    let cache_config = match &remote_target_config.cache {
+        Some(cache_config) if crate::http_cache::request_is_cacheable(&req) => cache_config.clone(),
+        _ => {
+            let result = forward_to_remote_backend(svc,req_host_name,client_ip,remote_target_config,req,client_is_trusted_proxy,error_page).await;
+            return result.map(apply_compression)
+        }
+    };
+
+    let site = remote_target_config.host_name.clone();
+    let key = crate::http_cache::cache_key(&req_host_name, req.uri());
+    let client_headers = req.headers().clone();
+    let cached = crate::http_cache::lookup(&cache_config, &site, &key, &client_headers).await;
+
+    let mut revalidating_cached_copy = false;
+    if let Some(cached) = &cached {
+        if cached.is_fresh() && !crate::http_cache::request_wants_revalidation(&client_headers) {
+            tracing::trace!("Serving {key} from the cache of {site}");
+            let mut response = cached.to_response("HIT", &client_headers);
+            response.extensions_mut().insert(crate::access_log::Upstream { address: String::from("cache"), responded: false });
+            return Ok(apply_compression(response))
+        }
+        revalidating_cached_copy = cached.add_validators(req.headers_mut());
+    }
+
+    let result = forward_to_remote_backend(svc,req_host_name,client_ip,remote_target_config,req,client_is_trusted_proxy,error_page).await;
+
+    let mut response = match (result,cached) {
+        // a 304 for the validators of the client says nothing about our copy, so it is passed on as it is
+        (Ok(response),Some(cached)) if response.status() == StatusCode::NOT_MODIFIED && revalidating_cached_copy => {
+            let refreshed = crate::http_cache::refresh(&cache_config, cached, response.headers()).await;
+            refreshed.to_response("REVALIDATED", &client_headers)
+        },
+        // failed calls are returned as 5xx responses by map_result, so errors from the site and from the proxy are handled the same way
+        (Ok(response),Some(cached)) if response.status().is_server_error() && cache_config.serve_stale_on_error.unwrap_or(true) => {
+            tracing::warn!("Serving a stale copy of {key} as {site} responded with {}", response.status());
+            cached.to_response("STALE", &client_headers)
+        },
+        (Err(e),Some(cached)) if cache_config.serve_stale_on_error.unwrap_or(true) => {
+            tracing::warn!("Serving a stale copy of {key} as the call to {site} failed: {e:?}");
+            cached.to_response("STALE", &client_headers)
+        },
+        (Ok(response),_) => crate::http_cache::store_response(&cache_config, &site, &key, &client_headers, response).await,
+        (Err(e),_) => return Err(e)
+    };
+
+    if response.extensions().get::<crate::access_log::Upstream>().is_none() {
+        response.extensions_mut().insert(crate::access_log::Upstream { address: String::from("cache"), responded: false });
+    }
+
+    Ok(apply_compression(response))
+}
+
+async fn forward_to_remote_backend(
+    svc:&ReverseProxyService,
     req_host_name:String,
-    _is_https:bool,
-    state: Arc<GlobalState>,
     client_ip:std::net::SocketAddr,
     remote_target_config:&crate::configuration::v2::RemoteSiteConfig,
     req:hyper::Request<IncomingBody>,
-    client:  Client<HttpsConnector<HttpConnector>, hyper::body::Incoming>,
-    h2_client: Client<HttpsConnector<HttpConnector>, hyper::body::Incoming>,
+    client_is_trusted_proxy: bool,
+    error_page: &super::error_pages::ErrorPageContext
 ) -> Result<EpicResponse,CustomError> {
+
+    let state = &svc.state;
     
     
     let mut original_path_and_query = req.uri().path_and_query()
-        .and_then(|x| Some(x.as_str())).unwrap_or_default();
-    if original_path_and_query == "/" { original_path_and_query = ""}
+        .and_then(|x| Some(x.as_str())).unwrap_or_default().to_string();
+    if original_path_and_query == "/" { original_path_and_query = String::new() }
    
-    let next_backend_target = if let Some(b) = remote_target_config.next_backend(&state, crate::configuration::v2::BackendFilter::Any,false).await {
-        b
-    } else {
-        return Err(CustomError("No backend found".to_string()))
-    };
-    
-    // if a target is marked with http, we wont try to use http
-    let enforce_https = next_backend_target.https.unwrap_or_default();
-   
-    let scheme = if enforce_https { "https" } else { "http" }; 
-    
-    let resolved_host_name = {
+    let mut lb_context = crate::configuration::v2::LoadBalancingContext::from_request(client_ip.ip(), req.headers());
 
-        if remote_target_config.forward_subdomains.unwrap_or_default() {
-            if let Some(subdomain) = get_subdomain(&req_host_name, &remote_target_config.host_name) {
-            //tracing::debug!("remote forward terminating proxy rewrote subdomain: {subdomain}!");
-                format!("{subdomain}.{}", &next_backend_target.address)
+    // requests can only be sent again if there is no body, and upgrades are never retried
+    let retry_policy = remote_target_config.retry.clone().filter(|_| 
+        hyper::body::Body::is_end_stream(req.body()) && !req.headers().contains_key(hyper::header::UPGRADE)
+    );
+    let method = req.method().clone();
+    let request_head = retry_policy.as_ref().map(|_| (req.uri().clone(), req.version(), req.headers().clone()));
+    let mut first_request = Some(req.map(Either::Left));
+    let mut last_failure : Option<(String,Result<crate::http_proxy::ProxyCallResult,crate::http_proxy::ProxyError>)> = None;
+    let mut attempt = 1;
+
+    loop {
+
+        let next_backend_target = if let Some(b) = remote_target_config.next_backend(state, crate::configuration::v2::BackendFilter::Any,&lb_context).await {
+            b
+        } else if let Some((target_url,result)) = last_failure {
+            // every backend has been tried
+            return map_result(&target_url,result,None,error_page).await
+        } else {
+            tracing::warn!("No backend found for {}.",remote_target_config.host_name);
+            return Ok(error_page.response(StatusCode::SERVICE_UNAVAILABLE, "No backend is available for this site right now.").await)
+        };
+        
+        // if a target is marked with http, we wont try to use http
+        let enforce_https = next_backend_target.https.unwrap_or_default();
+       
+        let scheme = if enforce_https { "https" } else { "http" }; 
+        
+        let resolved_host_name = {
+
+            if remote_target_config.forward_subdomains.unwrap_or_default() {
+                if let Some(subdomain) = get_subdomain(&req_host_name, &remote_target_config.host_name) {
+                //tracing::debug!("remote forward terminating proxy rewrote subdomain: {subdomain}!");
+                    format!("{subdomain}.{}", &next_backend_target.address)
+                } else {
+                    next_backend_target.address.clone()
+                }
             } else {
                 next_backend_target.address.clone()
             }
-        } else {
-            next_backend_target.address.clone()
+        };
+            
+        let target_url = format!("{scheme}://{}:{}{}",
+            resolved_host_name,
+            next_backend_target.port,
+            original_path_and_query
+        );
+
+        let req = match (first_request.take(), &request_head) {
+            (Some(req),_) => req,
+            (None,Some((uri,version,headers))) => {
+                let mut req = hyper::Request::new(Either::Right(Full::new(Bytes::new())));
+                *req.method_mut() = method.clone();
+                *req.uri_mut() = uri.clone();
+                *req.version_mut() = *version;
+                *req.headers_mut() = headers.clone();
+                req
+            },
+            (None,None) => unreachable!("requests are only sent again when they can be retried")
+        };
+
+        let backend_key = next_backend_target.key();
+
+        //tracing::info!("Incoming request to '{}' for remote proxy target {target_url}",next_backend_target.address);
+        let result = 
+            proxy(
+                &req_host_name,
+                state.clone(),
+                req,
+                crate::http_proxy::Target::Remote(Box::new(remote_target_config.clone())),
+                super::ProxyRequestContext {
+                    client_ip,
+                    client_is_trusted_proxy,
+                    original_connection_is_https: svc.is_https_only,
+                    target_url: target_url.clone(),
+                    backend: next_backend_target.clone()
+                },
+                svc.client.clone(),
+                svc.h2_client.clone()
+            ).await;
+
+        if let Some(outcome) = crate::outlier_detection::Outcome::of_proxy_call(&result) {
+            crate::outlier_detection::record(state, &remote_target_config.host_name, &next_backend_target, outcome);
         }
-    };
-        
-    let target_url = format!("{scheme}://{}:{}{}",
-        resolved_host_name,
-        next_backend_target.port,
-        original_path_and_query
-    );
 
-    //tracing::info!("Incoming request to '{}' for remote proxy target {target_url}",next_backend_target.address);
-    let result = 
-        proxy(
-            &req_host_name,
-            next_backend_target.https.unwrap_or_default(),
-            state.clone(),
-            req,
-            &target_url,
-            crate::http_proxy::Target::Remote(remote_target_config.clone()),
-            client_ip,
-            client,
-            h2_client,
-            &target_url,
-            next_backend_target.https.unwrap_or_default(),
-            next_backend_target
-        ).await;
-
-    map_result(&target_url,result).await
+        let policy = match &retry_policy {
+            Some(policy) if attempt < policy.attempts() => policy,
+            _ => return map_result(&target_url,result,None,error_page).await
+        };
+        let reason = match &result {
+            Err(crate::http_proxy::ProxyError::LegacyError(e)) if e.is_connect() => format!("the connection failed: {e:?}"),
+            Ok(crate::http_proxy::ProxyCallResult::NormalResponse(response)) if policy.should_retry_status(&method, response.status()) => {
+                format!("it responded with {}", response.status())
+            },
+            _ => return map_result(&target_url,result,None,error_page).await
+        };
+
+        tracing::warn!("Retrying the request to {} on another backend as {backend_key} failed (attempt {attempt}): {reason}",remote_target_config.host_name);
+        state.app_state.statistics.retried_requests.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        lb_context.excluded_backends.push(backend_key);
+        tokio::time::sleep(policy.backoff_for_retry(attempt)).await;
+        last_failure = Some((target_url,result));
+        attempt += 1;
+    }
 
 }
 
-async fn map_result(target_url:&str,result:Result<crate::http_proxy::ProxyCallResult,crate::http_proxy::ProxyError>) -> Result<EpicResponse,CustomError> {
+async fn map_result(
+    target_url:&str,
+    result:Result<crate::http_proxy::ProxyCallResult,crate::http_proxy::ProxyError>,
+    compression:Option<super::compression::ResponseCompression>,
+    error_page:&super::error_pages::ErrorPageContext
+) -> Result<EpicResponse,CustomError> {
     
-    match result {
+    let upstream_responded = result.is_ok();
+    let response = match result {
         Ok(super::ProxyCallResult::EpicResponse(epic_response)) => {
-            return Ok(epic_response)
+            Ok(epic_response)
         }
         Ok(crate::http_proxy::ProxyCallResult::NormalResponse(response)) => {
-                return create_simple_response_from_incoming(response).await;
+            // upgrades are returned as epic responses, so only normal responses from the backend are compressed
+            match compression {
+                Some(compression) => create_simple_response_from_incoming(response).await.map(|x| compression.apply(x)),
+                None => create_simple_response_from_incoming(response).await
+            }
         }
-        Err(crate::http_proxy::ProxyError::LegacyError(error)) => {
-            tracing::debug!("HyperLegacyError - Failed to call {}: {error:?}", &target_url);
-            Ok(Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(create_epic_string_full_body(&format!("HyperLegacyError - {error:?}")))
-                .expect("body building always works"))
-        },
-        Err(crate::http_proxy::ProxyError::HyperError(error)) => {
-            tracing::debug!("HyperError - Failed to call {}: {error:?}", &target_url);
-            Ok(Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(create_epic_string_full_body(&format!("HyperError - {error:?}")))
-                .expect("body building always works"))
-        },
-        Err(crate::http_proxy::ProxyError::OddBoxError(error)) => {
-            tracing::debug!("OddBoxError - Failed to call {}: {error:?}", &target_url);
-            Ok(Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(create_epic_string_full_body(&format!("ODD-BOX-ERROR: {error:?}")))
-                .expect("body building always works"))
-        },
-        Err(crate::http_proxy::ProxyError::ForwardHeaderError) => {
-            tracing::debug!("ForwardHeaderError - Failed to call {}", &target_url);
-            Ok(Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(create_epic_string_full_body("ForwardHeaderError"))
-                .expect("body building always works"))
-        },
-        Err(crate::http_proxy::ProxyError::InvalidUri(error)) => {
-            tracing::debug!("InvalidUri - Failed to call {}: {error:?}", &target_url);
-            Ok(Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(create_epic_string_full_body(&format!("InvalidUri: {error:?}")))
-                .expect("body building always works"))
-        }, 
-        Err(crate::http_proxy::ProxyError::UpgradeError(error)) => {
-            tracing::debug!("UpgradeError - Failed to call {}: {error:?}", &target_url);
-            Ok(Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(create_epic_string_full_body(&format!("UpgradeError: {error:?}")))
-                .expect("body building always works"))
+        Err(error) => {
+            // the details are only logged, clients get a generic description of what went wrong
+            tracing::warn!("Failed to call {target_url}: {error:?}");
+            Ok(error_page.response(error.status_code(), error.client_message()).await)
         }
-    }
+    };
+
+    response.map(|mut res| {
+        res.extensions_mut().insert(crate::access_log::Upstream { address: target_url.to_string(), responded: upstream_responded });
+        res
+    })
 }
 
 
@@ -0,0 +1,348 @@
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use http_body::Frame;
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::{Method, Request, StatusCode};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_stream::StreamExt;
+use crate::configuration::v2::StaticSiteConfig;
+use crate::CustomError;
+use super::{create_epic_string_full_body, create_response_channel, create_stream_response, EpicResponse};
+use super::error_pages::ErrorPageContext;
+
+// characters that can be left as they are in links to files in directory listings
+const FILE_NAME : &AsciiSet = &NON_ALPHANUMERIC.remove(b'.').remove(b'-').remove(b'_').remove(b'~');
+
+/// The part of a file that a Range header asks for.
+#[derive(Debug, PartialEq)]
+pub enum ByteRange {
+    Full,
+    /// First and last byte, both inclusive
+    Partial(u64,u64),
+    Unsatisfiable
+}
+
+/// Serves a request for a static site from its directory. Errors are created from the error pages of the site.
+pub async fn serve<B>(site:&StaticSiteConfig, req:&Request<B>, error_page:&ErrorPageContext) -> EpicResponse {
+
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        let mut response = error_page.response(StatusCode::METHOD_NOT_ALLOWED, "Only GET and HEAD requests are supported.").await;
+        response.headers_mut().insert(hyper::header::ALLOW, HeaderValue::from_static("GET, HEAD"));
+        return response
+    }
+
+    let root = match tokio::fs::canonicalize(&site.dir).await {
+        Ok(root) => root,
+        Err(e) => {
+            tracing::warn!("Unable to serve {} from {}: {e}", site.host_name, site.dir);
+            return error_page.response(StatusCode::NOT_FOUND, "The requested file was not found.").await
+        }
+    };
+
+    let Some(segments) = request_segments(req.uri().path()) else {
+        return error_page.response(StatusCode::BAD_REQUEST, "The requested path is not valid.").await
+    };
+
+    let path = within_root(&root, &segments.iter().fold(root.clone(), |path, segment| path.join(segment))).await;
+    let metadata = match &path {
+        Some(path) => tokio::fs::metadata(path).await.ok(),
+        None => None
+    };
+
+    match (path, metadata) {
+        (Some(path), Some(metadata)) if metadata.is_dir() => {
+            // relative links in index files and listings only work when the path of a directory ends with a slash
+            if !req.uri().path().ends_with('/') {
+                let location = match req.uri().query() {
+                    Some(query) => format!("{}/?{query}", req.uri().path()),
+                    None => format!("{}/", req.uri().path())
+                };
+                let mut response = text_response(StatusCode::MOVED_PERMANENTLY, "");
+                if let Ok(location) = HeaderValue::from_str(&location) {
+                    response.headers_mut().insert(hyper::header::LOCATION, location);
+                }
+                return response
+            }
+            if let Some(index_file) = find_index_file(site, &root, &path).await {
+                return serve_file(&index_file, req, error_page).await
+            }
+            if site.directory_listing.unwrap_or_default() {
+                return directory_listing(&path, req.uri().path()).await
+            }
+            not_found(site, &root, req, error_page).await
+        },
+        (Some(path), Some(metadata)) if metadata.is_file() => serve_file(&path, req, error_page).await,
+        _ => not_found(site, &root, req, error_page).await
+    }
+}
+
+/// Decodes the path of a request into the names of the directories and file that it points to.
+/// Returns None if the path tries to leave the directory of the site.
+pub fn request_segments(path:&str) -> Option<Vec<String>> {
+    let decoded = percent_encoding::percent_decode_str(path).decode_utf8().ok()?;
+    let mut segments = vec![];
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => return None,
+            x if x.contains(|c:char| c == '\\' || c == '\0' || (cfg!(windows) && c == ':')) => return None,
+            x => segments.push(x.to_string())
+        }
+    }
+    Some(segments)
+}
+
+/// Parses a Range header. Only single ranges are supported, requests for multiple ranges get the whole file.
+pub fn parse_range(range:Option<&str>, size:u64) -> ByteRange {
+
+    let Some(spec) = range.and_then(|x| x.trim().strip_prefix("bytes=")) else {
+        return ByteRange::Full
+    };
+    if spec.contains(',') {
+        return ByteRange::Full
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return ByteRange::Full
+    };
+    let (start, end) = (start.trim(), end.trim());
+
+    let range = if start.is_empty() {
+        // a suffix range such as bytes=-500 asks for the last 500 bytes
+        match end.parse::<u64>() {
+            Ok(suffix) if suffix > 0 && size > 0 => Some((size.saturating_sub(suffix), size - 1)),
+            Ok(_) => None,
+            Err(_) => return ByteRange::Full
+        }
+    } else {
+        let Ok(start) = start.parse::<u64>() else {
+            return ByteRange::Full
+        };
+        let end = if end.is_empty() { Ok(u64::MAX) } else { end.parse::<u64>() };
+        match end {
+            Ok(end) if end < start => return ByteRange::Full,
+            Ok(_) if start >= size => None,
+            Ok(end) => Some((start, end.min(size - 1))),
+            Err(_) => return ByteRange::Full
+        }
+    };
+
+    match range {
+        Some((start, end)) => ByteRange::Partial(start, end),
+        None => ByteRange::Unsatisfiable
+    }
+}
+
+fn http_date(unix_seconds:u64) -> Option<String> {
+    chrono::DateTime::from_timestamp(unix_seconds as i64, 0).map(|x| x.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+}
+
+fn content_type(path:&Path) -> String {
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    if mime.type_() == mime_guess::mime::TEXT || mime.subtype() == mime_guess::mime::JAVASCRIPT || mime.subtype() == mime_guess::mime::JSON {
+        format!("{}; charset=utf-8", mime.essence_str())
+    } else {
+        mime.essence_str().to_string()
+    }
+}
+
+fn text_response(status:StatusCode, text:&str) -> EpicResponse {
+    let mut response = EpicResponse::new(create_epic_string_full_body(text));
+    *response.status_mut() = status;
+    response
+}
+
+pub fn html_escape(text:&str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&#39;")
+}
+
+// resolves symlinks so that they cannot be used to serve files from outside of the directory
+async fn within_root(root:&Path, path:&Path) -> Option<PathBuf> {
+    let path = tokio::fs::canonicalize(path).await.ok()?;
+    if path.starts_with(root) { Some(path) } else { None }
+}
+
+async fn find_index_file(site:&StaticSiteConfig, root:&Path, dir:&Path) -> Option<PathBuf> {
+    for index_file in site.index_files() {
+        if let Some(path) = within_root(root, &dir.join(index_file)).await {
+            if tokio::fs::metadata(&path).await.is_ok_and(|x| x.is_file()) {
+                return Some(path)
+            }
+        }
+    }
+    None
+}
+
+async fn not_found<B>(site:&StaticSiteConfig, root:&Path, req:&Request<B>, error_page:&ErrorPageContext) -> EpicResponse {
+    if site.spa_fallback.unwrap_or_default() {
+        if let Some(index_file) = find_index_file(site, root, root).await {
+            return serve_file(&index_file, req, error_page).await
+        }
+    }
+    error_page.response(StatusCode::NOT_FOUND, "The requested file was not found.").await
+}
+
+// If-None-Match takes precedence over If-Modified-Since when both are sent
+fn is_not_modified(headers:&HeaderMap, etag:&str, modified:Option<u64>) -> bool {
+    if let Some(if_none_match) = headers.get(hyper::header::IF_NONE_MATCH).and_then(|x| x.to_str().ok()) {
+        return if_none_match.split(',').map(|x| x.trim()).any(|x| x == "*" || x.trim_start_matches("W/") == etag)
+    }
+    let since = headers.get(hyper::header::IF_MODIFIED_SINCE).and_then(|x| x.to_str().ok()).and_then(crate::http_cache::parse_http_date);
+    match (since, modified) {
+        (Some(since), Some(modified)) => modified <= since,
+        _ => false
+    }
+}
+
+async fn serve_file<B>(path:&Path, req:&Request<B>, error_page:&ErrorPageContext) -> EpicResponse {
+
+    let opened = async {
+        let file = tokio::fs::File::open(path).await?;
+        let metadata = file.metadata().await?;
+        Ok::<_,std::io::Error>((file, metadata))
+    }.await;
+    let (mut file, metadata) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            tracing::warn!("Failed to open {path:?}: {e}");
+            return error_page.response(StatusCode::FORBIDDEN, "The requested file cannot be read.").await
+        }
+    };
+
+    let size = metadata.len();
+    let modified = metadata.modified().ok().and_then(|x| x.duration_since(UNIX_EPOCH).ok()).map(|x| x.as_secs());
+    let etag = format!("\"{size:x}-{:x}\"", modified.unwrap_or_default());
+
+    let mut headers = HeaderMap::new();
+    if let Ok(value) = HeaderValue::from_str(&content_type(path)) {
+        headers.insert(hyper::header::CONTENT_TYPE, value);
+    }
+    headers.insert(hyper::header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        headers.insert(hyper::header::ETAG, value);
+    }
+    if let Some(value) = modified.and_then(http_date).and_then(|x| HeaderValue::from_str(&x).ok()) {
+        headers.insert(hyper::header::LAST_MODIFIED, value);
+    }
+
+    if is_not_modified(req.headers(), &etag, modified) {
+        let mut response = text_response(StatusCode::NOT_MODIFIED, "");
+        *response.headers_mut() = headers;
+        return response
+    }
+
+    // ranges are only used if the file has not changed since the client got the rest of it
+    let range_is_current = match req.headers().get(hyper::header::IF_RANGE).and_then(|x| x.to_str().ok()) {
+        Some(if_range) if if_range.starts_with('"') || if_range.starts_with("W/") => if_range == etag,
+        Some(if_range) => crate::http_cache::parse_http_date(if_range).is_some_and(|x| Some(x) == modified),
+        None => true
+    };
+    let range = if range_is_current {
+        parse_range(req.headers().get(hyper::header::RANGE).and_then(|x| x.to_str().ok()), size)
+    } else {
+        ByteRange::Full
+    };
+
+    let (status, start, length) = match range {
+        ByteRange::Full => (StatusCode::OK, 0, size),
+        ByteRange::Partial(start, end) => {
+            if let Ok(value) = HeaderValue::from_str(&format!("bytes {start}-{end}/{size}")) {
+                headers.insert(hyper::header::CONTENT_RANGE, value);
+            }
+            (StatusCode::PARTIAL_CONTENT, start, end - start + 1)
+        },
+        ByteRange::Unsatisfiable => {
+            let mut response = text_response(StatusCode::RANGE_NOT_SATISFIABLE, "");
+            if let Ok(value) = HeaderValue::from_str(&format!("bytes */{size}")) {
+                response.headers_mut().insert(hyper::header::CONTENT_RANGE, value);
+            }
+            return response
+        }
+    };
+    headers.insert(hyper::header::CONTENT_LENGTH, HeaderValue::from(length));
+
+    if req.method() == Method::HEAD {
+        let mut response = text_response(status, "");
+        *response.headers_mut() = headers;
+        return response
+    }
+
+    let (tx, rx) = create_response_channel(16);
+    tokio::spawn(async move {
+        if let Err(e) = file.seek(std::io::SeekFrom::Start(start)).await {
+            _ = tx.send(Err(CustomError(format!("{e:?}")))).await;
+            return
+        }
+        let mut chunks = tokio_util::io::ReaderStream::new(file.take(length));
+        while let Some(chunk) = chunks.next().await {
+            let frame = chunk.map(Frame::data).map_err(|e| CustomError(format!("{e:?}")));
+            if tx.send(frame).await.is_err() {
+                // the client went away
+                break
+            }
+        }
+    });
+
+    let mut response = create_stream_response(rx);
+    *response.status_mut() = status;
+    *response.headers_mut() = headers;
+    response
+}
+
+async fn directory_listing(dir:&Path, request_path:&str) -> EpicResponse {
+
+    let mut entries = vec![];
+    if let Ok(mut read_dir) = tokio::fs::read_dir(dir).await {
+        while let Ok(Some(entry)) = read_dir.next_entry().await {
+            let is_dir = entry.file_type().await.is_ok_and(|x| x.is_dir());
+            // directories are listed before files
+            entries.push((!is_dir, entry.file_name().to_string_lossy().to_string()));
+        }
+    }
+    entries.sort();
+
+    let title = html_escape(&percent_encoding::percent_decode_str(request_path).decode_utf8_lossy());
+    let mut html = format!("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {title}</title></head>\n<body>\n<h1>Index of {title}</h1>\n<ul>\n");
+    if request_path != "/" {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for (is_file, name) in entries {
+        let slash = if is_file { "" } else { "/" };
+        let href = percent_encoding::utf8_percent_encode(&name, FILE_NAME);
+        html.push_str(&format!("<li><a href=\"{href}{slash}\">{}{slash}</a></li>\n", html_escape(&name)));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+
+    let mut response = text_response(StatusCode::OK, &html);
+    response.headers_mut().insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn static_site_paths_and_ranges_are_parsed() {
+
+        assert_eq!(request_segments("/"), Some(vec![]));
+        assert_eq!(request_segments("/assets/./app%20v2.js"), Some(vec![String::from("assets"), String::from("app v2.js")]));
+        assert_eq!(request_segments("//docs/"), Some(vec![String::from("docs")]));
+        assert_eq!(request_segments("/../etc/passwd"), None);
+        assert_eq!(request_segments("/assets/%2e%2e/%2e%2e/secret"), None);
+        assert_eq!(request_segments("/assets/..%5c..%5csecret"), None);
+
+        assert_eq!(parse_range(None, 1000), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=0-99"), 1000), ByteRange::Partial(0, 99));
+        assert_eq!(parse_range(Some("bytes=900-"), 1000), ByteRange::Partial(900, 999));
+        assert_eq!(parse_range(Some("bytes=900-5000"), 1000), ByteRange::Partial(900, 999));
+        assert_eq!(parse_range(Some("bytes=-100"), 1000), ByteRange::Partial(900, 999));
+        assert_eq!(parse_range(Some("bytes=-5000"), 1000), ByteRange::Partial(0, 999));
+        assert_eq!(parse_range(Some("bytes=1000-"), 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=0-1,5-9"), 1000), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=50-10"), 1000), ByteRange::Full);
+        assert_eq!(parse_range(Some("items=0-10"), 1000), ByteRange::Full);
+
+    }
+}
@@ -6,8 +6,8 @@ use hyper::{
     body::Incoming, header::{HeaderName, HeaderValue, InvalidHeaderValue, ToStrError}, upgrade::OnUpgrade, HeaderMap, Request, Response, StatusCode, Version
 };
 use hyper_rustls::HttpsConnector;
-use hyper_util::{client::legacy::{connect::HttpConnector, Client}, rt::TokioIo};
-use std::{net::SocketAddr, sync::Arc, task::Poll, time::Duration};
+use hyper_util::{client::legacy::Client, rt::TokioIo};
+use std::{net::SocketAddr, pin::Pin, sync::Arc, task::Poll, time::Duration};
 use tungstenite::http;
 
 use lazy_static::lazy_static;
@@ -34,6 +34,11 @@ lazy_static! {
     ];
 
     static ref X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+    static ref X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+    static ref X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+    static ref X_FORWARDED_PORT: HeaderName = HeaderName::from_static("x-forwarded-port");
+    static ref X_REAL_IP: HeaderName = HeaderName::from_static("x-real-ip");
+    static ref FORWARDED: HeaderName = HeaderName::from_static("forwarded");
 }
 
 pub enum ProxyCallResult {
@@ -50,12 +55,187 @@ pub enum ProxyError {
     HyperError(hyper::Error),
     LegacyError(hyper_util::client::legacy::Error),
     OddBoxError(String),
+    Timeout(String),
+}
+
+impl ProxyError {
+    /// The status code to respond with when the request could not be proxied.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ProxyError::ForwardHeaderError => StatusCode::BAD_REQUEST,
+            ProxyError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ProxyError::LegacyError(e) if is_timeout(e) => StatusCode::GATEWAY_TIMEOUT,
+            ProxyError::HyperError(e) if e.is_timeout() || is_timeout(e) => StatusCode::GATEWAY_TIMEOUT,
+            _ => StatusCode::BAD_GATEWAY
+        }
+    }
+    /// Describes the error without any details about the site, so that it can be shown to clients.
+    pub fn client_message(&self) -> &'static str {
+        match self {
+            ProxyError::ForwardHeaderError => "The request contains headers that cannot be forwarded.",
+            _ if self.status_code() == StatusCode::GATEWAY_TIMEOUT => "The site did not respond in time.",
+            ProxyError::LegacyError(e) if e.is_connect() => "The site could not be reached.",
+            _ => "The site could not handle the request."
+        }
+    }
+}
+
+// true if a timeout is found anywhere in the chain of errors
+fn is_timeout(error:&(dyn std::error::Error + 'static)) -> bool {
+    let mut current = Some(error);
+    while let Some(e) = current {
+        if e.downcast_ref::<std::io::Error>().is_some_and(|x| x.kind() == std::io::ErrorKind::TimedOut) {
+            return true
+        }
+        current = e.source();
+    }
+    false
 }
 
 #[derive(Debug)]
 pub enum Target {
-    Remote(crate::configuration::v2::RemoteSiteConfig),
-    Proc(crate::configuration::v2::InProcessSiteConfig),
+    Remote(Box<crate::configuration::v2::RemoteSiteConfig>),
+    Proc(Box<crate::configuration::v2::InProcessSiteConfig>),
+}
+
+impl Target {
+    pub fn forwarded_headers_enabled(&self) -> bool {
+        match self {
+            Target::Remote(x) => x.forwarded_headers.unwrap_or(true),
+            Target::Proc(x) => x.forwarded_headers.unwrap_or(true)
+        }
+    }
+    pub fn site_name(&self) -> &str {
+        match self {
+            Target::Remote(x) => &x.host_name,
+            Target::Proc(x) => &x.host_name
+        }
+    }
+    pub fn request_header_rules(&self) -> Vec<crate::configuration::v2::HeaderRule> {
+        match self {
+            Target::Remote(x) => x.request_headers.clone().unwrap_or_default(),
+            Target::Proc(x) => x.request_headers.clone().unwrap_or_default()
+        }
+    }
+    pub fn response_header_rules(&self) -> Vec<crate::configuration::v2::HeaderRule> {
+        match self {
+            Target::Remote(x) => x.response_headers.clone(),
+            Target::Proc(x) => x.response_headers.clone()
+        }.unwrap_or_else(crate::configuration::v2::default_response_header_rules)
+    }
+    pub fn auth(&self) -> Option<crate::configuration::v2::AuthConfig> {
+        match self {
+            Target::Remote(x) => x.auth.clone(),
+            Target::Proc(x) => x.auth.clone()
+        }
+    }
+    pub fn compression(&self) -> Option<crate::configuration::v2::CompressionConfig> {
+        match self {
+            Target::Remote(x) => x.compression.clone(),
+            Target::Proc(x) => x.compression.clone()
+        }
+    }
+    pub fn hsts(&self) -> Option<crate::configuration::v2::HstsConfig> {
+        match self {
+            Target::Remote(x) => x.hsts.clone(),
+            Target::Proc(x) => x.hsts.clone()
+        }
+    }
+    pub fn header_rule_context(&self, client_ip: std::net::IpAddr, req_host_name: &str, is_https: bool) -> crate::configuration::v2::HeaderRuleContext {
+        crate::configuration::v2::HeaderRuleContext {
+            client_ip: client_ip.to_canonical(),
+            host: req_host_name.to_string(),
+            site: self.site_name().to_string(),
+            scheme: if is_https { "https" } else { "http" }
+        }
+    }
+}
+
+/// Url that a plain http request is redirected to when its site forces https. The port is left out when it is 443.
+pub fn https_redirect_location(req_host_name: &str, tls_port: u16, path_and_query: &str) -> String {
+    if tls_port == 443 {
+        format!("https://{req_host_name}{path_and_query}")
+    } else {
+        format!("https://{req_host_name}:{tls_port}{path_and_query}")
+    }
+}
+
+/// Creates the Forwarded, X-Forwarded-* and X-Real-IP headers for a request that is sent to a backend.
+/// Values sent by the client are only kept, or appended to, when the client is a trusted proxy.
+/// For all other clients they are replaced so that the client address cannot be spoofed.
+pub fn forwarded_headers(
+    incoming: &HeaderMap,
+    client_ip: std::net::IpAddr,
+    is_https: bool,
+    original_host: &str,
+    client_is_trusted_proxy: bool
+) -> Vec<(HeaderName,HeaderValue)> {
+
+    let client_ip = client_ip.to_canonical();
+    let proto = if is_https { "https" } else { "http" };
+    let port = original_host.rsplit_once(':')
+        .and_then(|(_,p)| p.parse::<u16>().ok())
+        .unwrap_or(if is_https { 443 } else { 80 });
+
+    // headers can be sent multiple times, in which case the values are treated as a single comma separated list
+    let existing = |name:&HeaderName| -> Option<String> {
+        if !client_is_trusted_proxy { return None }
+        let values : Vec<&str> = incoming.get_all(name).iter().filter_map(|x|x.to_str().ok()).collect();
+        if values.is_empty() { None } else { Some(values.join(", ")) }
+    };
+
+    // ipv6 addresses must be quoted and put in brackets: for="[2001:db8::1]"
+    let node = match client_ip {
+        std::net::IpAddr::V4(ip) => ip.to_string(),
+        std::net::IpAddr::V6(ip) => format!("\"[{ip}]\"")
+    };
+    let forwarded_element = format!("for={node};host=\"{original_host}\";proto={proto}");
+
+    let x_forwarded_for = existing(&X_FORWARDED_FOR);
+    let real_ip = existing(&X_REAL_IP)
+        .or(x_forwarded_for.as_ref().and_then(|x| x.split(',').next().map(|x|x.trim().to_string())))
+        .unwrap_or(client_ip.to_string());
+
+    let headers = [
+        (&*FORWARDED, match existing(&FORWARDED) {
+            Some(x) => format!("{x}, {forwarded_element}"),
+            None => forwarded_element
+        }),
+        (&*X_FORWARDED_FOR, match x_forwarded_for {
+            Some(x) => format!("{x}, {client_ip}"),
+            None => client_ip.to_string()
+        }),
+        (&*X_FORWARDED_PROTO, existing(&X_FORWARDED_PROTO).unwrap_or(proto.to_string())),
+        (&*X_FORWARDED_HOST, existing(&X_FORWARDED_HOST).unwrap_or(original_host.to_string())),
+        (&*X_FORWARDED_PORT, existing(&X_FORWARDED_PORT).unwrap_or(port.to_string())),
+        (&*X_REAL_IP, real_ip),
+    ];
+
+    headers.into_iter().filter_map(|(name,value)| match HeaderValue::from_str(&value) {
+        Ok(v) => Some((name.clone(),v)),
+        Err(e) => {
+            tracing::debug!("Not adding the {name} header as '{value}' is not a valid header value: {e:?}");
+            None
+        }
+    }).collect()
+}
+
+/// The host that the client requested, including the port if one was specified.
+pub fn original_host<B>(req:&Request<B>, req_host_name:&str) -> String {
+    req.headers().get(hyper::header::HOST).and_then(|x|x.to_str().ok()).map(|x|x.to_string())
+        .or(req.uri().authority().map(|x|x.to_string()))
+        .unwrap_or(req_host_name.to_string())
+}
+
+/// The per-request inputs of proxy: who sent the request, how it reached odd-box and the backend it is forwarded to.
+pub struct ProxyRequestContext {
+    pub client_ip: SocketAddr,
+    /// Forwarded headers from trusted proxies are kept rather than replaced
+    pub client_is_trusted_proxy: bool,
+    pub original_connection_is_https: bool,
+    /// The full url of the request to the backend, including the scheme
+    pub target_url: String,
+    pub backend: crate::configuration::v2::Backend
 }
 
 // We don't care about the original call scheme, version, etc.
@@ -63,22 +243,21 @@ pub enum Target {
 // our caller has already determined if the target is http or https depending on whatever backend was selected.
 // The job of this method is simply to create a new request with the target url and the original request's headers.
 // while also selecting http version and handling upgraded connections.  
-// TODO: simplify the signature, we dont need it to be this complicated..
 pub async fn proxy(
     req_host_name: &str,
-    original_connection_is_https:bool,
     state: Arc<GlobalState>,
-    mut req: hyper::Request<hyper::body::Incoming>,
-    target_url: &str,
+    mut req: hyper::Request<super::UpstreamBody>,
     target: Target,
-    client_ip: SocketAddr,
-    client:  Client<HttpsConnector<HttpConnector>, hyper::body::Incoming>,
-    h2_only_client: Client<HttpsConnector<HttpConnector>, hyper::body::Incoming>,
-    _fallback_url: &str,
-    use_https_to_backend_target: bool,
-    backend: crate::configuration::v2::Backend
+    context: ProxyRequestContext,
+    client:  Client<HttpsConnector<crate::http_proxy::TimeoutConnector>, super::UpstreamBody>,
+    h2_only_client: Client<HttpsConnector<crate::http_proxy::TimeoutConnector>, super::UpstreamBody>
 ) -> Result<ProxyCallResult, ProxyError> {
 
+    let client_ip = context.client_ip;
+    let original_connection_is_https = context.original_connection_is_https;
+    let target_url = context.target_url.as_str();
+    let backend = &context.backend;
+    let use_https_to_backend_target = backend.https.unwrap_or_default();
     
     let incoming_http_version = req.version();
     let request_upgrade_type = get_upgrade_type(req.headers());
@@ -130,9 +309,23 @@ pub async fn proxy(
         }
     }
 
+    if target.forwarded_headers_enabled() {
+        let original_host = original_host(&req, req_host_name);
+        for (name,value) in forwarded_headers(req.headers(), client_ip.ip(), original_connection_is_https, &original_host, context.client_is_trusted_proxy) {
+            req.headers_mut().insert(name, value);
+        }
+    }
     
     let mut proxied_request =
-        create_proxied_request(&target_url, req, request_upgrade_type.as_ref(), &req_host_name)?;
+        create_proxied_request(target_url, req, request_upgrade_type.as_ref(), req_host_name)?;
+
+    let timeouts = state.config.read().await.upstream_timeouts(Some(backend));
+    let total_deadline = timeouts.total().map(|x| tokio::time::Instant::now() + x);
+
+    let header_rule_context = target.header_rule_context(client_ip.ip(), req_host_name, original_connection_is_https);
+    crate::configuration::v2::apply_header_rules(&target.request_header_rules(), proxied_request.headers_mut(), &header_rule_context);
+    let response_header_rules = target.response_header_rules();
+    let hsts = if original_connection_is_https { target.hsts() } else { None };
 
     
     if proxied_request.version() == Version::HTTP_2 {
@@ -188,12 +381,8 @@ pub async fn proxy(
     let con: ProxyActiveConnection = create_connection(
         &proxied_request, 
         incoming_http_version,
-        target, 
-        &client_ip, 
+        &context, 
         target_scheme_info_str, 
-        proxied_request.version(), 
-        &target_url, 
-        original_connection_is_https,
         req_host_name.to_string()
     );
 
@@ -201,13 +390,23 @@ pub async fn proxy(
     tracing::trace!("Sending request:\n{:?}", proxied_request);
 
 
+    // the connect timeout is applied by the connector, so the time to connect is not counted against the time to first byte
+    let first_byte_deadline = timeouts.first_byte().map(|x| tokio::time::Instant::now() + timeouts.connect() + x);
+    let response_deadline = match (first_byte_deadline, total_deadline) {
+        (Some(first_byte_deadline), Some(total_deadline)) => Some(first_byte_deadline.min(total_deadline)),
+        (first_byte_deadline, total_deadline) => first_byte_deadline.or(total_deadline)
+    };
+
     // todo - prevent making a connection if client already has too many tcp connections open
-    let mut response = {
-        client
-            .request(proxied_request)
-            .await
-            .map_err(ProxyError::LegacyError)?
+    let pending_response = client.request(proxied_request);
+    let result = match response_deadline {
+        Some(deadline) => match tokio::time::timeout_at(deadline, pending_response).await {
+            Ok(result) => result,
+            Err(_) => return Err(ProxyError::Timeout(format!("no response from {target_url} in time")))
+        },
+        None => pending_response.await
     };
+    let mut response = result.map_err(ProxyError::LegacyError)?;
 
     tracing::trace!(
         "GOT THIS RESPONSE FROM REQ TO '{target_url}' : {:?}",response
@@ -244,8 +443,8 @@ pub async fn proxy(
 
                     tracing::debug!("Starting bidirectional stream copy for upgraded request.");
 
-                    match tokio::io::copy_bidirectional(&mut response_upgraded, &mut request_upgraded)
-                        .await {
+                    match crate::tcp_proxy::copy_bidirectional_with_idle_timeout(&mut response_upgraded, &mut request_upgraded, timeouts.idle())
+                        .await.result {
                             Ok(_) => {},
                             Err(e) => {
                                 tracing::warn!("coping between upgraded connections failed: {e:?}")
@@ -257,6 +456,7 @@ pub async fn proxy(
 
                             
 
+                crate::configuration::v2::apply_header_rules(&response_header_rules, response.headers_mut(), &header_rule_context);
                 let response = super::create_simple_response_from_incoming(
                         WrappedNormalResponse::new(response,state.clone(),con)
                     )
@@ -276,8 +476,16 @@ pub async fn proxy(
         }
     } else {
         // Got a normal response from the backend, we will just forward it to the client!       
-        let proxied_response = create_proxied_response(response);
-        Ok(ProxyCallResult::NormalResponse(WrappedNormalResponse::new(proxied_response,state.clone(),con)))
+        let mut proxied_response = create_proxied_response(response);
+        crate::configuration::v2::apply_header_rules(&response_header_rules, proxied_response.headers_mut(), &header_rule_context);
+        if let Some(hsts) = hsts {
+            if let Ok(value) = HeaderValue::from_str(&hsts.header_value()) {
+                proxied_response.headers_mut().insert(hyper::header::STRICT_TRANSPORT_SECURITY, value);
+            }
+        }
+        let response = WrappedNormalResponse::new(proxied_response,state.clone(),con)
+            .with_timeouts(total_deadline, timeouts.idle());
+        Ok(ProxyCallResult::NormalResponse(response))
     }
 }
 
@@ -287,6 +495,8 @@ pub async fn proxy(
 pub struct  WrappedNormalResponseBody {
     b : Incoming,
     on_drop : Option<Box<dyn FnOnce() + Send + 'static>>,
+    deadline : Option<Pin<Box<tokio::time::Sleep>>>,
+    idle : Option<(Duration, Pin<Box<tokio::time::Sleep>>)>,
 }
 impl Drop for WrappedNormalResponseBody {
     fn drop(&mut self) {
@@ -306,6 +516,10 @@ impl WrappedNormalResponse {
         (self.a,self.b)
     }
 
+    pub fn status(&self) -> StatusCode {
+        self.a.status
+    }
+
     
     pub fn new(res:Response<Incoming>,state: Arc<GlobalState>,con: ProxyActiveConnection) -> Self {
         tracing::trace!("Adding connection for this WrappedNormalResponse.");
@@ -325,28 +539,52 @@ impl WrappedNormalResponse {
 
         let (a,b) = res.into_parts();
         Self {
-            a, b: WrappedNormalResponseBody { b,on_drop: Some(on_drop) }
+            a, b: WrappedNormalResponseBody { b,on_drop: Some(on_drop), deadline: None, idle: None }
         }
     }
+
+    /// Fails the body if it is not complete by the deadline, or if the backend sends nothing for longer than the idle timeout.
+    pub fn with_timeouts(mut self, deadline: Option<tokio::time::Instant>, idle: Option<Duration>) -> Self {
+        self.b.deadline = deadline.map(|x| Box::pin(tokio::time::sleep_until(x)));
+        self.b.idle = idle.map(|x| (x, Box::pin(tokio::time::sleep(x))));
+        self
+    }
 }
 
 impl hyper::body::Body for WrappedNormalResponseBody {
     type Data = bytes::Bytes;
-    type Error = hyper::Error;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
 
     fn poll_frame(
         mut self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
-        match self.b.frame().poll_unpin(cx) {
-            Poll::Ready(Some(Ok(data))) => Poll::Ready(Some(Ok(data))),
+        let frame = self.b.frame().poll_unpin(cx);
+        match frame {
+            Poll::Ready(Some(Ok(data))) => {
+                if let Some((idle_timeout, idle)) = self.idle.as_mut() {
+                    let next_deadline = tokio::time::Instant::now() + *idle_timeout;
+                    idle.as_mut().reset(next_deadline);
+                }
+                Poll::Ready(Some(Ok(data)))
+            },
             Poll::Ready(Some(Err(e))) => {
                 // Handle error properly here
                 tracing::error!("Error while polling frame: {:?}", e);
-                Poll::Ready(Some(Err(e)))
+                Poll::Ready(Some(Err(e.into())))
             }
             Poll::Ready(None) => Poll::Ready(None),
-            Poll::Pending => Poll::Pending,
+            Poll::Pending => {
+                if self.deadline.as_mut().is_some_and(|x| x.poll_unpin(cx).is_ready()) {
+                    tracing::warn!("Closing a response that was not complete within the total timeout");
+                    return Poll::Ready(Some(Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "the response was not complete in time").into())))
+                }
+                if self.idle.as_mut().is_some_and(|(_,x)| x.poll_unpin(cx).is_ready()) {
+                    tracing::warn!("Closing a response after the backend sent nothing within the idle timeout");
+                    return Poll::Ready(Some(Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "the backend was idle for too long").into())))
+                }
+                Poll::Pending
+            },
         }
     }
 }
@@ -555,33 +793,73 @@ fn del_connection(state:Arc<GlobalState>,key:&ConnectionKey) {
     _ = guard.active_connections.remove(key);
 }
 
-fn create_connection(
-    req:&Request<Incoming>,
+fn create_connection<B>(
+    req:&Request<B>,
     incoming_http_version: Version,
-    _target:Target,
-    client_addr:&SocketAddr,
+    context: &ProxyRequestContext,
     target_scheme: &str,
-    target_http_version: hyper::http::Version,
-    target_addr: &str,
-    incoming_known_tls_only: bool,
     target_host_name : String
 ) -> ProxyActiveConnection {
     let uri = req.uri();
     let typ_info = 
         ProxyActiveConnectionType::TerminatingHttp { 
-            incoming_scheme: uri.scheme_str().unwrap_or(if incoming_known_tls_only { "HTTPS" } else {"HTTP"} ).to_owned(), 
+            incoming_scheme: uri.scheme_str().unwrap_or(if context.original_connection_is_https { "HTTPS" } else {"HTTP"} ).to_owned(), 
             incoming_http_version: format!("{:?}",incoming_http_version), 
-            outgoing_http_version: format!("{:?}",target_http_version), 
+            outgoing_http_version: format!("{:?}",req.version()), 
             outgoing_scheme: target_scheme.to_owned()
         };
 
     ProxyActiveConnection {
         target_name: target_host_name,
-        source_addr: client_addr.clone(),
-        target_addr: target_addr.to_owned(),
+        source_addr: context.client_ip,
+        target_addr: context.target_url.clone(),
+        backend_key: Some(context.backend.key()),
         //target: ReverseTcpProxyTarget::from_target(target),
         creation_time: Local::now(),
         description: None,
         connection_type: typ_info
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn forwarded_headers_are_only_appended_for_trusted_proxies() {
+
+        let mut incoming = hyper::HeaderMap::new();
+        incoming.insert("x-forwarded-for", "1.2.3.4".parse().unwrap());
+        incoming.insert("x-forwarded-proto", "http".parse().unwrap());
+        let client_ip : std::net::IpAddr = "10.0.0.1".parse().unwrap();
+
+        let header = |headers:&Vec<(hyper::header::HeaderName,hyper::header::HeaderValue)>,name:&str| headers.iter()
+            .find(|(k,_)| k == name).map(|(_,v)| v.to_str().unwrap().to_string()).unwrap();
+
+        let trusted = forwarded_headers(&incoming, client_ip, true, "example.com:4343", true);
+        assert_eq!(header(&trusted,"x-forwarded-for"), "1.2.3.4, 10.0.0.1");
+        assert_eq!(header(&trusted,"x-forwarded-proto"), "http");
+        assert_eq!(header(&trusted,"x-real-ip"), "1.2.3.4");
+
+        let untrusted = forwarded_headers(&incoming, client_ip, true, "example.com:4343", false);
+        assert_eq!(header(&untrusted,"x-forwarded-for"), "10.0.0.1");
+        assert_eq!(header(&untrusted,"x-forwarded-proto"), "https");
+        assert_eq!(header(&untrusted,"x-forwarded-port"), "4343");
+        assert_eq!(header(&untrusted,"forwarded"), "for=10.0.0.1;host=\"example.com:4343\";proto=https");
+
+    }
+
+    #[test]
+    fn https_redirects_use_the_tls_port_and_hsts_has_defaults() {
+
+        use crate::configuration::v2::HstsConfig;
+
+        assert_eq!(https_redirect_location("example.localtest.me", 4343, "/a?b=c"), "https://example.localtest.me:4343/a?b=c");
+        assert_eq!(https_redirect_location("example.localtest.me", 443, "/"), "https://example.localtest.me/");
+
+        assert_eq!(HstsConfig { max_age_seconds: None, include_subdomains: None }.header_value(), "max-age=31536000");
+        assert_eq!(HstsConfig { max_age_seconds: Some(60), include_subdomains: Some(true) }.header_value(), "max-age=60; includeSubDomains");
+
+    }
+}
@@ -1,9 +1,9 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::sync::Arc;
 use chrono::Local;
 use hyper_tungstenite::HyperWebsocket;
 use hyper::{body::Incoming as IncomingBody, Request};
 use tokio_rustls::rustls::ClientConfig;
-use crate::{global_state::GlobalState, CustomError};
+use crate::{configuration::host_matches, global_state::GlobalState, CustomError};
 use futures_util::{SinkExt,StreamExt};
 use super::{ReverseProxyService, Target};
 use crate::types::proxy_state::{
@@ -12,17 +12,21 @@ use crate::types::proxy_state::{
     ProxyActiveConnectionType
 };
 use hyper_rustls::ConfigBuilderExt;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
 
-pub async fn handle_ws(req:Request<IncomingBody>,service:ReverseProxyService,ws:HyperWebsocket) -> Result<(),CustomError> {
+/// The site that should handle a websocket request, along with the requested host name and the path to forward.
+pub struct WebsocketTarget {
+    pub target : Target,
+    pub req_host_name : String,
+    pub req_path : String,
+    /// Headers returned by forward auth that should be sent to the upstream websocket
+    pub auth_headers : Vec<(hyper::header::HeaderName,hyper::header::HeaderValue)>
+}
 
-    
-    let req_host_name = 
-        if let Some(hh) = req.headers().get("host") { 
-            let hostname_and_port = hh.to_str().map_err(|e|CustomError(format!("{e:?}")))?.to_string();
-            hostname_and_port.split(":").collect::<Vec<&str>>()[0].to_owned()
-        } else { 
-            req.uri().authority().ok_or(CustomError(format!("No hostname and no Authority found")))?.host().to_string()
-        } ;
+/// Finds the site that should handle a websocket request, or None if no site is configured for the host name.
+pub async fn find_target(req:&Request<IncomingBody>,req_host_name:&str,state:&GlobalState) -> Option<WebsocketTarget> {
+
+    let req_host_name = req_host_name.to_string();
         
     let req_path_and_uri = req.uri().path_and_query();
 
@@ -32,43 +36,63 @@ pub async fn handle_ws(req:Request<IncomingBody>,service:ReverseProxyService,ws:
     
     tracing::trace!("Handling websocket request: {req_host_name:?} --> {req_path}");
     
-    let read_guard = service.state.config.read().await;
+    let read_guard = state.config.read().await;
     
+    // path rules can send the request to another site than the one matching the host name
+    let (site_host_name,req_path) = match read_guard.find_path_rule(&req_host_name, req.uri().path()) {
+        Some(rule) => {
+            tracing::trace!("Path rule '{}' matched websocket request for {req_host_name}, routing to {}",rule.path,rule.target);
+            let rewritten_path = rule.rewrite_path_and_query(&req_path);
+            (rule.target,rewritten_path)
+        },
+        None => (req_host_name.clone(),req_path)
+    };
     
     let target = {
 
-        if let Some(proc) = read_guard.hosted_process.iter().flatten().find(|p| { 
-            req_host_name == p.host_name 
-            || p.capture_subdomains.unwrap_or_default() && req_host_name.ends_with(&format!(".{}",p.host_name)) 
-        }) {
-            crate::http_proxy::utils::Target::Proc(proc.clone())
-        } else if let Some(remsite) = read_guard.remote_target.iter().flatten().find(|x| { 
-            req_host_name == x.host_name 
-            || x.capture_subdomains.unwrap_or_default() && req_host_name.ends_with(&format!(".{}",x.host_name)) 
-        }) {
-            crate::http_proxy::utils::Target::Remote(remsite.clone())
+        if let Some(proc) = read_guard.hosted_process.iter().flatten().find(|p| host_matches(&site_host_name, &p.host_name, p.capture_subdomains)) {
+            crate::http_proxy::utils::Target::Proc(Box::new(proc.clone()))
+        } else if let Some(remsite) = read_guard.remote_target.iter().flatten().find(|x| host_matches(&site_host_name, &x.host_name, x.capture_subdomains)) {
+            crate::http_proxy::utils::Target::Remote(Box::new(remsite.clone()))
         } else {
-            return Err(CustomError(format!("No target is configured to handle requests to {req_host_name}")))
+            return None
         }
     };
 
-    let (target_host,port,enforce_https) = match &target {
+    Some(WebsocketTarget { target, req_host_name, req_path, auth_headers: vec![] })
+}
+
+pub async fn handle_ws(req:Request<IncomingBody>,service:ReverseProxyService,ws:HyperWebsocket,ws_target:WebsocketTarget) -> Result<(),CustomError> {
+
+    let WebsocketTarget { target, req_host_name, req_path, auth_headers } = ws_target;
+
+    let (target_host,port,enforce_https,backend_key,timeouts) = match &target {
         
         crate::http_proxy::Target::Remote(x) => {
-             let next_backend = x.next_backend(&service.state, crate::configuration::v2::BackendFilter::Any,false).await
+             let lb_context = match service.remote_addr {
+                Some(addr) => crate::configuration::v2::LoadBalancingContext::from_request(addr.ip(), req.headers()),
+                None => crate::configuration::v2::LoadBalancingContext::default()
+             };
+             let next_backend = x.next_backend(&service.state, crate::configuration::v2::BackendFilter::Any,&lb_context).await
                 .ok_or(CustomError(format!("no backend found")))?;
+             let timeouts = service.state.config.read().await.upstream_timeouts(Some(&next_backend));
              (
                 next_backend.address.clone(),
                 next_backend.port,
-                next_backend.https.unwrap_or_default()
+                next_backend.https.unwrap_or_default(),
+                Some(next_backend.key()),
+                timeouts
              )
         },
         crate::http_proxy::Target::Proc(x) => {
             let backend_is_https = x.https.unwrap_or_default();
+            let timeouts = service.state.config.read().await.upstream_timeouts(None);
             (
                 x.host_name.clone(),
                 x.active_port.unwrap_or_default(),
-                backend_is_https
+                backend_is_https,
+                None,
+                timeouts
             )
         }
     };
@@ -87,22 +111,47 @@ pub async fn handle_ws(req:Request<IncomingBody>,service:ReverseProxyService,ws:
     
     tracing::debug!("initiating websocket tunnel to {}",ws_url);
 
+    let mut upstream_request = ws_url.clone().into_client_request()
+        .map_err(|e|CustomError(format!("invalid websocket url {ws_url}: {e:?}")))?;
+
+    if let Some(client_addr) = service.remote_addr {
+        if target.forwarded_headers_enabled() {
+            let original_host = super::utils::original_host(&req, &req_host_name);
+            let client_is_trusted_proxy = service.state.config.read().await.is_trusted_proxy(client_addr.ip());
+            for (name,value) in super::utils::forwarded_headers(req.headers(), client_addr.ip(), service.is_https_only, &original_host, client_is_trusted_proxy) {
+                upstream_request.headers_mut().insert(name, value);
+            }
+        }
+        let context = target.header_rule_context(client_addr.ip(), &req_host_name, service.is_https_only);
+        crate::configuration::v2::apply_header_rules(&target.request_header_rules(), upstream_request.headers_mut(), &context);
+    }
+
+    for (name,value) in auth_headers {
+        upstream_request.headers_mut().append(name, value);
+    }
+
     let client_tls_config = ClientConfig::builder_with_protocol_versions(tokio_rustls::rustls::ALL_VERSIONS)
         .with_native_roots()
         .expect("should always be able to build a tls client")
         .with_no_client_auth();
     
-    let upstream_client = match tokio_tungstenite::connect_async_tls_with_config(
-        ws_url.clone(),
+    let upstream_connection = tokio_tungstenite::connect_async_tls_with_config(
+        upstream_request,
         None,
         true,
         Some(tokio_tungstenite::Connector::Rustls(Arc::new(client_tls_config)))
-    ).await {
-        Ok(x) => {
+    );
+    // the handshake has to complete within the connect timeout unless a first byte timeout is configured
+    let upstream_client = match tokio::time::timeout(timeouts.connect() + timeouts.first_byte().unwrap_or_default(), upstream_connection).await {
+        Err(_) => {
+            tracing::warn!("Timed out while connecting to the target websocket {ws_url}");
+            return Err(CustomError(String::from("timed out while connecting to the target websocket")))
+        },
+        Ok(Ok(x)) => {
             tracing::debug!("Successfully connected to target websocket");
             x
         },
-        Err(e) => {
+        Ok(Err(e)) => {
           
             tracing::warn!("FAILED TO CONNECT TO TARGET WEBSOCKET: {:?}",e);
             return Err(CustomError(format!("failed to connect to target websocket: {e:?}")))
@@ -128,11 +177,11 @@ pub async fn handle_ws(req:Request<IncomingBody>,service:ReverseProxyService,ws:
     let con = create_connection(
         req,
         target,
-        &service.remote_addr.expect("there must be a client socket.."),
+        &service,
         target_is_tls,
         target_version,
         &ws_url,
-        service.is_https_only
+        backend_key
     );
 
     let con_key = add_connection(service.state.clone(),  con.clone()).await;
@@ -189,13 +238,14 @@ async fn del_connection(global_state:Arc<GlobalState>,key:&ConnectionKey) {
 fn create_connection(
     req:Request<IncomingBody>,
     target:Target,
-    client_addr:&SocketAddr,
+    service:&ReverseProxyService,
     target_is_tls:bool,
     target_version: hyper::http::Version,
     target_addr: &str,
-    known_tls_only: bool
+    backend_key: Option<String>
 ) -> ProxyActiveConnection {
     
+    let known_tls_only = service.is_https_only;
     let typ_info = 
         ProxyActiveConnectionType::TerminatingWs { 
             incoming_scheme: req.uri().scheme_str().unwrap_or(if known_tls_only { "WSS" } else {"WS"} ).to_owned(), 
@@ -209,8 +259,9 @@ fn create_connection(
             Target::Proc(p) => p.host_name.clone(),
             Target::Remote(r) => r.host_name.clone()
         },
-        source_addr: client_addr.clone(),
+        source_addr: service.remote_addr.expect("there must be a client socket.."),
         target_addr: target_addr.to_owned(),
+        backend_key,
         creation_time: Local::now(),
         description: Some(format!("websocket connection")),
         connection_type: typ_info
@@ -46,6 +46,12 @@ mod self_update;
 use types::app_state::AppState;
 use lazy_static::lazy_static;
 mod letsencrypt;
+mod health_checks;
+mod config_reload;
+mod access_log;
+mod rate_limit;
+mod http_cache;
+mod outlier_detection;
 
 lazy_static! {
     static ref PROC_THREAD_MAP: Arc<DashMap<ProcId, ProcInfo>> = Arc::new(DashMap::new());
@@ -141,9 +147,10 @@ pub mod global_state {
                 let port = y.active_port.unwrap_or_default();
                 if port > 0 {
                     let t = ReverseTcpProxyTarget {
-                        disable_tcp_tunnel_mode: y.disable_tcp_tunnel_mode.unwrap_or_default(),
+                        disable_tcp_tunnel_mode: y.tcp_tunnel_mode_disabled(),
                         remote_target_config: None, // we dont need this for hosted processes
                         hosted_target_config: Some(y.clone()),
+                        static_site_config: None,
                         capture_subdomains: y.capture_subdomains.unwrap_or_default(),
                         forward_wildcard: y.forward_subdomains.unwrap_or_default(),
                         backends: vec![crate::configuration::v2::Backend {
@@ -154,7 +161,12 @@ pub mod global_state {
                             // and possibly invalidate sessions in some cases.
                             address: "localhost".to_string(), //y.host_name.to_owned(), // --- configurable
                             https: y.https,
-                            port: y.active_port.unwrap_or_default()
+                            port: y.active_port.unwrap_or_default(),
+                            health_check: None,
+                            weight: None,
+                            proxy_protocol: y.proxy_protocol.clone(),
+                            timeouts: None,
+                            outlier_detection: None
                         }],
                         host_name: y.host_name.to_string(),
                         is_hosted: true,
@@ -178,9 +190,10 @@ pub mod global_state {
                     let sub_domain = filter_result.and_then(|x|x);
 
                     let t = ReverseTcpProxyTarget { 
-                        disable_tcp_tunnel_mode: y.disable_tcp_tunnel_mode.unwrap_or_default(),
+                        disable_tcp_tunnel_mode: y.tcp_tunnel_mode_disabled(),
                         hosted_target_config: None,
                         remote_target_config: Some(y.clone()),
+                        static_site_config: None,
                         capture_subdomains: y.capture_subdomains.unwrap_or_default(),
                         forward_wildcard: y.forward_subdomains.unwrap_or_default(),
                         backends: y.backends.clone(),
@@ -196,6 +209,32 @@ pub mod global_state {
                 }
             }
 
+            if result.is_none() {
+                for y in cfg.static_site.iter().flatten() {
+
+                    let filter_result = Self::filter_fun(pre_filter_hostname, &y.host_name, y.capture_subdomains.unwrap_or_default());
+                    if filter_result.is_none() { continue };
+
+                    // static sites are served by the terminating proxy, so there is nothing to tunnel to
+                    let t = ReverseTcpProxyTarget {
+                        disable_tcp_tunnel_mode: true,
+                        hosted_target_config: None,
+                        remote_target_config: None,
+                        static_site_config: Some(y.clone()),
+                        capture_subdomains: y.capture_subdomains.unwrap_or_default(),
+                        forward_wildcard: false,
+                        backends: vec![],
+                        host_name: y.host_name.to_owned(),
+                        is_hosted: false,
+                        sub_domain: filter_result.and_then(|x|x)
+                    };
+                    let shared_result = Arc::new(t);
+                    self.reverse_tcp_proxy_target_cache.insert(pre_filter_hostname.into(), shared_result.clone());
+                    result = Some(shared_result);
+                    break;
+                }
+            }
+
             result
             
         }
@@ -424,6 +463,9 @@ async fn main() -> anyhow::Result<()> {
 
 
     tokio::task::spawn(crate::letsencrypt::bg_worker_for_lets_encrypt_certs(global_state.clone()));
+    tokio::task::spawn(crate::health_checks::bg_worker_for_backend_health_checks(global_state.clone()));
+    tokio::task::spawn(crate::config_reload::bg_worker_for_config_file_changes(global_state.clone()));
+    tokio::task::spawn(crate::access_log::bg_worker_for_access_log(global_state.clone()));
     
     // Spawn task for the admin api if enabled
     if let Some(api_port) = api_port {
@@ -0,0 +1,73 @@
+use std::time::Instant;
+
+use crate::configuration::v2::Backend;
+use crate::global_state::GlobalState;
+use crate::http_proxy::{ProxyCallResult, ProxyError};
+
+pub enum Outcome {
+    Success,
+    /// The backend could not be reached, or did not respond in time
+    Failed(String),
+    ServerError(hyper::StatusCode)
+}
+
+impl Outcome {
+    /// Errors that are not caused by the backend, such as invalid requests, are not counted.
+    pub fn of_proxy_call(result:&Result<ProxyCallResult,ProxyError>) -> Option<Outcome> {
+        match result {
+            Ok(ProxyCallResult::NormalResponse(response)) if response.status().is_server_error() => Some(Outcome::ServerError(response.status())),
+            Ok(_) => Some(Outcome::Success),
+            Err(ProxyError::LegacyError(e)) if e.is_connect() => Some(Outcome::Failed(format!("connection failed: {e:?}"))),
+            Err(ProxyError::Timeout(e)) => Some(Outcome::Failed(e.clone())),
+            Err(_) => None
+        }
+    }
+}
+
+/// Records the result of a request or tcp tunnel for backends that have outlier detection configured.
+/// Like the health checks, breakers are kept per site so that sites sharing a backend do not eject it for each other.
+pub fn record(state:&GlobalState, host_name:&str, backend:&Backend, outcome:Outcome) {
+
+    let Some(config) = &backend.outlier_detection else { return };
+    let key = crate::health_checks::health_key(host_name, backend);
+    let now = Instant::now();
+
+    let error = match outcome {
+        Outcome::Success => {
+            if let Some(mut breaker) = state.app_state.circuit_breakers.get_mut(&key) {
+                if breaker.record_success(config, now) {
+                    tracing::info!("Backend {key} responded successfully after being ejected and is back in rotation");
+                }
+            }
+            return
+        },
+        Outcome::ServerError(_) if !config.count_server_errors.unwrap_or(true) => return,
+        Outcome::ServerError(status) => format!("responded with {status}"),
+        Outcome::Failed(error) => error
+    };
+
+    let mut breaker = state.app_state.circuit_breakers.entry(key.clone()).or_default();
+    if breaker.record_failure(config, now, error.clone()) {
+        tracing::warn!("Backend {key} is ejected for {}s as requests to it keep failing: {error}", config.cooldown().as_secs());
+    }
+}
+
+/// True if the backend has been ejected and its cool-down is not over yet,
+/// or if it is half-open and already has as many trial requests in flight as it may have.
+pub fn is_ejected(state:&GlobalState, host_name:&str, backend:&Backend) -> bool {
+    match &backend.outlier_detection {
+        Some(config) => state.app_state.circuit_breakers.get(&crate::health_checks::health_key(host_name, backend))
+            .is_some_and(|x| !x.accepts_request(config, Instant::now())),
+        None => false
+    }
+}
+
+/// Called when a backend has been selected for a request. Requests to half-open backends count as trial requests,
+/// returns false if the backend has no trial requests left.
+pub fn start_request(state:&GlobalState, host_name:&str, backend:&Backend) -> bool {
+    let Some(config) = &backend.outlier_detection else { return true };
+    match state.app_state.circuit_breakers.get_mut(&crate::health_checks::health_key(host_name, backend)) {
+        Some(mut breaker) => breaker.start_request(config, Instant::now()),
+        None => true
+    }
+}
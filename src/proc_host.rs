@@ -1,15 +1,199 @@
+use crate::configuration::v2::{HealthCheck, HealthCheckKind, ReadinessCheck, ReadinessCheckKind, RestartBackoff, RestartPolicy, StopSignal};
 use crate::configuration::LogFormat;
 use crate::global_state::GlobalState;
 use crate::http_proxy::ProcMessage;
 use crate::types::app_state::ProcState;
-use std::collections::HashMap;
-use std::io::Write;
+use crate::types::proc_info::ProcId;
+use std::collections::{HashMap, VecDeque};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+
+/// Waits until a hosted process is ready to receive requests and returns the port it is listening on.
+/// Processes with a readiness check are ready once the check has passed,
+/// other processes are considered ready once their port accepts tcp connections.
+pub async fn wait_until_ready(state:&GlobalState, host_name:&str, proc_id:&ProcId, timeout:Duration) -> Option<u16> {
+    let started_at = Instant::now();
+    while started_at.elapsed() < timeout {
+        let proc_state = state.app_state.site_status_map.get(host_name).map(|x|x.value().clone());
+        let active_port = crate::PROC_THREAD_MAP.get(proc_id).and_then(|x| if x.pid.is_some() { x.config.active_port } else { None });
+        match (proc_state,active_port) {
+            (Some(ProcState::Ready),Some(port)) => return Some(port),
+            (Some(ProcState::Running),Some(port)) => {
+                if crate::health_checks::tcp_probe("localhost", port, Duration::from_millis(500)).await.is_ok() {
+                    return Some(port)
+                }
+            },
+            _ => {}
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    None
+}
+
+// runs the tcp or http readiness check until it passes or the readiness timeout is reached.
+// log line checks are handled by the output readers.
+async fn wait_for_readiness_probe(check:ReadinessCheck, port:u16, https:bool, is_ready:Arc<AtomicBool>) {
+    let started_at = Instant::now();
+    let probe_timeout = Duration::from_secs(2);
+    while started_at.elapsed() < check.timeout() {
+        let result = match check.kind {
+            ReadinessCheckKind::Http => {
+                let url = crate::health_checks::probe_url(https, "localhost", port, check.path.as_deref());
+                crate::health_checks::http_probe(&url, probe_timeout).await
+            },
+            _ => crate::health_checks::tcp_probe("localhost", port, probe_timeout).await
+        };
+        if result.is_ok() {
+            is_ready.store(true, std::sync::atomic::Ordering::SeqCst);
+            return
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+}
+
+// probes a running process until the liveness check has failed too many times in a row,
+// then returns the last failure so that the caller can restart the process.
+async fn monitor_liveness(check:HealthCheck, port:u16, https:bool) -> String {
+    let mut consecutive_failures = 0;
+    loop {
+        tokio::time::sleep(check.interval()).await;
+        let result = match check.kind {
+            HealthCheckKind::Http => {
+                let url = crate::health_checks::probe_url(https, "localhost", port, check.path.as_deref());
+                crate::health_checks::http_probe(&url, check.timeout()).await
+            },
+            HealthCheckKind::Tcp => crate::health_checks::tcp_probe("localhost", port, check.timeout()).await
+        };
+        match result {
+            Ok(()) => consecutive_failures = 0,
+            Err(e) => {
+                consecutive_failures += 1;
+                if consecutive_failures >= check.unhealthy_threshold() {
+                    return format!("liveness check failed {consecutive_failures} times in a row: {e}")
+                }
+            }
+        }
+    }
+}
 
+// keeps track of automatic restarts so that we can back off and detect crash loops
+#[derive(Default)]
+struct RestartTracker {
+    consecutive_restarts : u32,
+    recent_failures : VecDeque<Instant>
+}
+
+impl RestartTracker {
+    // records a failure of a process that ran for the given duration and returns the delay before the next restart
+    fn record_failure(&mut self, backoff:&RestartBackoff, ran_for:Duration, now:Instant) -> Duration {
+        if ran_for > backoff.crash_loop_window() {
+            self.consecutive_restarts = 0;
+        }
+        self.consecutive_restarts = self.consecutive_restarts.saturating_add(1);
+        self.recent_failures.push_back(now);
+        while self.recent_failures.front().is_some_and(|x| now.duration_since(*x) > backoff.crash_loop_window()) {
+            self.recent_failures.pop_front();
+        }
+        backoff.delay_for_attempt(self.consecutive_restarts)
+    }
+    fn is_crash_looping(&self, backoff:&RestartBackoff) -> bool {
+        self.recent_failures.len() >= backoff.crash_loop_threshold() as usize
+    }
+    fn exceeds(&self, max_restarts:Option<u32>) -> bool {
+        max_restarts.is_some_and(|max| self.consecutive_restarts > max)
+    }
+}
+
+// a process has failed if it could not be started, failed its checks or exited with anything but a zero exit code
+fn has_failed(failure_reason:Option<&str>, exit_status:Option<std::process::ExitStatus>) -> bool {
+    failure_reason.is_some() || !exit_status.is_some_and(|x|x.success())
+}
+
+fn should_restart(policy:&RestartPolicy, failed:bool) -> bool {
+    match policy {
+        RestartPolicy::Always => true,
+        RestartPolicy::OnFailure => failed,
+        RestartPolicy::Never => false
+    }
+}
+
+// processes are started in their own process group so that we can stop anything they have started as well,
+// which means that the group id is the same as the pid of the process.
+#[cfg(unix)]
+fn signal_process_group(child:&std::process::Child, signal:i32) -> bool {
+    unsafe { libc::kill(-(child.id() as i32), signal) == 0 }
+}
+
+#[cfg(unix)]
+fn unix_signal(signal:&StopSignal) -> i32 {
+    match signal {
+        StopSignal::Term => libc::SIGTERM,
+        StopSignal::Int => libc::SIGINT,
+        StopSignal::Quit => libc::SIGQUIT,
+        StopSignal::Hup => libc::SIGHUP,
+        StopSignal::Kill => libc::SIGKILL
+    }
+}
+
+#[cfg(windows)]
+fn taskkill(child:&std::process::Child, force:bool) -> bool {
+    let pid = child.id().to_string();
+    let mut args = vec!["/PID", pid.as_str(), "/T"];
+    if force {
+        args.push("/F");
+    }
+    Command::new("taskkill").args(args).stdout(Stdio::null()).stderr(Stdio::null()).status().is_ok_and(|x|x.success())
+}
+
+/// Asks the process and all of its children to stop, and kills them if they have not exited within the grace period.
+async fn stop_process(child:&mut std::process::Child, host_name:&str, signal:&StopSignal, grace_period:Duration) {
+
+    let started_at = Instant::now();
+
+    #[cfg(unix)]
+    {
+        signal_process_group(child, unix_signal(signal));
+        loop {
+            // reap the process itself so that it does not keep the group alive as a zombie
+            _ = child.try_wait();
+            if !signal_process_group(child, 0) {
+                return
+            }
+            if started_at.elapsed() >= grace_period {
+                break
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        tracing::warn!("[{host_name}] Did not stop within {}s, killing it.", grace_period.as_secs());
+        signal_process_group(child, libc::SIGKILL);
+    }
+
+    #[cfg(windows)]
+    {
+        _ = signal; // there is no equivalent of unix signals for detached processes on windows
+        taskkill(child, false);
+        while let Ok(None) = child.try_wait() {
+            if started_at.elapsed() >= grace_period {
+                tracing::warn!("[{host_name}] Did not stop within {}s, killing it.", grace_period.as_secs());
+                taskkill(child, true);
+                break
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    _ = child.kill();
+    for _ in 0..20 {
+        if let Ok(Some(_)) = child.try_wait() {
+            break
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
 
 pub async fn host(
     mut resolved_proc: crate::configuration::v2::FullyResolvedInProcessSiteConfig,
@@ -22,6 +206,9 @@ pub async fn host(
     crate::PROC_THREAD_MAP.insert(resolved_proc.proc_id.clone(), crate::types::proc_info::ProcInfo { 
         config: resolved_proc.clone(),
         pid: None,
+        restart_count: 0,
+        last_failure: None,
+        last_exit_code: None,
         liveness_ptr: std::sync::Arc::<AtomicBool>::downgrade(&my_arc) 
     });
 
@@ -57,14 +244,21 @@ pub async fn host(
 
     let mut selected_port: Option<u16> = None;
 
-    let mut missing_bin: bool = false;
+    let stop_signal = resolved_proc.stop_signal.clone().unwrap_or_default();
+    let stop_timeout = resolved_proc.stop_timeout();
 
-    loop {
+    let restart_policy = resolved_proc.restart_policy.clone().unwrap_or_default();
+    let restart_backoff = resolved_proc.restart_backoff.clone().unwrap_or_default();
+    let mut restarts = RestartTracker::default();
+    
+    // set when the restart policy has disabled the process so that we keep showing why it is not running
+    let mut stopped_by_restart_policy = false;
 
-        if missing_bin {
-            // dont want to try this too often if file is gone
-            tokio::time::sleep(Duration::from_secs(10)).await;
-        }
+    let depends_on = resolved_proc.depends_on.clone().unwrap_or_default();
+    let stop_with_dependencies = resolved_proc.stop_with_dependencies.unwrap_or_default();
+    let mut waiting_for_dependencies = false;
+
+    loop {
 
         {
             let entry = crate::PROC_THREAD_MAP.get_mut(&resolved_proc.proc_id);
@@ -92,7 +286,7 @@ pub async fn host(
         if initialized == false {
             state.app_state.site_status_map.insert(resolved_proc.host_name.clone(), ProcState::Stopped);
             initialized = true;
-        } else {
+        } else if !stopped_by_restart_policy && !waiting_for_dependencies {
             state.app_state.site_status_map.insert(resolved_proc.host_name.clone(), ProcState::Stopped);
             
         }
@@ -128,6 +322,11 @@ pub async fn host(
                     let is_for_me = s == "all" || acceptable_names.contains(&s); 
                     if is_for_me {
                         enabled = false;
+                    } else if stop_with_dependencies && depends_on.contains(&s) {
+                        tracing::info!("[{}] Stopping as its dependency {s} is being stopped",&resolved_proc.host_name);
+                        enabled = false;
+                        // lets anything that depends on this site know that it is being stopped as well
+                        _ = state.broadcaster.send(ProcMessage::Stop(resolved_proc.host_name.clone()));
                     }
                 }
             }
@@ -140,14 +339,36 @@ pub async fn host(
                     state.app_state.site_status_map.insert(resolved_proc.host_name.clone(), ProcState::Stopped);
                 }
             }
+            waiting_for_dependencies = false;
             continue;
         }
 
         
         if enabled != is_enabled_before {
             tracing::info!("[{}] Enabled via command from proxy service",&resolved_proc.host_name);
+            stopped_by_restart_policy = false;
+            restarts = RestartTracker::default();
         }
 
+        // dependencies are started first, and we keep checking on them here so that stop commands are still handled while waiting
+        let dependencies_not_running : Vec<&String> = depends_on.iter().filter(|x| !matches!(
+            state.app_state.site_status_map.get(*x).map(|s|s.value().clone()),
+            Some(ProcState::Running) | Some(ProcState::Ready)
+        )).collect();
+        if !dependencies_not_running.is_empty() {
+            if !waiting_for_dependencies {
+                tracing::info!("[{}] Waiting for dependencies to start: {}",&resolved_proc.host_name,
+                    dependencies_not_running.iter().map(|x|x.as_str()).collect::<Vec<_>>().join(", "));
+                for dependency in &dependencies_not_running {
+                    _ = state.broadcaster.send(ProcMessage::Start(dependency.to_string()));
+                }
+                waiting_for_dependencies = true;
+            }
+            state.app_state.site_status_map.insert(resolved_proc.host_name.clone(), ProcState::Starting);
+            continue;
+        }
+        waiting_for_dependencies = false;
+
         
 
         
@@ -182,15 +403,11 @@ pub async fn host(
         tracing::warn!("[{}] Executing command '{}' in directory '{}'",resolved_proc.host_name,resolved_proc.bin,workdir);
 
         
-        let resolved_bin_path = if let Some(p) = resolve_bin_path(&workdir, &resolved_proc.bin) {
-            missing_bin = false;
-            p
-        } else {
+        // a missing binary is handled like any other failure to start so that the restart policy decides when to try again
+        let resolved_bin_path = resolve_bin_path(workdir, &resolved_proc.bin).ok_or_else(||{
             tracing::error!("Failed to resolve path of binary for site: '{}' - workdir: {}, bin: {}",&resolved_proc.host_name,workdir,resolved_proc.bin);
-            state.app_state.site_status_map.insert(resolved_proc.host_name.clone(), ProcState::Faulty);
-            missing_bin = true;
-            continue
-        };
+            std::io::Error::new(std::io::ErrorKind::NotFound, format!("could not find the binary '{}'",resolved_proc.bin))
+        });
 
         
         let mut process_specific_environment_variables = HashMap::new();
@@ -234,7 +451,7 @@ pub async fn host(
         use std::os::windows::process::CommandExt;
         
         #[cfg(target_os = "windows")] 
-        let cmd = Command::new(resolved_bin_path)
+        let cmd = resolved_bin_path.and_then(|bin| Command::new(bin)
             .args(pre_resolved_args)
             .envs(&process_specific_environment_variables)
             .current_dir(&workdir)
@@ -242,23 +459,59 @@ pub async fn host(
             .stderr(Stdio::piped())
             .stdin(Stdio::null())
             // dont want windows to let child take over our keyboard input and such
-            .creation_flags(DETACHED_PROCESS).spawn(); 
+            .creation_flags(DETACHED_PROCESS).spawn()); 
+
+        #[cfg(not(target_os = "windows"))]
+        use std::os::unix::process::CommandExt;
 
         #[cfg(not(target_os = "windows"))]
-        let cmd = Command::new(resolved_bin_path)
+        let cmd = resolved_bin_path.and_then(|bin| Command::new(bin)
             .args(pre_resolved_args)
             .envs(&process_specific_environment_variables)
             .current_dir(&workdir)
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .stdin(Stdio::null())
-            .spawn();
+            // run in a separate process group so that we can stop wrappers such as npm together with their children
+            .process_group(0)
+            .spawn());
+
+        // set when we kill the process ourselves because it failed its readiness or liveness checks
+        let mut failure_reason : Option<String> = None;
+        let mut exit_status : Option<std::process::ExitStatus> = None;
+        let spawned_at = Instant::now();
 
         match cmd {
             Ok(mut child) => {
 
-                
-                state.app_state.site_status_map.insert(resolved_proc.host_name.clone(), ProcState::Running);
+                // without a readiness check we consider the process to be running as soon as it has been spawned
+                let readiness_check = resolved_proc.readiness_check.clone();
+                let readiness_timeout = readiness_check.as_ref().map(|x|x.timeout()).unwrap_or_default();
+                let started_at = Instant::now();
+                let is_ready = Arc::new(AtomicBool::new(false));
+                let mut waiting_for_readiness = readiness_check.is_some();
+                let mut marked_as_running = !waiting_for_readiness;
+                if !waiting_for_readiness {
+                    state.app_state.site_status_map.insert(resolved_proc.host_name.clone(), ProcState::Running);
+                }
+
+                let ready_log_pattern = readiness_check.as_ref()
+                    .filter(|x| x.kind == ReadinessCheckKind::LogLine)
+                    .and_then(|x| x.log_pattern.as_ref())
+                    .and_then(|x| regex::Regex::new(x).ok());
+
+                let readiness_probe = match &readiness_check {
+                    Some(check) if check.kind != ReadinessCheckKind::LogLine => Some(tokio::spawn(wait_for_readiness_probe(
+                        check.clone(),
+                        selected_port.unwrap_or_default(),
+                        resolved_proc.https.unwrap_or_default(),
+                        is_ready.clone()
+                    ))),
+                    _ => None
+                };
+
+                // liveness checks only start once the process is ready so that slow starting processes are not restarted
+                let mut liveness_monitor : Option<tokio::task::JoinHandle<String>> = None;
                 {
                     let entry = crate::PROC_THREAD_MAP.get_mut(&resolved_proc.proc_id);
                     match entry {
@@ -284,6 +537,8 @@ pub async fn host(
                 let procname = resolved_proc.host_name.clone();
                 let reclone = re.clone();
                 let logformat = resolved_proc.log_format.clone();
+                let ready_pattern = ready_log_pattern.clone();
+                let ready_flag = is_ready.clone();
                 _ = std::thread::Builder::new().name(format!("{procname}")).spawn(move || {
                     
                     let mut current_log_level = 0;
@@ -291,6 +546,10 @@ pub async fn host(
                     for line in std::io::BufRead::lines(stdout_reader) {
                         if let Ok(line) = line{
 
+                            if ready_pattern.as_ref().is_some_and(|x|x.is_match(&line)) {
+                                ready_flag.store(true, std::sync::atomic::Ordering::SeqCst);
+                            }
+
                             // todo: should move custom logging elsewhere if theres ever more than one
                             if let Some(LogFormat::dotnet) = &logformat {
                                 if line.len() > 0 {
@@ -327,9 +586,14 @@ pub async fn host(
                 });
 
                 let procname = resolved_proc.host_name.clone();
+                let ready_pattern = ready_log_pattern;
+                let ready_flag = is_ready.clone();
                 _ = std::thread::Builder::new().name(format!("{procname}")).spawn(move || {
                     for line in std::io::BufRead::lines(stderr_reader) {
                         if let Ok(line) = line{
+                            if ready_pattern.as_ref().is_some_and(|x|x.is_match(&line)) {
+                                ready_flag.store(true, std::sync::atomic::Ordering::SeqCst);
+                            }
                             if line.len() > 0 {
                                 tracing::error!("{}",line.trim());
                             }
@@ -337,13 +601,70 @@ pub async fn host(
                     }
                 });
                 
-                while let Ok(None) = child.try_wait() {
+                loop {
+
+                    match child.try_wait() {
+                        Ok(None) => {},
+                        Ok(Some(status)) => {
+                            exit_status = Some(status);
+                            break
+                        },
+                        Err(e) => {
+                            tracing::warn!("[{}] Failed to check if the process is still running: {e:?}", resolved_proc.host_name);
+                            break
+                        }
+                    }
+
+                    if waiting_for_readiness {
+                        if is_ready.load(std::sync::atomic::Ordering::SeqCst) {
+                            waiting_for_readiness = false;
+                            tracing::info!("[{}] Ready after {}ms", resolved_proc.host_name, started_at.elapsed().as_millis());
+                            state.app_state.site_status_map.insert(resolved_proc.host_name.clone(), ProcState::Ready);
+                        } else if started_at.elapsed() > readiness_timeout {
+                            tracing::warn!("[{}] Did not become ready within {}s, restarting the process.", resolved_proc.host_name, readiness_timeout.as_secs());
+                            failure_reason = Some(format!("did not become ready within {}s", readiness_timeout.as_secs()));
+                            state.app_state.site_status_map.insert(resolved_proc.host_name.clone(), ProcState::Faulty);
+                            stop_process(&mut child, &resolved_proc.host_name, &stop_signal, stop_timeout).await;
+                            break;
+                        }
+                    }
+
+                    // a process that passed its readiness check moves on to running on the next round.
+                    // anything that misses the ready state sees it as running, and checks that its port accepts connections.
+                    if !waiting_for_readiness && !marked_as_running && matches!(
+                        state.app_state.site_status_map.get(&resolved_proc.host_name).map(|x|x.value().clone()),
+                        Some(ProcState::Ready)
+                    ) {
+                        state.app_state.site_status_map.insert(resolved_proc.host_name.clone(), ProcState::Running);
+                        marked_as_running = true;
+                    }
+
+                    if !waiting_for_readiness && liveness_monitor.is_none() {
+                        if let Some(check) = &resolved_proc.liveness_check {
+                            liveness_monitor = Some(tokio::spawn(monitor_liveness(
+                                check.clone(),
+                                selected_port.unwrap_or_default(),
+                                resolved_proc.https.unwrap_or_default()
+                            )));
+                        }
+                    }
+
+                    if liveness_monitor.as_ref().is_some_and(|x| x.is_finished()) {
+                        if let Some(monitor) = liveness_monitor.take() {
+                            let reason = monitor.await.unwrap_or_else(|e| format!("liveness monitor failed: {e}"));
+                            tracing::warn!("[{}] Restarting the process as it appears to be hanging: {reason}", resolved_proc.host_name);
+                            failure_reason = Some(reason);
+                            state.app_state.site_status_map.insert(resolved_proc.host_name.clone(), ProcState::Faulty);
+                            stop_process(&mut child, &resolved_proc.host_name, &stop_signal, stop_timeout).await;
+                            break;
+                        }
+                    }
                     
                     let exit = state.app_state.exit.load(std::sync::atomic::Ordering::SeqCst) == true;
                     if exit {
                         tracing::info!("[{}] Stopping due to app exit", resolved_proc.host_name);
                         state.app_state.site_status_map.insert(resolved_proc.host_name.clone(), ProcState::Stopping);
-                        _ = child.kill();
+                        stop_process(&mut child, &resolved_proc.host_name, &stop_signal, stop_timeout).await;
                         break
                     }
                     
@@ -354,10 +675,10 @@ pub async fn host(
                                 if acceptable_names.contains(&s) {
                                     tracing::warn!("[{}] Dropping due to having been deleted by proxy.", resolved_proc.host_name);
                                     state.app_state.site_status_map.remove(&resolved_proc.host_name);
-                                    if let Some(mut stdin) = child.stdin.take() {
-                                        _ = stdin.write_all(b"q");
-                                    } 
-                                    _ = child.kill();
+                                    stop_process(&mut child, &resolved_proc.host_name, &stop_signal, stop_timeout).await;
+                                    if let Some(monitor) = liveness_monitor.take() {
+                                        monitor.abort();
+                                    }
                                     // inform sender that we actually stopped the process and that we are exiting our loop
                                     match sender.send(0).await {
                                         Ok(_) => {},
@@ -374,6 +695,11 @@ pub async fn host(
                                 let is_for_me = s == "all" || acceptable_names.contains(&s); 
                                 if is_for_me {
                                     enabled = false;
+                                } else if stop_with_dependencies && depends_on.contains(&s) {
+                                    tracing::info!("[{}] Stopping as its dependency {s} is being stopped",&resolved_proc.host_name);
+                                    enabled = false;
+                                    // lets anything that depends on this site know that it is being stopped as well
+                                    _ = state.broadcaster.send(ProcMessage::Stop(resolved_proc.host_name.clone()));
                                 }
                             },
                             _ => {}
@@ -381,40 +707,90 @@ pub async fn host(
                     }
                     if !enabled {
                         tracing::warn!("[{}] Stopping due to having been disabled by proxy.", resolved_proc.host_name);
-                        // note: we just send q here because some apps like iisexpress requires it
                         
                         state.app_state.site_status_map.insert(resolved_proc.host_name.clone(), ProcState::Stopping);
                         
-                        if let Some(mut stdin) = child.stdin.take() {
-                            _ = stdin.write_all(b"q");
-                        } 
-                        _ = child.kill();
+                        stop_process(&mut child, &resolved_proc.host_name, &stop_signal, stop_timeout).await;
                         break;
                     } 
                     
                     tokio::time::sleep(Duration::from_millis(100)).await;
                 }
+                if let Some(probe) = readiness_probe {
+                    probe.abort();
+                }
+                if let Some(monitor) = liveness_monitor {
+                    monitor.abort();
+                }
                 state.app_state.site_status_map.insert(procname, ProcState::Stopped);
                 
             },
             Err(e) => {
                 tracing::info!("[{}] Failed to start! {e:?}",resolved_proc.host_name);
+                failure_reason = Some(format!("failed to start: {e}"));
                 state.app_state.site_status_map.insert(resolved_proc.host_name.clone(), ProcState::Faulty);                
             },
         }
         
         if enabled {
             if !state.app_state.exit.load(std::sync::atomic::Ordering::SeqCst) {
-                tracing::warn!("[{}] Stopped unexpectedly.. Will automatically restart the process in 5 seconds unless stopped.",resolved_proc.host_name);
-                state.app_state.site_status_map.insert(resolved_proc.host_name.clone(), ProcState::Faulty);
-                time_to_sleep_ms_after_each_iteration = 5000; // wait 5 seconds before restarting but NOT in here as we have a lock
+                
+                let exit_code = exit_status.and_then(|x|x.code());
+                let failed = has_failed(failure_reason.as_deref(), exit_status);
+                let reason = failure_reason.unwrap_or_else(|| match exit_code {
+                    Some(code) => format!("process exited with code {code}"),
+                    None => String::from("process exited unexpectedly")
+                });
+
+                if let Some(mut item) = crate::PROC_THREAD_MAP.get_mut(&resolved_proc.proc_id) {
+                    item.last_exit_code = exit_code;
+                    if failed {
+                        item.last_failure = Some(reason.clone());
+                    }
+                }
+
+                if !should_restart(&restart_policy, failed) {
+                    tracing::warn!("[{}] Stopped ({reason}). Not restarting the process due to the restart policy ({restart_policy:?}).",resolved_proc.host_name);
+                    state.app_state.site_status_map.insert(resolved_proc.host_name.clone(), if failed { ProcState::Faulty } else { ProcState::Stopped });
+                    stopped_by_restart_policy = true;
+                    enabled = false;
+                } else {
+                    let was_crash_looping = restarts.is_crash_looping(&restart_backoff);
+                    let delay = restarts.record_failure(&restart_backoff, spawned_at.elapsed(), Instant::now());
+                    if restarts.exceeds(resolved_proc.max_restarts) {
+                        tracing::error!("[{}] Stopped ({reason}). Giving up after {} restarts in a row.",resolved_proc.host_name,restarts.consecutive_restarts - 1);
+                        state.app_state.site_status_map.insert(resolved_proc.host_name.clone(), ProcState::Faulty);
+                        stopped_by_restart_policy = true;
+                        enabled = false;
+                    } else {
+                        if let Some(mut item) = crate::PROC_THREAD_MAP.get_mut(&resolved_proc.proc_id) {
+                            item.restart_count = item.restart_count.saturating_add(1);
+                        }
+                        if restarts.is_crash_looping(&restart_backoff) {
+                            // only announce the crash loop once so that it does not drown out everything else in the logs
+                            if !was_crash_looping {
+                                tracing::error!("[{}] Is crash looping ({reason}). Further restarts will only be logged at debug level.",resolved_proc.host_name);
+                            }
+                            tracing::debug!("[{}] Stopped ({reason}). Will automatically restart the process in {}s unless stopped.",resolved_proc.host_name,delay.as_secs());
+                            state.app_state.site_status_map.insert(resolved_proc.host_name.clone(), ProcState::CrashLooping);
+                        } else {
+                            tracing::warn!("[{}] Stopped unexpectedly ({reason}).. Will automatically restart the process in {}s unless stopped.",resolved_proc.host_name,delay.as_secs());
+                            state.app_state.site_status_map.insert(resolved_proc.host_name.clone(), ProcState::Faulty);
+                        }
+                        time_to_sleep_ms_after_each_iteration = delay.as_millis() as u64; // wait before restarting but NOT in here as we have a lock
+                    }
+                }
             } else {
                 tracing::info!("[{}] Stopped due to exit signal. Bye!",resolved_proc.host_name);
                 break
             }
         }
             
-        tokio::time::sleep(Duration::from_millis(time_to_sleep_ms_after_each_iteration)).await;
+        // backoff delays can be long, so we keep an eye on the exit signal while waiting
+        let sleep_until = Instant::now() + Duration::from_millis(time_to_sleep_ms_after_each_iteration);
+        while Instant::now() < sleep_until && !state.app_state.exit.load(std::sync::atomic::Ordering::SeqCst) {
+            tokio::time::sleep(Duration::from_millis(100).min(sleep_until.saturating_duration_since(Instant::now()))).await;
+        }
     }
 }
 
@@ -444,4 +820,127 @@ fn resolve_bin_path(workdir: &str, bin: &str) -> Option<PathBuf> {
         Ok(path) => Some(path),
         Err(_) => None,
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[tokio::test]
+    async fn readiness_probes_give_up_after_the_timeout() {
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("should bind");
+        let port = listener.local_addr().expect("should have an address").port();
+        let check = ReadinessCheck { kind: ReadinessCheckKind::Tcp, path: None, log_pattern: None, timeout_seconds: Some(1) };
+
+        let is_ready = Arc::new(AtomicBool::new(false));
+        wait_for_readiness_probe(check.clone(), port, false, is_ready.clone()).await;
+        assert!(is_ready.load(std::sync::atomic::Ordering::SeqCst));
+
+        drop(listener);
+        let is_ready = Arc::new(AtomicBool::new(false));
+        let started_at = Instant::now();
+        wait_for_readiness_probe(check, port, false, is_ready.clone()).await;
+        assert!(!is_ready.load(std::sync::atomic::Ordering::SeqCst));
+        assert!(started_at.elapsed() >= Duration::from_secs(1));
+        assert!(started_at.elapsed() < Duration::from_secs(4), "should not keep probing long after the timeout");
+
+    }
+
+    #[tokio::test]
+    async fn liveness_monitoring_returns_once_the_threshold_is_reached() {
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("should bind");
+        let port = listener.local_addr().expect("should have an address").port();
+        drop(listener);
+
+        let check = HealthCheck {
+            kind: HealthCheckKind::Tcp,
+            path: None,
+            interval_seconds: Some(1),
+            timeout_seconds: Some(1),
+            unhealthy_threshold: Some(2),
+            healthy_threshold: None
+        };
+        let started_at = Instant::now();
+        let reason = tokio::time::timeout(Duration::from_secs(10), monitor_liveness(check, port, false)).await
+            .expect("should give up on a process that is not listening");
+        assert!(reason.starts_with("liveness check failed 2 times in a row"), "{reason}");
+        assert!(started_at.elapsed() >= Duration::from_secs(2), "should wait for the interval between checks");
+
+    }
+
+    #[test]
+    fn restarts_back_off_until_the_process_is_crash_looping_or_out_of_restarts() {
+
+        let backoff = RestartBackoff {
+            initial_delay_seconds: Some(1),
+            max_delay_seconds: Some(4),
+            crash_loop_threshold: Some(3),
+            crash_loop_window_seconds: Some(60)
+        };
+        let start = Instant::now();
+        let at = |seconds:u64| start + Duration::from_secs(seconds);
+        let mut restarts = RestartTracker::default();
+
+        assert_eq!(restarts.record_failure(&backoff, Duration::from_secs(1), at(0)), Duration::from_secs(1));
+        assert_eq!(restarts.record_failure(&backoff, Duration::from_secs(1), at(2)), Duration::from_secs(2));
+        assert!(!restarts.is_crash_looping(&backoff));
+        assert_eq!(restarts.record_failure(&backoff, Duration::from_secs(1), at(5)), Duration::from_secs(4));
+        assert!(restarts.is_crash_looping(&backoff));
+        assert_eq!(restarts.record_failure(&backoff, Duration::from_secs(1), at(10)), Duration::from_secs(4), "the delay is capped");
+
+        assert!(!restarts.exceeds(None));
+        assert!(!restarts.exceeds(Some(4)));
+        assert!(restarts.exceeds(Some(3)));
+
+        // failures outside of the window no longer count towards the crash loop
+        restarts.record_failure(&backoff, Duration::from_secs(1), at(100));
+        assert!(!restarts.is_crash_looping(&backoff));
+
+        // and a process that stayed up for longer than the window starts over with the initial delay
+        assert_eq!(restarts.record_failure(&backoff, Duration::from_secs(120), at(300)), Duration::from_secs(1));
+        assert!(!restarts.exceeds(Some(1)));
+
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn restart_policies_decide_based_on_how_the_process_exited() {
+
+        let exit_with = |code:u8| Command::new("sh").args(["-c", &format!("exit {code}")]).status().expect("should run sh");
+        assert!(!has_failed(None, Some(exit_with(0))));
+        assert!(has_failed(None, Some(exit_with(3))));
+        assert!(has_failed(None, None));
+        assert!(has_failed(Some("readiness check timed out"), Some(exit_with(0))));
+
+        assert!(should_restart(&RestartPolicy::Always, false));
+        assert!(should_restart(&RestartPolicy::Always, true));
+        assert!(!should_restart(&RestartPolicy::OnFailure, false));
+        assert!(should_restart(&RestartPolicy::OnFailure, true));
+        assert!(!should_restart(&RestartPolicy::Never, true));
+
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn processes_are_signalled_and_killed_after_the_grace_period() {
+
+        use std::os::unix::process::CommandExt;
+
+        let mut child = Command::new("sleep").arg("30").process_group(0).spawn().expect("should start sleep");
+        let started_at = Instant::now();
+        stop_process(&mut child, "test", &StopSignal::Term, Duration::from_secs(5)).await;
+        assert!(child.try_wait().expect("should be able to wait").is_some());
+        assert!(started_at.elapsed() < Duration::from_secs(2), "sleep stops on SIGTERM so we should not wait for the grace period");
+
+        // processes that ignore the signal are killed once the grace period is over
+        let mut child = Command::new("sh").args(["-c", "trap '' TERM; sleep 30 & wait"]).process_group(0).spawn().expect("should start sh");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let started_at = Instant::now();
+        stop_process(&mut child, "test", &StopSignal::Term, Duration::from_secs(1)).await;
+        assert!(child.try_wait().expect("should be able to wait").is_some());
+        assert!(started_at.elapsed() >= Duration::from_secs(1));
+
+    }
+}
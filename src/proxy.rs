@@ -1,6 +1,5 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
 use hyper_rustls::ConfigBuilderExt;
 use lazy_static::lazy_static;
 use socket2::Socket;
@@ -45,18 +44,18 @@ pub async fn listen(
     let https_builder =
         hyper_rustls::HttpsConnectorBuilder::default().with_tls_config(client_tls_config);
     
-    let connector: hyper_rustls::HttpsConnector<hyper_util::client::legacy::connect::HttpConnector> = 
-        https_builder.https_or_http().enable_all_versions().build();
+    let connector: hyper_rustls::HttpsConnector<crate::http_proxy::TimeoutConnector> = 
+        https_builder.https_or_http().enable_all_versions().wrap_connector(crate::http_proxy::TimeoutConnector::new(state.clone()));
     
     let executor = hyper_util::rt::TokioExecutor::new();
     
     
-    let client : hyper_util::client::legacy::Client<hyper_rustls::HttpsConnector<hyper_util::client::legacy::connect::HttpConnector>, hyper::body::Incoming>  = 
+    let client : hyper_util::client::legacy::Client<hyper_rustls::HttpsConnector<crate::http_proxy::TimeoutConnector>, crate::http_proxy::UpstreamBody>  = 
         hyper_util::client::legacy::Builder::new(executor.clone())
         .http2_only(false)
         .build(connector.clone());
 
-    let h2_client : hyper_util::client::legacy::Client<hyper_rustls::HttpsConnector<hyper_util::client::legacy::connect::HttpConnector>, hyper::body::Incoming>  = 
+    let h2_client : hyper_util::client::legacy::Client<hyper_rustls::HttpsConnector<crate::http_proxy::TimeoutConnector>, crate::http_proxy::UpstreamBody>  = 
         hyper_util::client::legacy::Builder::new(executor)
         .http2_only(true)
         .build(connector);
@@ -68,6 +67,7 @@ pub async fn listen(
         remote_addr: None, 
         tx:tx.clone(), 
         is_https_only:false,
+        tls_port: bind_addr_tls.port(),
         client,
         h2_client
     };
@@ -274,19 +274,49 @@ async fn listen_https(
 async fn handle_new_tcp_stream(
     rustls_config: Option<std::sync::Arc<tokio_rustls::rustls::ServerConfig>>,
     mut fresh_service_template_with_source_info: ReverseProxyService,
-    tcp_stream: TcpStream,
+    mut tcp_stream: TcpStream,
     source_addr:SocketAddr,
     incoming_connection_is_on_tls_port: bool,
     tx: std::sync::Arc<tokio::sync::broadcast::Sender<ProcMessage>>,
     state: Arc<GlobalState>
 ) {
 
-    
+    // when we are behind a load balancer that uses the PROXY protocol, the real client address comes from its header
+    let accept_proxy_protocol = state.config.read().await.accept_proxy_protocol.unwrap_or_default();
+    let source_addr = if accept_proxy_protocol {
+        match tcp_proxy::proxy_protocol::read_header(&mut tcp_stream).await {
+            Ok(Some(client_addr)) => client_addr,
+            Ok(None) => source_addr,
+            Err(e) => {
+                tracing::warn!("Closing connection from {source_addr}: {e}");
+                return
+            }
+        }
+    } else {
+        source_addr
+    };
+    fresh_service_template_with_source_info.remote_addr = Some(source_addr);
+
+    if !state.config.read().await.client_is_allowed(source_addr.ip(), None) {
+        tracing::info!("Closing connection from {source_addr} as it is not allowed by the global allow/deny lists");
+        state.app_state.statistics.denied_connections.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        return
+    }
+
     let (mut managed_stream,peek_result) = 
         tcp_proxy::ReverseTcpProxy::eat_tcp_stream(tcp_stream, source_addr).await;
 
     managed_stream.seal();
 
+    // checked before deciding between tunnel and terminating mode so that tunnelled connections are covered as well
+    if let Ok(PeekResult { target_host: Some(target_host), .. }) = &peek_result {
+        if !state.config.read().await.client_is_allowed(source_addr.ip(), Some(target_host)) {
+            tracing::info!("Closing connection from {source_addr} to {target_host} as it is not allowed by the allow/deny lists of the site");
+            state.app_state.statistics.denied_connections.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            return
+        }
+    }
+
     match peek_result {
         
         // we see that this is cleartext data, and we expect clear text data, and we also extracted a hostname by peeking.
@@ -304,7 +334,8 @@ async fn handle_new_tcp_stream(
 
                 fresh_service_template_with_source_info.resolved_target = Some(cloned_target.clone());
                 
-                if target.disable_tcp_tunnel_mode == false && target.backends.iter().any(|x|{
+                // sites that force https are handed to the terminating proxy so that it can redirect the client
+                if !target.disable_tcp_tunnel_mode && !target.force_https() && target.backends.iter().any(|x|{
                     // todo : support checking for h2 hint so that we dont try to connect to a NOH2 backend
                     // if the incoming connections http_version is h2
                     x.https.unwrap_or_default()==false
@@ -326,30 +357,16 @@ async fn handle_new_tcp_stream(
                                 | Some(app_state::ProcState::Starting) => {
                                     _ = tx.send(ProcMessage::Start(target.host_name.clone()));
                                     let thn = target.host_name.clone();
-                                    let mut has_started = false;
                                     // done here to allow non-browser clients to reach the target socket without receiving unexpected loading screen html blobs
-                                    // as long as we are able to start the backing process within 10 seconds
+                                    // as long as the backing process becomes ready within its startup timeout
                                     tracing::debug!("handling an incoming request to a stopped target, waiting for {thn} to spin up - after this we will release the request to the terminating proxy and show a 'please wait' page instaead.");
-                                        
-                                    for _ in 1..100 {
-                                        match state.app_state.site_status_map.get(&target.host_name) {
-                                            Some(my_ref) => {
-                                                match my_ref.value() {
-                                                    app_state::ProcState::Running => {
-                                                        tracing::info!("{thn} is now running!");
-                                                        has_started = true;
-                                                        // give the hosted target some time to set up it's tcp listener
-                                                        tokio::time::sleep(Duration::from_millis(3000)).await;
-                                                        break
-                                                    },
-                                                    _ => { }
-                                                }
-                                            },
-                                            _ => { }
-                                        }
-                                        tokio::time::sleep(Duration::from_millis(100)).await;
-                                    }
+                                    
+                                    let has_started = match &target.hosted_target_config {
+                                        Some(cfg) => crate::proc_host::wait_until_ready(&state, &thn, cfg.get_id(), cfg.startup_timeout()).await.is_some(),
+                                        None => false
+                                    };
                                     if has_started {
+                                        tracing::info!("{thn} is now ready!");
                                         tracing::trace!("Using unencrypted tcp tunnel for remote target: {:?}",target.host_name);
                                         tcp_proxy::ReverseTcpProxy::tunnel(managed_stream, cloned_target, false,state.clone(),source_addr).await;
                                         return;
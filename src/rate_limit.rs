@@ -0,0 +1,222 @@
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::configuration::v2::RateLimit;
+
+lazy_static::lazy_static! {
+    static ref BUCKETS : dashmap::DashMap<(String,String),Bucket> = dashmap::DashMap::new();
+}
+
+// buckets that have not been used for a while are full again, so they can be dropped without changing any limits
+const MAX_BUCKETS : usize = 10_000;
+const IDLE_BUCKET_TIMEOUT : Duration = Duration::from_secs(600);
+
+#[derive(Debug)]
+struct Bucket {
+    tokens : f64,
+    updated : Instant,
+    allowed : u64,
+    limited : u64
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RateLimitCounter {
+    /// Host name of the site that the limit belongs to, or * for the global limit
+    pub scope : String,
+    /// Client ip, followed by the header value and path when the limit is configured to use them
+    pub key : String,
+    /// Requests that were left after the last request
+    pub available : u32,
+    pub allowed : u64,
+    pub limited : u64
+}
+
+/// The client ip, followed by the header value and path when the limit uses them.
+pub fn request_key(limit:&RateLimit, client_ip:IpAddr, headers:&hyper::HeaderMap, path:&str) -> String {
+    let mut key = client_ip.to_canonical().to_string();
+    if let Some(header) = &limit.per_header {
+        key.push(' ');
+        key.push_str(headers.get(header.as_str()).and_then(|x| x.to_str().ok()).unwrap_or("-"));
+    }
+    if limit.per_path.unwrap_or_default() {
+        key.push(' ');
+        key.push_str(path);
+    }
+    key
+}
+
+// refills the bucket of the key and returns it, creating a full bucket if the key has not been seen before
+fn refilled_bucket<'a>(scope:&str, limit:&RateLimit, key:&str) -> dashmap::mapref::one::RefMut<'a,(String,String),Bucket> {
+
+    if BUCKETS.len() > MAX_BUCKETS {
+        BUCKETS.retain(|_,bucket| bucket.updated.elapsed() < IDLE_BUCKET_TIMEOUT);
+    }
+
+    let mut bucket = BUCKETS.entry((scope.to_string(),key.to_string())).or_insert_with(|| Bucket {
+        tokens: limit.capacity(),
+        updated: Instant::now(),
+        allowed: 0,
+        limited: 0
+    });
+
+    let now = Instant::now();
+    let refill = now.duration_since(bucket.updated).as_secs_f64() * limit.refill_rate();
+    bucket.tokens = (bucket.tokens + refill).min(limit.capacity());
+    bucket.updated = now;
+    bucket
+}
+
+// time until the bucket has a token again, or None if it has one now
+fn time_until_available(bucket:&Bucket, limit:&RateLimit) -> Option<Duration> {
+    if bucket.tokens >= 1.0 {
+        None
+    } else {
+        let retry_after = (1.0 - bucket.tokens) / limit.refill_rate();
+        Some(Duration::try_from_secs_f64(retry_after).unwrap_or(Duration::from_secs(limit.period_seconds.unwrap_or(1))))
+    }
+}
+
+/// Checks all limits that apply to a request. Returns the time until the request would be allowed if any of them are exceeded.
+pub fn check(limits:&[(String,RateLimit)], client_ip:IpAddr, headers:&hyper::HeaderMap, path:&str) -> Result<(),Duration> {
+    let keys = limits.iter()
+        .map(|(scope,limit)| (scope.as_str(), limit, request_key(limit, client_ip, headers, path)))
+        .collect::<Vec<_>>();
+    check_keys(&keys)
+}
+
+/// Checks the limits that apply to a tcp tunnel. Tunnels only count towards limits that opt in to it,
+/// and there are no headers or paths to key them on.
+pub fn check_tunnel(limits:&[(String,RateLimit)], client_ip:IpAddr) -> Result<(),Duration> {
+    let headers = hyper::HeaderMap::new();
+    let keys = limits.iter()
+        .filter(|(_,limit)| limit.count_tunnels.unwrap_or_default())
+        .map(|(scope,limit)| (scope.as_str(), limit, request_key(limit, client_ip, &headers, "-")))
+        .collect::<Vec<_>>();
+    check_keys(&keys)
+}
+
+// tokens are only taken once every bucket has one, so that a request that is rejected by one limit
+// does not use up the tokens of the others.
+fn check_keys(keys:&[(&str,&RateLimit,String)]) -> Result<(),Duration> {
+
+    let mut retry_after : Option<Duration> = None;
+    for (scope,limit,key) in keys {
+        let mut bucket = refilled_bucket(scope, limit, key);
+        if let Some(wait) = time_until_available(&bucket, limit) {
+            bucket.limited += 1;
+            retry_after = Some(retry_after.map_or(wait, |x| x.max(wait)));
+        }
+    }
+    if let Some(retry_after) = retry_after {
+        return Err(retry_after)
+    }
+
+    for (scope,limit,key) in keys {
+        let mut bucket = refilled_bucket(scope, limit, key);
+        bucket.tokens -= 1.0;
+        bucket.allowed += 1;
+    }
+    Ok(())
+}
+
+pub fn counters() -> Vec<RateLimitCounter> {
+    let mut counters : Vec<RateLimitCounter> = BUCKETS.iter().map(|x| {
+        let ((scope,key),bucket) = x.pair();
+        RateLimitCounter {
+            scope: scope.clone(),
+            key: key.clone(),
+            available: bucket.tokens.floor() as u32,
+            allowed: bucket.allowed,
+            limited: bucket.limited
+        }
+    }).collect();
+    counters.sort_by(|a,b| (&a.scope,&a.key).cmp(&(&b.scope,&b.key)));
+    counters
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn rate_limits_allow_bursts_and_report_when_to_retry() {
+
+        let limit = RateLimit {
+            requests: 1,
+            period_seconds: Some(60),
+            burst: Some(2),
+            per_header: Some(String::from("x-api-key")),
+            per_path: Some(true),
+            count_tunnels: None
+        };
+
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert("x-api-key", "abc".parse().unwrap());
+        let key = request_key(&limit, "::ffff:10.0.0.1".parse().unwrap(), &headers, "/api");
+        assert_eq!(key, "10.0.0.1 abc /api");
+
+        let limits = vec![(String::from("rate-limit-test"), limit)];
+        let client_ip : IpAddr = "10.0.0.1".parse().unwrap();
+        assert!(check(&limits, client_ip, &headers, "/api").is_ok());
+        assert!(check(&limits, client_ip, &headers, "/api").is_ok());
+        let retry_after = check(&limits, client_ip, &headers, "/api").unwrap_err();
+        assert!(retry_after.as_secs() > 50 && retry_after.as_secs() <= 60);
+        assert!(check(&limits, client_ip, &headers, "/other").is_ok());
+        assert!(check(&limits, "10.0.0.3".parse().unwrap(), &headers, "/api").is_ok());
+
+    }
+
+    #[test]
+    fn requests_rejected_by_one_limit_do_not_use_up_the_others() {
+
+        let limit = |requests:u32| RateLimit { requests, period_seconds: Some(60), burst: None, per_header: None, per_path: None, count_tunnels: None };
+        let limits = vec![
+            (String::from("rate-limit-check-global"), limit(5)),
+            (String::from("rate-limit-check-site"), limit(1)),
+        ];
+        let client_ip : IpAddr = "10.0.0.2".parse().unwrap();
+        let headers = hyper::HeaderMap::new();
+
+        assert!(check(&limits, client_ip, &headers, "/").is_ok());
+        for _ in 0..10 {
+            let retry_after = check(&limits, client_ip, &headers, "/").unwrap_err();
+            assert!(retry_after.as_secs() > 50 && retry_after.as_secs() <= 60);
+        }
+
+        // only the first request was allowed, so the global limit still has all but one of its tokens
+        let global = counters().into_iter().find(|x| x.scope == "rate-limit-check-global").expect("should have a counter");
+        assert_eq!((global.available, global.allowed, global.limited), (4, 1, 0));
+        let site = counters().into_iter().find(|x| x.scope == "rate-limit-check-site").expect("should have a counter");
+        assert_eq!((site.available, site.allowed, site.limited), (0, 1, 10));
+
+    }
+
+    #[test]
+    fn tunnels_rejected_by_the_site_limit_do_not_use_up_the_global_limit() {
+
+        let limit = |requests:u32| RateLimit { requests, period_seconds: Some(60), burst: None, per_header: None, per_path: None, count_tunnels: Some(true) };
+        let limits = vec![
+            (String::from("rate-limit-tunnel-global"), limit(5)),
+            (String::from("rate-limit-tunnel-site"), limit(1)),
+            (String::from("rate-limit-tunnel-http-only"), RateLimit { count_tunnels: None, ..limit(1) }),
+        ];
+        let client_ip : IpAddr = "10.0.0.4".parse().unwrap();
+
+        assert!(check_tunnel(&limits, client_ip).is_ok());
+        for _ in 0..3 {
+            assert!(check_tunnel(&limits, client_ip).is_err());
+        }
+
+        let counter = |scope:&str| counters().into_iter().find(|x| x.scope == scope);
+        let global = counter("rate-limit-tunnel-global").expect("should have a counter");
+        assert_eq!((global.available, global.allowed, global.limited), (4, 1, 0));
+        let site = counter("rate-limit-tunnel-site").expect("should have a counter");
+        assert_eq!((site.available, site.allowed, site.limited), (0, 1, 3));
+        assert!(counter("rate-limit-tunnel-http-only").is_none());
+
+    }
+}
@@ -0,0 +1,123 @@
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::Instant;
+
+// keeps track of when data was last read from the wrapped stream, and how much was written to it
+struct ActivityTracked<'a, S> {
+    stream: &'a mut S,
+    started: Instant,
+    last_activity_ms: Arc<AtomicU64>,
+    written: Arc<AtomicU64>
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ActivityTracked<'_, S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled_before = buf.filled().len();
+        let result = Pin::new(&mut *self.stream).poll_read(cx, buf);
+        if buf.filled().len() > filled_before {
+            self.last_activity_ms.store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ActivityTracked<'_, S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut *self.stream).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            self.written.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        result
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut *self.stream).poll_flush(cx)
+    }
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut *self.stream).poll_shutdown(cx)
+    }
+}
+
+/// The number of bytes that were copied in each direction, and the error that ended the copy if it did not end cleanly.
+pub struct CopiedBytes {
+    pub a_to_b: u64,
+    pub b_to_a: u64,
+    pub result: std::io::Result<()>
+}
+
+/// Same as tokio::io::copy_bidirectional, but fails with a TimedOut error once no data
+/// has been sent in either direction for longer than the idle timeout.
+/// The bytes that were copied are returned even if the copy failed or timed out.
+pub async fn copy_bidirectional_with_idle_timeout<A, B>(a: &mut A, b: &mut B, idle_timeout: Option<Duration>) -> CopiedBytes
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin
+{
+    let started = Instant::now();
+    let last_activity_ms = Arc::new(AtomicU64::new(0));
+    let written_to_a = Arc::new(AtomicU64::new(0));
+    let written_to_b = Arc::new(AtomicU64::new(0));
+    let mut a = ActivityTracked { stream: a, started, last_activity_ms: last_activity_ms.clone(), written: written_to_a.clone() };
+    let mut b = ActivityTracked { stream: b, started, last_activity_ms: last_activity_ms.clone(), written: written_to_b.clone() };
+
+    let copy = tokio::io::copy_bidirectional(&mut a, &mut b);
+    tokio::pin!(copy);
+
+    let result = match idle_timeout {
+        None => copy.await.map(|_| ()),
+        Some(idle_timeout) => loop {
+            let idle_until = started + Duration::from_millis(last_activity_ms.load(Ordering::Relaxed)) + idle_timeout;
+            tokio::select! {
+                result = &mut copy => break result.map(|_| ()),
+                _ = tokio::time::sleep_until(idle_until) => {
+                    // data may have arrived while sleeping, in which case we just sleep until the new deadline
+                    if Instant::now() >= started + Duration::from_millis(last_activity_ms.load(Ordering::Relaxed)) + idle_timeout {
+                        break Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "the connection was idle for too long"))
+                    }
+                }
+            }
+        }
+    };
+
+    CopiedBytes {
+        a_to_b: written_to_b.load(Ordering::Relaxed),
+        b_to_a: written_to_a.load(Ordering::Relaxed),
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn idle_connections_are_closed_with_the_bytes_that_were_copied() {
+
+        let (mut client, mut a) = tokio::io::duplex(64);
+        let (mut b, mut server) = tokio::io::duplex(64);
+
+        let copy = tokio::spawn(async move {
+            copy_bidirectional_with_idle_timeout(&mut a, &mut b, Some(Duration::from_millis(100))).await
+        });
+
+        client.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8;5];
+        server.read_exact(&mut buf).await.unwrap();
+        server.write_all(b"hi").await.unwrap();
+        client.read_exact(&mut buf[..2]).await.unwrap();
+
+        let copied = copy.await.unwrap();
+        assert_eq!(copied.result.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        assert_eq!((copied.a_to_b, copied.b_to_a), (5, 2));
+
+    }
+}
@@ -29,6 +29,9 @@ impl ManagedStream {
     pub fn seal(&mut self) {
         self.sealed = true;
     }
+    pub fn local_addr(&self) -> std::io::Result<std::net::SocketAddr> {
+        self.stream.local_addr()
+    }
     /// peeks data from the tcpstream without consuming it.
     /// consequent calls to this function will further read data from the TcpStream
     /// in a nondestructive manner as the data is stored in an internal managed buffer.
@@ -3,5 +3,8 @@ mod http1;
 mod http2;
 mod tcp;
 mod managed_stream;
+mod idle_timeout;
+pub mod proxy_protocol;
 pub use managed_stream::ManagedStream;
+pub use idle_timeout::copy_bidirectional_with_idle_timeout;
 pub use tcp::*;
\ No newline at end of file
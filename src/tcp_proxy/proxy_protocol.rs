@@ -0,0 +1,169 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use crate::configuration::v2::ProxyProtocol;
+
+const V2_SIGNATURE : [u8;12] = [0x0D,0x0A,0x0D,0x0A,0x00,0x0D,0x0A,0x51,0x55,0x49,0x54,0x0A];
+
+// a v1 header is at most 107 bytes long, including the trailing crlf
+const V1_MAX_LENGTH : usize = 107;
+
+fn to_v6(ip:IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(x) => x.to_ipv6_mapped(),
+        IpAddr::V6(x) => x
+    }
+}
+
+/// Creates the PROXY protocol header that is sent to a backend before any other data.
+/// The destination is the address that the client connected to.
+pub fn create_header(version:&ProxyProtocol, client:SocketAddr, destination:SocketAddr) -> Vec<u8> {
+
+    // both addresses must be of the same family
+    let (source_ip,destination_ip) = match (client.ip().to_canonical(),destination.ip().to_canonical()) {
+        (IpAddr::V4(s),IpAddr::V4(d)) => (IpAddr::V4(s),IpAddr::V4(d)),
+        (s,d) => (IpAddr::V6(to_v6(s)),IpAddr::V6(to_v6(d)))
+    };
+
+    match version {
+        ProxyProtocol::V1 => {
+            let family = if source_ip.is_ipv4() { "TCP4" } else { "TCP6" };
+            format!("PROXY {family} {source_ip} {destination_ip} {} {}\r\n",client.port(),destination.port()).into_bytes()
+        },
+        ProxyProtocol::V2 => {
+            let mut header = V2_SIGNATURE.to_vec();
+            header.push(0x21); // version 2, PROXY command
+            match (source_ip,destination_ip) {
+                (IpAddr::V4(s),IpAddr::V4(d)) => {
+                    header.push(0x11); // tcp over ipv4
+                    header.extend_from_slice(&12u16.to_be_bytes());
+                    header.extend_from_slice(&s.octets());
+                    header.extend_from_slice(&d.octets());
+                },
+                (s,d) => {
+                    header.push(0x21); // tcp over ipv6
+                    header.extend_from_slice(&36u16.to_be_bytes());
+                    header.extend_from_slice(&to_v6(s).octets());
+                    header.extend_from_slice(&to_v6(d).octets());
+                }
+            }
+            header.extend_from_slice(&client.port().to_be_bytes());
+            header.extend_from_slice(&destination.port().to_be_bytes());
+            header
+        }
+    }
+}
+
+/// Parses a complete v1 or v2 PROXY protocol header.
+/// Returns the address of the client as reported by the proxy, or None for LOCAL and UNKNOWN headers
+/// in which case the address of the connection itself should be used.
+pub fn parse_header(header:&[u8]) -> Result<Option<SocketAddr>,String> {
+
+    if header.starts_with(&V2_SIGNATURE) {
+
+        if header.len() < 16 {
+            return Err("the PROXY protocol v2 header is incomplete".into())
+        }
+        let (version_and_command,family) = (header[12],header[13]);
+        let body = &header[16..];
+
+        if version_and_command >> 4 != 2 {
+            return Err(format!("unsupported PROXY protocol version {}",version_and_command >> 4))
+        }
+        match version_and_command & 0x0F {
+            0x0 => return Ok(None), // LOCAL, used by proxies for their own connections such as health checks
+            0x1 => {},
+            x => return Err(format!("unknown PROXY protocol v2 command {x}"))
+        }
+
+        return match family >> 4 {
+            0x1 => {
+                if body.len() < 12 { return Err("the PROXY protocol v2 address block is too short".into()) }
+                let ip = Ipv4Addr::new(body[0],body[1],body[2],body[3]);
+                Ok(Some(SocketAddr::new(IpAddr::V4(ip),u16::from_be_bytes([body[8],body[9]]))))
+            },
+            0x2 => {
+                if body.len() < 36 { return Err("the PROXY protocol v2 address block is too short".into()) }
+                let mut octets = [0u8;16];
+                octets.copy_from_slice(&body[..16]);
+                Ok(Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)),u16::from_be_bytes([body[32],body[33]]))))
+            },
+            // unix sockets and unspecified addresses do not tell us anything useful about the client
+            _ => Ok(None)
+        }
+    }
+
+    if !header.starts_with(b"PROXY ") {
+        return Err("the connection did not start with a PROXY protocol header".into())
+    }
+
+    let line = std::str::from_utf8(header).map_err(|_|"the PROXY protocol v1 header is not valid utf8".to_string())?;
+    let line = line.strip_suffix("\r\n").ok_or("the PROXY protocol v1 header does not end with crlf".to_string())?;
+    match line.split(' ').collect::<Vec<&str>>().as_slice() {
+        ["PROXY","UNKNOWN",..] => Ok(None),
+        ["PROXY","TCP4"|"TCP6",source_ip,_destination_ip,source_port,_destination_port] => {
+            let ip = source_ip.parse::<IpAddr>().map_err(|e|format!("invalid source address in PROXY protocol v1 header: {e}"))?;
+            let port = source_port.parse::<u16>().map_err(|e|format!("invalid source port in PROXY protocol v1 header: {e}"))?;
+            Ok(Some(SocketAddr::new(ip,port)))
+        },
+        _ => Err(format!("invalid PROXY protocol v1 header: {line}"))
+    }
+}
+
+/// Reads a PROXY protocol header from the start of a connection, leaving the rest of the stream untouched.
+pub async fn read_header(stream:&mut TcpStream) -> Result<Option<SocketAddr>,String> {
+    let header = tokio::time::timeout(Duration::from_secs(5), read_header_bytes(stream)).await
+        .map_err(|_|"timed out waiting for a PROXY protocol header".to_string())?
+        .map_err(|e|format!("failed to read the PROXY protocol header: {e}"))?;
+    parse_header(&header)
+}
+
+async fn read_header_bytes(stream:&mut TcpStream) -> Result<Vec<u8>,std::io::Error> {
+
+    // both versions are longer than the v2 signature so we can always read that much
+    let mut header = vec![0u8;V2_SIGNATURE.len()];
+    stream.read_exact(&mut header).await?;
+
+    if header == V2_SIGNATURE {
+        let mut fixed = [0u8;4];
+        stream.read_exact(&mut fixed).await?;
+        header.extend_from_slice(&fixed);
+        let mut body = vec![0u8;u16::from_be_bytes([fixed[2],fixed[3]]) as usize];
+        stream.read_exact(&mut body).await?;
+        header.extend_from_slice(&body);
+    } else if header.starts_with(b"PROXY ") {
+        // read one byte at a time so that we do not consume any data after the header
+        while !header.ends_with(b"\r\n") && header.len() < V1_MAX_LENGTH {
+            header.push(stream.read_u8().await?);
+        }
+    }
+
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn proxy_protocol_headers_can_be_parsed_after_being_created() {
+
+        let client : std::net::SocketAddr = "192.168.1.10:51234".parse().unwrap();
+        let destination : std::net::SocketAddr = "10.0.0.1:443".parse().unwrap();
+        let client_v6 : std::net::SocketAddr = "[2001:db8::1]:51234".parse().unwrap();
+
+        let v1 = create_header(&ProxyProtocol::V1, client, destination);
+        assert_eq!(v1, b"PROXY TCP4 192.168.1.10 10.0.0.1 51234 443\r\n".to_vec());
+
+        for version in [ProxyProtocol::V1, ProxyProtocol::V2] {
+            assert_eq!(parse_header(&create_header(&version, client, destination)), Ok(Some(client)));
+            assert_eq!(parse_header(&create_header(&version, client_v6, destination)), Ok(Some(client_v6)));
+        }
+
+        assert_eq!(parse_header(b"PROXY UNKNOWN\r\n"), Ok(None));
+        assert!(parse_header(b"GET / HTTP/1.1\r\n").is_err());
+
+    }
+}
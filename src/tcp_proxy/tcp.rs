@@ -7,9 +7,11 @@ use std::{
     sync::Arc,
 };
 use crate::configuration::v2::BackendFilter;
+use crate::configuration::v2::LoadBalancingContext;
 use crate::global_state::GlobalState;
 use crate::tcp_proxy::tls::client_hello::TlsClientHello;
 use crate::types::proxy_state::{ProxyActiveConnection, ProxyActiveConnectionType};
+use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
 use tracing::*;
 
@@ -22,6 +24,7 @@ use super::managed_stream::{self, ManagedStream};
 pub struct ReverseTcpProxyTarget {
     pub remote_target_config: Option<crate::configuration::v2::RemoteSiteConfig>,
     pub hosted_target_config: Option<crate::configuration::v2::InProcessSiteConfig>,
+    pub static_site_config: Option<crate::configuration::v2::StaticSiteConfig>,
     pub backends: Vec<crate::configuration::v2::Backend>,
     pub host_name: String,
     pub is_hosted : bool,
@@ -54,6 +57,15 @@ pub enum PeekError {
 
 impl ReverseTcpProxyTarget {
 
+    pub fn force_https(&self) -> bool {
+        match (&self.hosted_target_config,&self.remote_target_config,&self.static_site_config) {
+            (Some(x),_,_) => x.force_https.unwrap_or_default(),
+            (None,Some(x),_) => x.force_https.unwrap_or_default(),
+            (None,None,Some(x)) => x.force_https.unwrap_or_default(),
+            _ => false
+        }
+    }
+
     #[allow(dead_code)]
     fn is_valid_ip_or_dns(target: &str) -> bool {
         webpki::DnsNameRef::try_from_ascii_str(target)
@@ -180,6 +192,12 @@ impl ReverseTcpProxy {
         client_address: SocketAddr
     ) {
 
+        let rate_limits = state.config.read().await.find_rate_limits(&target.host_name);
+        if crate::rate_limit::check_tunnel(&rate_limits, client_address.ip()).is_err() {
+            tracing::debug!("Rate limited tcp tunnel from {client_address} to {}",target.host_name);
+            return;
+        }
+
         // THIS SHOULD BE THE ONLY PLACE WE INCREMENT THE TUNNEL COUNTER
         match state.app_state.statistics.tunnelled_tcp_connections_per_hostname.get_mut(&target.host_name) {
             Some(mut guard) => {
@@ -196,7 +214,8 @@ impl ReverseTcpProxy {
         let primary_backend =  {
 
             let b = if let Some(remconf) = &target.remote_target_config {
-                remconf.next_backend(&state, if incoming_traffic_is_tls { BackendFilter::Https } else { BackendFilter::Http },true).await
+                let lb_context = LoadBalancingContext { client_ip: Some(client_address.ip()), cookies: vec![], excluded_backends: vec![] };
+                remconf.next_backend(&state, if incoming_traffic_is_tls { BackendFilter::Https } else { BackendFilter::Http },&lb_context).await
             } else {
                 target.backends.first().cloned()
             };
@@ -226,15 +245,37 @@ impl ReverseTcpProxy {
 
         tracing::trace!("tcp tunneling to target: {resolved_target_address} (tls: {incoming_traffic_is_tls})");
 
-        match TcpStream::connect(resolved_target_address.clone()).await {
+        let timeouts = state.config.read().await.upstream_timeouts(Some(&primary_backend));
+        let connected = match tokio::time::timeout(timeouts.connect(), TcpStream::connect(resolved_target_address.clone())).await {
+            Ok(result) => result,
+            Err(_) => Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out while connecting"))
+        };
+
+        let outcome = match &connected {
+            Ok(_) => crate::outlier_detection::Outcome::Success,
+            Err(e) => crate::outlier_detection::Outcome::Failed(format!("tcp connection failed: {e}"))
+        };
+        crate::outlier_detection::record(&state, &target.host_name, &primary_backend, outcome);
+
+        match connected {
             Ok(mut rem_stream) => {
 
                 
                 if let Ok(target_addr_socket) = rem_stream.peer_addr() {
+
+                    if let Some(version) = &primary_backend.proxy_protocol {
+                        let destination = client_tcp_stream.local_addr().unwrap_or(target_addr_socket);
+                        let header = super::proxy_protocol::create_header(version, client_address, destination);
+                        if let Err(e) = rem_stream.write_all(&header).await {
+                            warn!("failed to send the PROXY protocol header to {resolved_target_address}: {e:?}");
+                            return
+                        }
+                    }
                     
                     let item = ProxyActiveConnection {
                         target_name: target.host_name.clone(),
                         target_addr: format!("{resolved_target_address} ({})",target_addr_socket.ip()),
+                        backend_key: Some(primary_backend.key()),
                         source_addr: client_address,
                         creation_time: Local::now(),
                         description: None,
@@ -250,15 +291,42 @@ impl ReverseTcpProxy {
                     // ADD TO STATE BEFORE STARTING THE STREAM
                     state.app_state.statistics.active_connections.insert(item_key, item);
 
-                    match tokio::io::copy_bidirectional(&mut client_tcp_stream, &mut rem_stream).await {
-                        Ok(_a) => {
+                    let tunnel_log_entry = |event:crate::access_log::AccessLogEvent,bytes:Option<(u64,u64)>,duration_ms:Option<u64>| crate::access_log::AccessLogEntry {
+                        timestamp: Local::now(),
+                        event,
+                        client_ip: client_address.ip(),
+                        host: target.host_name.clone(),
+                        method: None,
+                        path: None,
+                        protocol: String::from(if incoming_traffic_is_tls { "TLS" } else { "TCP" }),
+                        upstream_protocol: None,
+                        upstream: Some(resolved_target_address.clone()),
+                        status: None,
+                        bytes_received: bytes.map(|(client_to_remote,_)|client_to_remote),
+                        bytes_sent: bytes.map(|(_,remote_to_client)|remote_to_client),
+                        duration_ms,
+                        referer: None,
+                        user_agent: None,
+                    };
+                    crate::access_log::record(tunnel_log_entry(crate::access_log::AccessLogEvent::TunnelOpened,None,None));
+                    let opened_at = std::time::Instant::now();
+
+                    let copied = super::copy_bidirectional_with_idle_timeout(&mut client_tcp_stream, &mut rem_stream, timeouts.idle()).await;
+                    match copied.result {
+                        Ok(()) => {
                             // could add this to target stats at some point
-                            //debug!("stream completed ok! -- {} <--> {}", a.0, a.1)
+                            //debug!("stream completed ok! -- {} <--> {}", copied.a_to_b, copied.b_to_a)
+                        }
+                        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                            tracing::debug!("Closed idle tcp tunnel from {client_address} to {resolved_target_address}");
                         }
                         Err(e) => {
-                            trace!("Stream failed with err: {e:?}")
+                            trace!("Stream failed with err: {e:?}");
                         }
-                    }
+                    };
+
+                    let bytes = Some((copied.a_to_b,copied.b_to_a));
+                    crate::access_log::record(tunnel_log_entry(crate::access_log::AccessLogEvent::TunnelClosed,bytes,Some(opened_at.elapsed().as_millis() as u64)));
                    
                     // DROP FROM ACTIVE STATE ONCE DONE
                     state.app_state.statistics.active_connections.remove(&item_key);
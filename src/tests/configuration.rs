@@ -150,3 +150,185 @@ use crate::configuration::OddBoxConfiguration;
 
 }
 
+
+
+#[test] pub fn path_rules_prefer_longest_prefix_and_strip_when_asked() {
+
+    let rules = vec![
+        crate::configuration::v2::PathRule { path: "/api/*".into(), target: "api".into(), strip_prefix: Some(true) },
+        crate::configuration::v2::PathRule { path: "/api/v2".into(), target: "api-v2".into(), strip_prefix: None },
+    ];
+
+    let rule = crate::configuration::v2::find_path_rule(&rules, "/api/users").expect("should match /api");
+    assert_eq!(rule.target,"api");
+    assert_eq!(rule.rewrite_path_and_query("/api/users?id=1"),"/users?id=1");
+    assert_eq!(rule.rewrite_path_and_query("/api"),"/");
+
+    let rule = crate::configuration::v2::find_path_rule(&rules, "/api/v2/users").expect("should match /api/v2");
+    assert_eq!(rule.target,"api-v2");
+    assert_eq!(rule.rewrite_path_and_query("/api/v2/users"),"/api/v2/users");
+
+    assert!(crate::configuration::v2::find_path_rule(&rules, "/apix").is_none());
+    assert!(crate::configuration::v2::find_path_rule(&rules, "/").is_none());
+
+}
+
+
+#[test] pub fn hash_load_balancing_only_moves_clients_of_removed_backends() {
+
+    let backends = (1..=4).map(|i| crate::configuration::v2::Backend {
+        address: format!("backend{i}.local"),
+        port: 80,
+        https: None,
+        hints: None,
+        health_check: None,
+        weight: None,
+        proxy_protocol: None,
+        timeouts: None,
+        outlier_detection: None
+    }).collect::<Vec<_>>();
+
+    let all = backends.iter().collect::<Vec<_>>();
+    let without_last = backends.iter().take(3).collect::<Vec<_>>();
+
+    for i in 0..100 {
+        let client = format!("10.0.0.{i}");
+        let first = crate::configuration::v2::pick_backend_by_hash(&client, &all).expect("should pick a backend");
+        let second = crate::configuration::v2::pick_backend_by_hash(&client, &all).expect("should pick a backend");
+        assert_eq!(first,second);
+        if first != &backends[3] {
+            let after_removal = crate::configuration::v2::pick_backend_by_hash(&client, &without_last).expect("should pick a backend");
+            assert_eq!(first,after_removal);
+        }
+    }
+
+}
+
+#[test] pub fn restart_backoff_doubles_until_the_max_delay() {
+
+    let backoff = crate::configuration::v2::RestartBackoff {
+        initial_delay_seconds: Some(2),
+        max_delay_seconds: Some(10),
+        crash_loop_threshold: None,
+        crash_loop_window_seconds: None
+    };
+
+    let delays = (1..=5).map(|x|backoff.delay_for_attempt(x).as_secs()).collect::<Vec<_>>();
+    assert_eq!(delays, vec![2,4,8,10,10]);
+
+    // the default keeps the old behavior of waiting 5 seconds before the first restart
+    let default_backoff = crate::configuration::v2::RestartBackoff::default();
+    assert_eq!(default_backoff.delay_for_attempt(1).as_secs(), 5);
+    assert_eq!(default_backoff.delay_for_attempt(100).as_secs(), 300);
+
+}
+
+#[test] pub fn site_changes_only_include_sites_that_differ() {
+
+    let old_config = crate::configuration::v2::OddBoxV2Config::example();
+    let mut new_config = old_config.clone();
+
+    assert!(old_config.site_changes(&new_config).hosted_processes.is_empty());
+    assert!(old_config.site_changes(&new_config).remote_targets.is_empty());
+
+    let hosted = new_config.hosted_process.as_mut().expect("example has hosted processes");
+    hosted[0].port = Some(9999);
+    let mut added = hosted[0].clone();
+    added.host_name = "new_site.local".into();
+    hosted.push(added);
+
+    new_config.remote_target.as_mut().expect("example has remote targets").remove(0);
+
+    let changes = old_config.site_changes(&new_config);
+    assert_eq!(changes.hosted_processes.changed, vec!["some_host.local".to_string()]);
+    assert_eq!(changes.hosted_processes.added, vec!["new_site.local".to_string()]);
+    assert!(changes.hosted_processes.removed.is_empty());
+    assert_eq!(changes.remote_targets.removed, vec!["lobsters.local".to_string()]);
+    assert!(changes.remote_targets.added.is_empty() && changes.remote_targets.changed.is_empty());
+
+}
+
+#[test] pub fn dependency_cycles_are_rejected() {
+
+    let mut config = crate::configuration::v2::OddBoxV2Config::example();
+    let hosted = config.hosted_process.as_mut().expect("example has hosted processes");
+    let mut api = hosted[0].clone();
+    api.host_name = "api.local".into();
+    api.port = None;
+    hosted[0].depends_on = Some(vec!["api.local".into()]);
+    hosted.push(api);
+
+    let wrapper = crate::configuration::ConfigWrapper::new(config.clone());
+    wrapper.is_valid().expect("a site may depend on another site");
+
+    config.hosted_process.as_mut().expect("example has hosted processes")[1].depends_on = Some(vec!["some_host.local".into()]);
+    let wrapper = crate::configuration::ConfigWrapper::new(config);
+    let error = wrapper.is_valid().expect_err("sites should not be able to depend on each other");
+    assert!(error.to_string().contains("some_host.local -> api.local -> some_host.local"));
+
+}
+
+#[test] pub fn only_a_leading_tilde_is_resolved_to_the_home_directory() {
+
+    let mut config = crate::configuration::v2::OddBoxV2Config::example();
+    config.path = Some("/srv/odd-box/odd-box.toml".into());
+    config.error_pages = Some(crate::configuration::v2::ErrorPagesConfig {
+        html: Some("/srv/site~old/404.html".into()),
+        json: Some("~/errors/error.json".into())
+    });
+
+    let wrapper = crate::configuration::ConfigWrapper::new(config);
+    let pages = wrapper.find_error_pages("unknown.local").expect("should use the global error pages");
+    assert_eq!(pages.html.as_deref(), Some("/srv/site~old/404.html"));
+    let home_dir = dirs::home_dir().expect("should have a home directory");
+    assert_eq!(pages.json, Some(home_dir.join("errors/error.json").to_string_lossy().to_string()));
+
+}
+
+#[test] pub fn static_sites_have_rewrite_rules_and_error_pages() {
+
+    let toml = r#"
+        version = "V2"
+        port_range_start = 4200
+        env_vars = []
+
+        [[static_site]]
+        host_name = "docs.local"
+        dir = "/srv/docs"
+        rewrite_rules = [ { pattern = "^/v1/(.*)$", replacement = "/$1" } ]
+        error_pages = { html = "/srv/errors/docs.html" }
+    "#;
+    let config = match crate::configuration::OddBoxConfig::parse(toml).expect("should parse the configuration") {
+        crate::configuration::OddBoxConfig::V2(x) => *x,
+        _ => panic!("expected a v2 configuration")
+    };
+
+    let reparsed = match crate::configuration::OddBoxConfig::parse(&config.to_string().expect("should serialize")).expect("should parse the configuration") {
+        crate::configuration::OddBoxConfig::V2(x) => x,
+        _ => panic!("expected a v2 configuration")
+    };
+    assert_eq!(reparsed.static_site, config.static_site);
+
+    let wrapper = crate::configuration::ConfigWrapper::new(config);
+    wrapper.is_valid().expect("the configuration should be valid");
+    assert_eq!(
+        wrapper.find_rewrite("docs.local", "/v1/guide", None),
+        Some(crate::configuration::v2::RewriteOutcome::Rewrite { path_and_query: String::from("/guide") })
+    );
+    let pages = wrapper.find_error_pages("docs.local").expect("should use the error pages of the site");
+    assert_eq!(pages.html.as_deref(), Some("/srv/errors/docs.html"));
+
+}
+
+#[test]
+pub fn subdomains_only_match_sites_that_capture_them() {
+
+    use crate::configuration::host_matches;
+
+    assert!(host_matches("site.local", "site.local", None));
+    assert!(!host_matches("api.site.local", "site.local", None));
+    assert!(!host_matches("api.site.local", "site.local", Some(false)));
+    assert!(host_matches("api.site.local", "site.local", Some(true)));
+    assert!(!host_matches("othersite.local", "site.local", Some(true)));
+
+}
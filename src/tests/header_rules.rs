@@ -0,0 +1,27 @@
+#[test] pub fn header_rules_replace_placeholders_and_remove_headers() {
+
+    use crate::configuration::v2::{HeaderAction, HeaderRule, HeaderRuleContext, apply_header_rules};
+
+    let rule = |name:&str,action:HeaderAction,value:Option<&str>| HeaderRule { name: name.into(), action, value: value.map(|x|x.into()) };
+    let rules = vec![
+        rule("x-client", HeaderAction::Set, Some("$client_ip via $scheme://$host ($site)")),
+        rule("x-tag", HeaderAction::Append, Some("second")),
+        rule("odd-box", HeaderAction::Remove, None),
+    ];
+    let context = HeaderRuleContext {
+        client_ip: "10.0.0.5".parse().unwrap(),
+        host: "www.example.com".into(),
+        site: "example.com".into(),
+        scheme: "https"
+    };
+
+    let mut headers = hyper::HeaderMap::new();
+    headers.insert("x-tag", "first".parse().unwrap());
+    headers.insert("odd-box", "YEAH BABY YEAH".parse().unwrap());
+    apply_header_rules(&rules, &mut headers, &context);
+
+    assert_eq!(headers.get("x-client").unwrap(), "10.0.0.5 via https://www.example.com (example.com)");
+    assert_eq!(headers.get_all("x-tag").iter().count(), 2);
+    assert!(headers.get("odd-box").is_none());
+
+}
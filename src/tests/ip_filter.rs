@@ -0,0 +1,23 @@
+#[test] pub fn allow_and_deny_lists_match_cidr_ranges() {
+
+    use crate::configuration::v2::{cidr_contains, ip_is_allowed};
+
+    let ip = |x:&str| x.parse::<std::net::IpAddr>().unwrap();
+
+    assert_eq!(cidr_contains("192.168.1.0/24", ip("192.168.1.77")), Some(true));
+    assert_eq!(cidr_contains("192.168.1.0/24", ip("192.168.2.1")), Some(false));
+    assert_eq!(cidr_contains("192.168.1.0/24", ip("::ffff:192.168.1.77")), Some(true));
+    assert_eq!(cidr_contains("0.0.0.0/0", ip("8.8.8.8")), Some(true));
+    assert_eq!(cidr_contains("fd00::/8", ip("fd12::1")), Some(true));
+    assert_eq!(cidr_contains("10.0.0.1", ip("10.0.0.2")), Some(false));
+    assert_eq!(cidr_contains("10.0.0.0/33", ip("10.0.0.1")), None);
+    assert_eq!(cidr_contains("localhost", ip("127.0.0.1")), None);
+
+    let allow = vec![String::from("10.0.0.0/8")];
+    let deny = vec![String::from("10.0.0.13")];
+    assert!(ip_is_allowed(None, None, ip("1.2.3.4")));
+    assert!(ip_is_allowed(Some(&allow), Some(&deny), ip("10.1.2.3")));
+    assert!(!ip_is_allowed(Some(&allow), Some(&deny), ip("10.0.0.13")));
+    assert!(!ip_is_allowed(Some(&allow), None, ip("1.2.3.4")));
+
+}
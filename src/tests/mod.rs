@@ -1,2 +1,7 @@
 mod configuration;
-mod main;
\ No newline at end of file
+mod main;
+mod header_rules;
+mod rewrite_rules;
+mod ip_filter;
+mod upstream_timeouts;
+mod retries;
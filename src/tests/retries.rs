@@ -0,0 +1,27 @@
+#[test] pub fn retries_back_off_and_only_repeat_idempotent_requests() {
+
+    use std::time::Duration;
+    use hyper::{Method, StatusCode};
+
+    let policy = crate::configuration::v2::RetryPolicy {
+        attempts: Some(4),
+        backoff_ms: Some(100),
+        on_unavailable: Some(true)
+    };
+
+    assert_eq!(policy.attempts(), 4);
+    let delays = (1..=3).map(|x| policy.backoff_for_retry(x)).collect::<Vec<_>>();
+    assert_eq!(delays, vec![Duration::from_millis(100), Duration::from_millis(200), Duration::from_millis(400)]);
+
+    assert!(policy.should_retry_status(&Method::GET, StatusCode::BAD_GATEWAY));
+    assert!(policy.should_retry_status(&Method::DELETE, StatusCode::SERVICE_UNAVAILABLE));
+    assert!(!policy.should_retry_status(&Method::POST, StatusCode::BAD_GATEWAY));
+    assert!(!policy.should_retry_status(&Method::GET, StatusCode::GATEWAY_TIMEOUT));
+    assert!(!policy.should_retry_status(&Method::GET, StatusCode::INTERNAL_SERVER_ERROR));
+
+    let default_policy = crate::configuration::v2::RetryPolicy { attempts: None, backoff_ms: None, on_unavailable: None };
+    assert_eq!(default_policy.attempts(), 2);
+    assert_eq!(default_policy.backoff_for_retry(1), Duration::ZERO);
+    assert!(!default_policy.should_retry_status(&Method::GET, StatusCode::BAD_GATEWAY));
+
+}
@@ -0,0 +1,25 @@
+#[test] pub fn rewrite_rules_expand_captures_and_keep_the_query() {
+
+    use crate::configuration::v2::{RedirectSiteConfig, RewriteOutcome, RewriteRule};
+
+    let rewrite = RewriteRule { pattern: "^/blog/(?<year>\\d{4})/(.*)$".into(), replacement: "/posts/$2?year=${year}".into(), redirect: None };
+    let redirect = RewriteRule { pattern: "^/old/(.*)$".into(), replacement: "https://new.localtest.me/$1".into(), redirect: Some(308) };
+
+    assert_eq!(rewrite.evaluate("/blog/2024/hello", Some("page=2")), Some(RewriteOutcome::Rewrite { path_and_query: "/posts/hello?year=2024".into() }));
+    assert_eq!(redirect.evaluate("/old/a/b", Some("page=2")), Some(RewriteOutcome::Redirect { status_code: 308, location: "https://new.localtest.me/a/b?page=2".into() }));
+    assert_eq!(redirect.evaluate("/new/a", None), None);
+    assert!(RewriteRule { pattern: "(".into(), replacement: "/".into(), redirect: None }.validate().is_err());
+    assert!(RewriteRule { pattern: "^/$".into(), replacement: "/".into(), redirect: Some(200) }.validate().is_err());
+
+    let mut site = RedirectSiteConfig {
+        host_name: "old.localtest.me".into(),
+        capture_subdomains: None,
+        redirect_to: "https://new.localtest.me/".into(),
+        status_code: None,
+        keep_path: None
+    };
+    assert_eq!(site.redirect("/a?b=c"), RewriteOutcome::Redirect { status_code: 301, location: "https://new.localtest.me/a?b=c".into() });
+    site.keep_path = Some(false);
+    assert_eq!(site.redirect("/a?b=c"), RewriteOutcome::Redirect { status_code: 301, location: "https://new.localtest.me/".into() });
+
+}
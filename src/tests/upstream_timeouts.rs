@@ -0,0 +1,34 @@
+#[allow(unused)]
+use crate::configuration::OddBoxConfiguration;
+
+#[test] pub fn backend_timeouts_fall_back_to_the_global_timeouts() {
+
+    use std::time::Duration;
+    use crate::configuration::v2::UpstreamTimeouts;
+
+    let mut config = crate::configuration::v2::OddBoxV2Config::example();
+    config.timeouts = Some(UpstreamTimeouts { connect_seconds: Some(20), first_byte_seconds: None, total_seconds: Some(120), idle_seconds: None });
+    let backend = &mut config.remote_target.as_mut().expect("example has remote targets")[0].backends[0];
+    backend.timeouts = Some(UpstreamTimeouts { connect_seconds: Some(3), first_byte_seconds: None, total_seconds: None, idle_seconds: Some(0) });
+    let backend = backend.clone();
+
+    let wrapper = crate::configuration::ConfigWrapper::new(config);
+    let timeouts = wrapper.upstream_timeouts(Some(&backend));
+    assert_eq!(timeouts.connect(), Duration::from_secs(3));
+    assert_eq!(timeouts.first_byte(), None);
+    assert_eq!(timeouts.total(), Some(Duration::from_secs(120)));
+    assert_eq!(timeouts.idle(), Some(Duration::from_secs(1)));
+    assert_eq!(wrapper.upstream_timeouts(None).idle(), None);
+    let configured = UpstreamTimeouts { connect_seconds: None, first_byte_seconds: Some(30), total_seconds: None, idle_seconds: None };
+    assert_eq!(configured.or(&timeouts).first_byte(), Some(Duration::from_secs(30)));
+    assert_eq!(configured.or(&timeouts).connect(), Duration::from_secs(3));
+
+    assert_eq!(wrapper.find_connect_timeout("lobste.rs", 443), Duration::from_secs(3));
+    assert_eq!(wrapper.find_connect_timeout("www.lobste.rs", 443), Duration::from_secs(3));
+    assert_eq!(wrapper.find_connect_timeout("lobste.rs", 80), Duration::from_secs(20));
+    assert_eq!(wrapper.find_connect_timeout("localhost", 8080), Duration::from_secs(20));
+
+    let error = crate::http_proxy::ProxyError::Timeout(String::from("no response"));
+    assert_eq!(error.status_code(), hyper::StatusCode::GATEWAY_TIMEOUT);
+
+}
@@ -156,6 +156,7 @@ pub async fn run(
                     if global_state.app_state.site_status_map.iter().find(|x|
                             x.value() == &ProcState::Stopping 
                         || x.value() == &ProcState::Running
+                        || x.value() == &ProcState::Ready
                         || x.value() == &ProcState::Starting 
                         
                     ).is_none() {
@@ -474,7 +475,9 @@ pub async fn run(
                                                 let (_k,state) = guard.pair_mut();
                                                 match state {
                                                     ProcState::Faulty =>  *state = ProcState::Stopping,
+                                                    ProcState::CrashLooping =>  *state = ProcState::Stopping,
                                                     ProcState::Running =>  *state = ProcState::Stopping,
+                                                    ProcState::Ready =>  *state = ProcState::Stopping,
                                                     _ => {}
                                                 }
                                             }
@@ -734,14 +737,14 @@ fn draw_ui<B: ratatui::backend::Backend>(
                 site_rects.push((item_rect,id.to_string()));
 
                 let mut s = match state {
-                    &ProcState::Running => Style::default().fg(
+                    &ProcState::Running | &ProcState::Ready => Style::default().fg(
                         if is_dark_theme {
                             Color::LightGreen
                         } else {
                             Color::Green
                         }
                     ),
-                    &ProcState::Faulty => Style::default().fg(
+                    &ProcState::Faulty | &ProcState::CrashLooping => Style::default().fg(
                         if is_dark_theme {
                                 Color::LightMagenta
                             } else {
@@ -792,7 +795,14 @@ fn draw_ui<B: ratatui::backend::Backend>(
 
                 let status = match state {
                     &ProcState::Running => ratatui::text::Span::styled(format!("{:?}",state),s),
-                    &ProcState::Faulty => ratatui::text::Span::styled(format!("{state:?} (retrying in 5s)"),s),
+                    &ProcState::Ready => ratatui::text::Span::styled(format!("{:?}",state),s),
+                    &ProcState::Faulty | &ProcState::CrashLooping => {
+                        let exit_code = crate::PROC_THREAD_MAP.iter().find(|x| x.config.host_name == *id).and_then(|x|x.last_exit_code);
+                        match exit_code {
+                            Some(code) => ratatui::text::Span::styled(format!("{state:?} (exit code {code})"),s),
+                            None => ratatui::text::Span::styled(format!("{:?}",state),s)
+                        }
+                    },
                     &ProcState::Starting => ratatui::text::Span::styled(format!("{:?}",state),s),
                     &ProcState::Stopped => ratatui::text::Span::styled(format!("{:?}",state),s),
                     &ProcState::Stopping => ratatui::text::Span::styled(format!("{:?}..",state),s),
@@ -89,11 +89,50 @@ pub fn draw(
 
     
 
-    let p3 = Paragraph::new(format!("Number of unique hostnames seen: {}", num_unique_hostnames)).style(style);
+    let p3 = Paragraph::new(format!(
+        "Number of unique hostnames seen: {} - Denied connections: {} - Retried requests: {}",
+        num_unique_hostnames,
+        global_state.app_state.statistics.denied_connections.load(std::sync::atomic::Ordering::SeqCst),
+        global_state.app_state.statistics.retried_requests.load(std::sync::atomic::Ordering::SeqCst)
+    )).style(style);
+
+    let mut unhealthy_backends = global_state
+        .app_state
+        .backend_health
+        .iter()
+        .filter(|x| !x.value().healthy)
+        .map(|x| format!("{} ({})", x.key(), x.value().last_error.clone().unwrap_or_default()))
+        .collect::<Vec<String>>();
+    unhealthy_backends.sort();
+
+    let ejected_backends = global_state
+        .app_state
+        .circuit_breakers
+        .iter()
+        .filter(|x| x.value().ejected_at.is_some())
+        .count();
+
+    let p4 = Paragraph::new(format!(
+        "Health checked backends: {} - Unhealthy: {} - Ejected by outlier detection: {}",
+        global_state.app_state.backend_health.len(),
+        unhealthy_backends.len(),
+        ejected_backends
+    )).style(style);
 
     f.render_widget(p1, area.offset(Offset { x: 4, y: 1 }));
     f.render_widget(p2, area.offset(Offset { x: 4, y: 2 }));
     f.render_widget(p3, area.offset(Offset { x: 4, y: 3 }));
+    f.render_widget(p4, area.offset(Offset { x: 4, y: 4 }));
+
+    let unhealthy_style = Style::default().fg(Color::Red);
+    let max_listed = (size.height as usize).saturating_sub(10);
+    for (i,b) in unhealthy_backends.iter().take(max_listed).enumerate() {
+        f.render_widget(
+            Paragraph::new(format!("- {b}")).style(unhealthy_style),
+            area.offset(Offset { x: 6, y: 5 + i as i32 })
+        );
+    }
+    let listed_count = unhealthy_backends.len().min(max_listed) as i32;
 
 
     // TODO - Use a scrollable table and display all host specific stats
@@ -104,7 +143,7 @@ pub fn draw(
 
     f.render_widget(
         Paragraph::new(Text::styled("... This page will have more data in the future :-)", Style::default().fg(Color::DarkGray))),
-        area.offset(Offset { x: 4, y: 5 })
+        area.offset(Offset { x: 4, y: 6 + listed_count })
             
     );
 
@@ -27,7 +27,11 @@ pub fn draw(
         vec![
             format!("[PROC_HOST] {}",thread_info.config.host_name),
             format!("{}",thread_info.pid.as_ref().map_or(String::new(),|x|x.to_string())),
-            format!("selected port: {:?}", thread_info.config.active_port)
+            match (thread_info.restart_count,&thread_info.last_failure) {
+                (0,_) => format!("selected port: {:?}", thread_info.config.active_port),
+                (n,Some(reason)) => format!("selected port: {:?} - restarts: {n} - last failure: {reason}", thread_info.config.active_port),
+                (n,None) => format!("selected port: {:?} - restarts: {n}", thread_info.config.active_port)
+            }
         ]
     }).chain(crate::BG_WORKER_THREAD_MAP.iter().map(|guard|{
         let (thread_id, thread_info) = guard.pair();
@@ -2,13 +2,19 @@ use std::sync::atomic::AtomicBool;
 use utoipa::ToSchema;
 use std::sync::Arc;
 use crate::types::proxy_state::*;
+use crate::types::backend_health::BackendHealth;
+use crate::types::circuit_breaker::CircuitBreaker;
 use ratatui::widgets::ListState;
 
 #[derive(Debug,PartialEq,Clone,serde::Serialize,ToSchema)]
 pub enum ProcState {
     Faulty,
+    /// Has failed too many times within a short period and is being restarted with a backoff delay
+    CrashLooping,
     Stopped,    
     Starting,
+    /// Has passed its readiness check, moves on to Running right after
+    Ready,
     Stopping,
     Running,
     Remote
@@ -19,6 +25,10 @@ pub struct AppState {
     pub exit: AtomicBool,
     pub site_status_map: Arc<dashmap::DashMap<String,ProcState>>,
     pub statistics : Arc<ProxyStats>,
+    /// Keyed by site and backend, see health_checks::health_key
+    pub backend_health: Arc<dashmap::DashMap<String,BackendHealth>>,
+    /// Keyed by site and backend like the health state, see health_checks::health_key
+    pub circuit_breakers: Arc<dashmap::DashMap<String,CircuitBreaker>>,
 }
 
 impl AppState {
@@ -31,9 +41,13 @@ impl AppState {
             statistics : Arc::new(ProxyStats { 
                 terminated_http_connections_per_hostname: dashmap::DashMap::new(),
                 active_connections: dashmap::DashMap::new(),
-                tunnelled_tcp_connections_per_hostname: dashmap::DashMap::new()
+                tunnelled_tcp_connections_per_hostname: dashmap::DashMap::new(),
+                denied_connections: std::sync::atomic::AtomicUsize::new(0),
+                retried_requests: std::sync::atomic::AtomicUsize::new(0)
                 
             }),
+            backend_health: Arc::new(dashmap::DashMap::new()),
+            circuit_breakers: Arc::new(dashmap::DashMap::new()),
             exit: AtomicBool::new(false),
             //view_mode: ViewMode::Console,
         };
@@ -0,0 +1,29 @@
+
+/// Current health of a backend as observed by the active health checks.
+/// Backends without an entry have not been checked and are considered healthy.
+#[derive(Debug,Clone)]
+pub struct BackendHealth {
+    pub healthy : bool,
+    pub consecutive_failures : u32,
+    pub consecutive_successes : u32,
+    pub last_check : Option<chrono::DateTime<chrono::Local>>,
+    pub last_error : Option<String>
+}
+
+impl BackendHealth {
+    pub fn new() -> Self {
+        Self {
+            healthy: true,
+            consecutive_failures: 0,
+            consecutive_successes: 0,
+            last_check: None,
+            last_error: None
+        }
+    }
+}
+
+impl Default for BackendHealth {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,233 @@
+use std::time::Instant;
+use serde::Serialize;
+use utoipa::ToSchema;
+use crate::configuration::v2::OutlierDetection;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Serialize,ToSchema)]
+pub enum CircuitState {
+    /// The backend gets traffic as usual
+    Closed,
+    /// The backend has been ejected and gets no traffic until the cool-down is over
+    Open,
+    /// The cool-down is over and the backend gets a limited number of trial requests.
+    /// The first failure ejects it again and the first success closes the circuit.
+    HalfOpen
+}
+
+/// Failures of a backend as observed by the outlier detection, based on real requests and tcp tunnels.
+/// Backends without an entry have not failed and are not ejected.
+#[derive(Debug,Clone)]
+pub struct CircuitBreaker {
+    pub consecutive_failures : u32,
+    /// When the current run of failures started
+    pub first_failure_at : Option<Instant>,
+    /// When the backend was last ejected, cleared once it has recovered
+    pub ejected_at : Option<Instant>,
+    pub ejections : u32,
+    pub last_error : Option<String>,
+    /// Trial requests in flight while the backend is half-open
+    pub trial_requests : u32,
+    /// When the last trial request started. Trials that never report back, such as websocket connections,
+    /// no longer count once the window has passed.
+    pub last_trial_at : Option<Instant>
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new() -> Self {
+        Self {
+            consecutive_failures: 0,
+            first_failure_at: None,
+            ejected_at: None,
+            ejections: 0,
+            last_error: None,
+            trial_requests: 0,
+            last_trial_at: None
+        }
+    }
+
+    pub fn state(&self, config:&OutlierDetection, now:Instant) -> CircuitState {
+        match self.ejected_at {
+            Some(ejected_at) if now < ejected_at + config.cooldown() => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+            None => CircuitState::Closed
+        }
+    }
+
+    fn trials_in_flight(&self, config:&OutlierDetection, now:Instant) -> u32 {
+        match self.last_trial_at {
+            Some(last_trial_at) if now.duration_since(last_trial_at) <= config.window() => self.trial_requests,
+            _ => 0
+        }
+    }
+
+    /// True if the backend is closed, or half-open with trial requests left.
+    pub fn accepts_request(&self, config:&OutlierDetection, now:Instant) -> bool {
+        match self.state(config, now) {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen => self.trials_in_flight(config, now) < config.half_open_requests()
+        }
+    }
+
+    /// Counts a request to a half-open backend as a trial request. Returns false if it has no trial requests left.
+    /// Open backends are only selected when no other backend can take the request, so they are not limited here.
+    pub fn start_request(&mut self, config:&OutlierDetection, now:Instant) -> bool {
+        if self.state(config, now) != CircuitState::HalfOpen {
+            return true
+        }
+        let trials = self.trials_in_flight(config, now);
+        if trials >= config.half_open_requests() {
+            return false
+        }
+        self.trial_requests = trials + 1;
+        self.last_trial_at = Some(now);
+        true
+    }
+
+    /// Returns true if the backend was half-open and is now closed again.
+    pub fn record_success(&mut self, config:&OutlierDetection, now:Instant) -> bool {
+        match self.state(config, now) {
+            // requests that started before the backend was ejected should not bring it back
+            CircuitState::Open => false,
+            CircuitState::HalfOpen => {
+                *self = Self { ejections: self.ejections, ..Self::new() };
+                true
+            },
+            CircuitState::Closed => {
+                self.consecutive_failures = 0;
+                self.first_failure_at = None;
+                false
+            }
+        }
+    }
+
+    /// Returns true if the failure ejected the backend.
+    pub fn record_failure(&mut self, config:&OutlierDetection, now:Instant, error:String) -> bool {
+        self.last_error = Some(error);
+        match self.state(config, now) {
+            CircuitState::Open => false,
+            CircuitState::HalfOpen => {
+                self.ejected_at = Some(now);
+                self.ejections = self.ejections.saturating_add(1);
+                self.trial_requests = 0;
+                self.last_trial_at = None;
+                true
+            },
+            CircuitState::Closed => {
+                if self.first_failure_at.map_or(true, |x| now.duration_since(x) > config.window()) {
+                    self.first_failure_at = Some(now);
+                    self.consecutive_failures = 0;
+                }
+                self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+                if self.consecutive_failures < config.consecutive_failures() {
+                    return false
+                }
+                self.consecutive_failures = 0;
+                self.first_failure_at = None;
+                self.ejected_at = Some(now);
+                self.ejections = self.ejections.saturating_add(1);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn outlier_detection_ejects_and_half_opens_backends() {
+
+        let config = OutlierDetection {
+            consecutive_failures: Some(3),
+            window_seconds: Some(10),
+            cooldown_seconds: Some(30),
+            count_server_errors: None,
+            half_open_requests: None
+        };
+        let start = Instant::now();
+        let at = |seconds:u64| start + Duration::from_secs(seconds);
+        let mut breaker = CircuitBreaker::new();
+
+        // failures that are too far apart do not eject the backend
+        assert!(!breaker.record_failure(&config, at(0), "refused".into()));
+        assert!(!breaker.record_failure(&config, at(1), "refused".into()));
+        assert!(!breaker.record_failure(&config, at(20), "refused".into()));
+        assert_eq!(breaker.state(&config, at(20)), CircuitState::Closed);
+
+        // a success resets the count
+        assert!(!breaker.record_success(&config, at(21)));
+        assert!(!breaker.record_failure(&config, at(22), "refused".into()));
+        assert!(!breaker.record_failure(&config, at(23), "refused".into()));
+        assert!(breaker.record_failure(&config, at(24), "refused".into()));
+        assert_eq!(breaker.state(&config, at(25)), CircuitState::Open);
+
+        // successes from requests that started before the ejection do not bring it back
+        assert!(!breaker.record_success(&config, at(26)));
+        assert_eq!(breaker.state(&config, at(53)), CircuitState::Open);
+
+        // after the cool-down a single failure ejects it again
+        assert_eq!(breaker.state(&config, at(54)), CircuitState::HalfOpen);
+        assert!(breaker.record_failure(&config, at(55), "503".into()));
+        assert_eq!(breaker.state(&config, at(56)), CircuitState::Open);
+
+        // and a success closes it
+        assert!(breaker.record_success(&config, at(90)));
+        assert_eq!(breaker.state(&config, at(90)), CircuitState::Closed);
+        assert_eq!(breaker.ejections, 2);
+
+    }
+
+    #[test]
+    fn half_open_backends_only_take_a_limited_number_of_trial_requests() {
+
+        let mut config = OutlierDetection {
+            consecutive_failures: Some(1),
+            window_seconds: Some(10),
+            cooldown_seconds: Some(30),
+            count_server_errors: None,
+            half_open_requests: None
+        };
+        let start = Instant::now();
+        let at = |seconds:u64| start + Duration::from_secs(seconds);
+        let mut breaker = CircuitBreaker::new();
+
+        assert!(breaker.start_request(&config, at(0)));
+        assert!(breaker.record_failure(&config, at(0), "refused".into()));
+        assert!(!breaker.accepts_request(&config, at(1)));
+
+        // one trial request at a time by default
+        assert!(breaker.accepts_request(&config, at(30)));
+        assert!(breaker.start_request(&config, at(30)));
+        assert!(!breaker.accepts_request(&config, at(31)));
+        assert!(!breaker.start_request(&config, at(31)));
+
+        // a trial that never reports back stops counting after the window
+        assert!(breaker.start_request(&config, at(41)));
+
+        // a failed trial ejects the backend again
+        assert!(breaker.record_failure(&config, at(42), "503".into()));
+        assert_eq!(breaker.state(&config, at(43)), CircuitState::Open);
+        assert!(!breaker.accepts_request(&config, at(43)));
+
+        config.half_open_requests = Some(2);
+        assert!(breaker.start_request(&config, at(72)));
+        assert!(breaker.start_request(&config, at(72)));
+        assert!(!breaker.start_request(&config, at(72)));
+
+        // and a successful one closes the circuit
+        assert!(breaker.record_success(&config, at(73)));
+        assert!(breaker.accepts_request(&config, at(73)));
+        assert_eq!(breaker.trial_requests, 0);
+
+    }
+}
@@ -4,4 +4,6 @@ pub mod app_state;
 pub mod proxy_state;
 pub mod tui_state;
 pub mod proc_info;
+pub mod backend_health;
+pub mod circuit_breaker;
 pub mod args;
\ No newline at end of file
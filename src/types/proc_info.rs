@@ -17,7 +17,13 @@ impl ProcId {
 pub struct ProcInfo {
     pub liveness_ptr : Weak<AtomicBool>,
     pub config : FullyResolvedInProcessSiteConfig,
-    pub pid : Option<String>
+    pub pid : Option<String>,
+    /// Number of times the process has been restarted automatically after failing
+    pub restart_count : u32,
+    /// Why the process was last restarted automatically, if it ever was
+    pub last_failure : Option<String>,
+    /// Exit code from the last time the process stopped on its own
+    pub last_exit_code : Option<i32>
 }
 
 #[derive(Debug)]
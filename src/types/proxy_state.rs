@@ -5,7 +5,11 @@ use std::sync::atomic::AtomicUsize;
 pub struct ProxyStats {
     pub active_connections : dashmap::DashMap<ConnectionKey,ProxyActiveConnection>,
     pub tunnelled_tcp_connections_per_hostname : dashmap::DashMap<String,AtomicUsize>,
-    pub terminated_http_connections_per_hostname : dashmap::DashMap<String,AtomicUsize>
+    pub terminated_http_connections_per_hostname : dashmap::DashMap<String,AtomicUsize>,
+    /// Connections and requests rejected by the allow and deny lists
+    pub denied_connections : AtomicUsize,
+    /// Requests that were sent to another backend after failing on the first one
+    pub retried_requests : AtomicUsize
 }
 
 
@@ -37,5 +41,7 @@ pub struct ProxyActiveConnection {
     pub description : Option<String>,
     pub connection_type : ProxyActiveConnectionType,
     pub source_addr: SocketAddr,
-    pub target_addr: String
+    pub target_addr: String,
+    /// Set when the connection goes to a configured backend, see Backend::key
+    pub backend_key: Option<String>
 }
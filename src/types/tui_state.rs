@@ -76,7 +76,7 @@ impl TuiState {
             let mut info = if let Some(v) = site_states_map.get_mut(selected_site) {v} else {return};
             let (_,state) = info.pair_mut();
             match state {
-                ProcState::Faulty => {
+                ProcState::Faulty | ProcState::CrashLooping => {
                     *state = ProcState::Stopped;
                     Some(false)
                 },
@@ -84,7 +84,7 @@ impl TuiState {
                     *state = ProcState::Starting;
                     Some(true)
                 }
-                ProcState::Running =>  {
+                ProcState::Running | ProcState::Ready =>  {
                     *state = ProcState::Stopping;
                     Some(false)
                 }